@@ -18,6 +18,7 @@
 pub mod qoa;
 
 use std::error::Error;
+use std::fmt;
 use std::path::PathBuf;
 use std::ptr::slice_from_raw_parts_mut;
 use std::ffi::CString;
@@ -36,6 +37,19 @@ impl Default for QoaDesc {
 	}
 }
 
+impl QoaDesc {
+	/// Returns the total squared error between the input and reconstructed
+	/// samples, recorded by the reference encoder over all channels.
+	pub fn error(&self) -> f64 { self.error }
+
+	/// Returns the root mean squared error recorded by the reference encoder, over
+	/// all channels, or `0` if the descriptor has no samples.
+	pub fn rmse(&self) -> f64 {
+		let len = self.samples as f64 * self.channels as f64;
+		if len > 0.0 { (self.error / len).sqrt() } else { 0.0 }
+	}
+}
+
 impl Default for qoa_lms_t {
 	fn default() -> Self {
 		Self {
@@ -80,7 +94,42 @@ pub fn scale_slice(
 	slice
 }
 
-pub fn encode(source: &[i16], descriptor: &mut QoaDesc) -> Result<Box<[u8]>, &'static str> {
+/// An error returned by the reference encoder, holding the descriptor it was
+/// given.
+#[derive(Copy, Clone, Debug)]
+pub struct QoaError {
+	pub channels: u32,
+	pub samplerate: u32,
+	pub samples: u32,
+	/// The `error` field read back from the descriptor.
+	pub error: f64,
+}
+
+impl From<&QoaDesc> for QoaError {
+	fn from(value: &QoaDesc) -> Self {
+		Self {
+			channels: value.channels,
+			samplerate: value.samplerate,
+			samples: value.samples,
+			error: value.error,
+		}
+	}
+}
+
+impl fmt::Display for QoaError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let Self { channels, samplerate, samples, error } = self;
+		write!(
+			f,
+			"reference encoder failed with {channels} channels, {samples} samples at \
+			{samplerate}Hz (error {error})"
+		)
+	}
+}
+
+impl Error for QoaError { }
+
+pub fn encode(source: &[i16], descriptor: &mut QoaDesc) -> Result<Box<[u8]>, QoaError> {
 	let ref mut len = 0;
 
 	Ok(unsafe {
@@ -90,13 +139,18 @@ pub fn encode(source: &[i16], descriptor: &mut QoaDesc) -> Result<Box<[u8]>, &'s
 		);
 
 		if data.is_null() {
-			return Err("encode error")
+			return Err(QoaError::from(&*descriptor))
 		}
 
 		Box::from_raw(data)
 	})
 }
 
+#[deprecated(note = "use `encode`, which returns a `QoaError`")]
+pub fn encode_static(source: &[i16], descriptor: &mut QoaDesc) -> Result<Box<[u8]>, &'static str> {
+	encode(source, descriptor).map_err(|_| "encode error")
+}
+
 pub fn read_wav(path: PathBuf, descriptor: &mut QoaDesc) -> Result<Box<[i16]>, Box<dyn Error>> {
 	let path = CString::new(path.to_str().ok_or("invalid path")?)?;
 
@@ -114,7 +168,7 @@ pub fn decode(source: &[u8], descriptor: &mut QoaDesc) -> Result<Box<[i16]>, &'s
 	Ok(unsafe {
 		let data = slice_from_raw_parts_mut(
 			qoa_decode(source.as_ptr(), source.len() as i32, descriptor).cast(),
-			descriptor.samples as usize
+			(descriptor.samples * descriptor.channels) as usize
 		);
 
 		if data.is_null() {
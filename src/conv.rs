@@ -15,20 +15,299 @@
 //! Conversion streams to and from other formats, converting raw PCM16-LE samples via the
 //! Symphonia crate.
 
-use std::io;
-use std::cmp::{max, min};
+use std::{error, io};
+use std::cmp::min;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use amplify_derive::Display;
 use errors::{Error as SymError, Error::ResetRequired};
 use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal};
-use symphonia::core::codecs::{CodecType, Decoder as SymDecoder, decl_codec_type};
-use symphonia::core::errors;
-use symphonia::core::formats::{FormatReader, Track};
-use crate::{PcmSink, PcmSource, PcmStream};
+use symphonia::core::codecs::{CodecParameters, CodecType, Decoder as SymDecoder, DecoderOptions, decl_codec_type};
+use symphonia::core::errors::{self, SeekErrorKind};
+use symphonia::core::formats::{Cue, FormatOptions, FormatReader, Packet, SeekedTo, SeekMode, SeekTo, Track};
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions, ReadBytes, SeekBuffered};
+use symphonia::core::meta::{Metadata, MetadataLog, MetadataOptions, MetadataRevision};
+use symphonia::core::probe::{Descriptor, Hint, Instantiate, Probe, ProbeResult, QueryDescriptor};
+use symphonia::core::support_format;
+use symphonia::default::{get_codecs, get_probe};
+use crate::{CHECKSUM_MAGIC, EncodeError, Encoder, LOOP_MAGIC, META_MAGIC, PcmSink, PcmSource, PcmStream, QoaHeader};
+use crate::io::Buffer;
 use crate::pcm_io::Error;
+use crate::util::Then;
 
 /// Quite OK Audio
 pub const CODEC_TYPE_QOA: CodecType = decl_codec_type(b"qoaf");
 
+/// Returns the index of the Symphonia buffer plane holding each QOA channel. The
+/// planes are ordered by speaker position, which usually matches QOA's channel
+/// order, but mixed rear and side layouts don't. Layouts QOA doesn't define are
+/// kept in plane order.
+pub(crate) fn channel_order(channels: Channels) -> Vec<usize> {
+	const FL : Channels = Channels::FRONT_LEFT;
+	const FR : Channels = Channels::FRONT_RIGHT;
+	const FC : Channels = Channels::FRONT_CENTRE;
+	const LFE: Channels = Channels::LFE1;
+	const RC : Channels = Channels::REAR_CENTRE;
+	const RL : Channels = Channels::REAR_LEFT;
+	const RR : Channels = Channels::REAR_RIGHT;
+	const SL : Channels = Channels::SIDE_LEFT;
+	const SR : Channels = Channels::SIDE_RIGHT;
+	// Back channels may be either rear or side speakers, unless both are present.
+	const BL : Channels = RL.union(SL);
+	const BR : Channels = RR.union(SR);
+
+	let layout: &[Channels] = match channels.count() {
+		3 => &[FL, FR, FC],
+		4 => &[FL, FR, BL, BR],
+		5 => &[FL, FR, FC, BL, BR],
+		6 => &[FL, FR, FC, LFE, BL, BR],
+		7 => &[FL, FR, FC, LFE, RC, SL, SR],
+		8 => &[FL, FR, FC, LFE, RL, RR, SL, SR],
+		_ => &[]
+	};
+
+	let order: Option<Vec<usize>> = layout.iter().map(|&positions| {
+		let position = channels.intersection(positions);
+		// The plane index is the number of positions before this one.
+		(position.bits().count_ones() == 1).then(||
+			(channels.bits() & (position.bits() - 1)).count_ones() as usize
+		)
+	}).collect();
+
+	order.filter(|order| !order.is_empty())
+		 .unwrap_or_else(|| (0..channels.count()).collect())
+}
+
+/// A Symphonia [`FormatReader`] for QOA streams, demuxing each frame as a packet
+/// of [`CODEC_TYPE_QOA`], header included. The track's channels are the first
+/// positions in Symphonia's order. A trailer ends the stream; seeking isn't
+/// supported.
+pub struct QoaReader {
+	source: MediaSourceStream,
+	tracks: Vec<Track>,
+	metadata: MetadataLog,
+	/// The timestamp of the next packet, in samples per channel.
+	ts: u64,
+}
+
+impl QueryDescriptor for QoaReader {
+	fn query() -> &'static [Descriptor] {
+		&[
+			support_format!(
+				"qoa",
+				"Quite OK Audio",
+				&["qoa"],
+				&["audio/qoa"],
+				&[b"qoaf"]
+			)
+		]
+	}
+
+	fn score(_context: &[u8]) -> u8 { 255 }
+}
+
+impl FormatReader for QoaReader {
+	fn try_new(mut source: MediaSourceStream, _: &FormatOptions) -> Result<Self, SymError> {
+		let header = source.read_boxed_slice_exact(16)?;
+		let QoaHeader { sample_count, sample_rate, channel_count, .. } =
+			QoaHeader::try_from(&*header).map_err(|_| SymError::DecodeError("qoa: invalid header"))?;
+		// Rewind to the first frame header.
+		source.seek_buffered_rev(8);
+
+		let channels = (1u64 << channel_count).wrapping_sub(1) as u32;
+		let mut params = CodecParameters::new();
+		params.for_codec(CODEC_TYPE_QOA)
+			  .with_sample_rate(sample_rate)
+			  .with_channels(Channels::from_bits_truncate(channels));
+		if sample_count > 0 {
+			params.with_n_frames(sample_count as u64);
+		}
+
+		Ok(Self {
+			source,
+			tracks: vec![Track::new(0, params)],
+			metadata: MetadataLog::default(),
+			ts: 0,
+		})
+	}
+
+	fn cues(&self) -> &[Cue] { &[] }
+
+	fn metadata(&mut self) -> Metadata<'_> { self.metadata.metadata() }
+
+	fn seek(&mut self, _: SeekMode, _: SeekTo) -> Result<SeekedTo, SymError> {
+		Err(SymError::SeekError(SeekErrorKind::Unseekable))
+	}
+
+	fn tracks(&self) -> &[Track] { &self.tracks }
+
+	fn next_packet(&mut self) -> Result<Packet, SymError> {
+		let header = self.source.read_be_u64()?;
+
+		if let LOOP_MAGIC | CHECKSUM_MAGIC | META_MAGIC = (header >> 32) as u32 {
+			return Err(SymError::IoError(io::ErrorKind::UnexpectedEof.into()))
+		}
+
+		let samples = (header >> 16) as u16 as u64;
+		let size    = header as u16 as usize;
+		if size < 8 {
+			return Err(SymError::DecodeError("qoa: invalid frame header"))
+		}
+
+		let mut frame = vec![0; size];
+		frame[..8].copy_from_slice(&header.to_be_bytes());
+		self.source.read_buf_exact(&mut frame[8..])?;
+
+		let packet = Packet::new_from_boxed_slice(0, self.ts, samples, frame.into_boxed_slice());
+		self.ts += samples;
+		Ok(packet)
+	}
+
+	fn into_inner(self: Box<Self>) -> MediaSourceStream { self.source }
+}
+
+/// Probes in-memory QOA `bytes` with a Symphonia probe registering [`QoaReader`],
+/// returning the format reader and its default track. The bytes are read through
+/// a [`Cursor`], which Symphonia accepts as a `MediaSource`; the probe is hinted
+/// with the `qoa` extension.
+pub fn probe_qoa(bytes: Vec<u8>) -> Result<(Box<dyn FormatReader>, Track), SymError> {
+	let source = MediaSourceStream::new(
+		Box::new(Cursor::new(bytes)),
+		MediaSourceStreamOptions::default()
+	);
+	let mut probe = Probe::default();
+	probe.register_all::<QoaReader>();
+	let ProbeResult { format, .. } = probe.format(
+		Hint::new().with_extension("qoa"),
+		source,
+		&FormatOptions::default(),
+		&MetadataOptions::default()
+	)?;
+	let track = format.default_track()
+					  .ok_or(SymError::Unsupported("no default track"))?
+					  .clone();
+	Ok((format, track))
+}
+
+/// Returns the tags of a Symphonia metadata revision as key-value pairs, for
+/// [`Encoder::set_metadata`](crate::Encoder::set_metadata).
+pub fn revision_tags(revision: &MetadataRevision) -> Vec<(String, String)> {
+	revision.tags()
+			.iter()
+			.map(|tag| (tag.key.clone(), tag.value.to_string()))
+			.collect()
+}
+
+/// An error opening or encoding an audio file.
+#[derive(Debug, Display)]
+pub enum FileError {
+	#[display("could not open file ({0})")]
+	Io(io::Error),
+	#[display("could not read file ({0})")]
+	Format(SymError),
+	#[display("no tracks found")]
+	NoTracks,
+	#[display("could not encode file ({0})")]
+	Encode(EncodeError),
+}
+
+impl error::Error for FileError {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Self::Io(err) => Some(err),
+			Self::Format(err) => Some(err),
+			Self::Encode(err) => Some(err),
+			Self::NoTracks => None
+		}
+	}
+}
+
+impl From<io::Error> for FileError {
+	fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+
+impl From<SymError> for FileError {
+	fn from(value: SymError) -> Self { Self::Format(value) }
+}
+
+impl From<EncodeError> for FileError {
+	fn from(value: EncodeError) -> Self { Self::Encode(value) }
+}
+
+/// Opens the audio file at `path` with the default Symphonia probe and codecs,
+/// returning a source of its default track and its tags. Tags from the container
+/// are preferred, falling back to those found while probing, such as ID3 tags
+/// preceding it.
+///
+/// # Errors
+///
+/// [`FileError::Io`]: the file can't be opened.
+///
+/// [`FileError::Format`]: the format or codec is unsupported.
+///
+/// [`FileError::NoTracks`]: the file has no default track.
+pub fn open_file(path: impl AsRef<Path>) -> Result<(FormatSource, Vec<(String, String)>), FileError> {
+	let path = path.as_ref();
+	let mut hint = Hint::new();
+	if let Some(ext) = path.extension().and_then(OsStr::to_str) {
+		hint.with_extension(ext);
+	}
+
+	let source = MediaSourceStream::new(
+		Box::new(File::open(path)?),
+		MediaSourceStreamOptions::default()
+	);
+	let ProbeResult { format: mut demuxer, metadata: mut probed, .. } = get_probe().format(
+		&hint,
+		source,
+		&FormatOptions::default(),
+		&MetadataOptions::default()
+	)?;
+	let track = demuxer.default_track().ok_or(FileError::NoTracks)?.clone();
+
+	let mut tags = demuxer.metadata()
+						  .current()
+						  .map(revision_tags)
+						  .unwrap_or_default();
+	if tags.is_empty() {
+		tags = probed.get()
+					 .and_then(|meta| meta.current().map(revision_tags))
+					 .unwrap_or_default();
+	}
+
+	let decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+	Ok((FormatSource::new(track, demuxer, decoder), tags))
+}
+
+/// Encodes the audio file at `path` into QOA bytes, keeping its tags as metadata.
+/// Files of unknown length are encoded in streaming mode.
+///
+/// # Errors
+///
+/// Any error returned by [`open_file`], or [`FileError::Encode`] if encoding
+/// fails.
+pub fn encode_file(path: impl AsRef<Path>) -> Result<Vec<u8>, FileError> {
+	let (mut source, tags) = open_file(path)?;
+	let mut enc = match source.sample_count() {
+		0 => Encoder::new_streaming(Buffer::default()),
+		samples => Encoder::new_fixed(
+			samples,
+			source.sample_rate(),
+			source.channel_count(),
+			Buffer::default()
+		)?
+	};
+	enc.set_metadata(tags);
+	enc.encode_format(&mut source)?;
+	Ok(enc.close().ok_or(EncodeError::Closed)??.encode())
+}
+
 /// A [`Pcm16Source`] implementation reading samples from a Symphonia format stream.
+///
+/// Symphonia requires its format readers and decoders to be [`Send`] and
+/// [`Sync`], so the source is as well, and can be read on another thread.
 pub struct FormatSource {
 	track: Track,
 	demuxer: Box<dyn FormatReader>,
@@ -51,7 +330,9 @@ impl FormatSource {
 		}
 	}
 
-	fn read(&mut self) -> Result<Option<AudioBuffer<i16>>, SymError> {
+	/// Returns the next decoded buffer, or `None` at the end of the stream. A
+	/// partially read buffer is returned before decoding the next packet.
+	pub(crate) fn next_buffer(&mut self) -> Result<Option<AudioBuffer<i16>>, SymError> {
 		let Self { track, demuxer, decoder, buffer, .. } = self;
 
 		if let Some(buf) = buffer.take() {
@@ -72,6 +353,9 @@ impl FormatSource {
 			}
 		} {
 			if packet.track_id() == track.id {
+				// Other sample formats are scaled to 16 bits by Symphonia, shifting
+				// wider samples right rather than truncating them; 8-bit samples are
+				// unsigned, and are centered on zero before shifting left.
 				fn convert_or_deref(buf: AudioBufferRef) -> AudioBuffer<i16> {
 					if let AudioBufferRef::S16(inner) = buf {
 						inner.into_owned()
@@ -118,33 +402,482 @@ impl PcmStream for FormatSource {
 }
 
 impl PcmSource for FormatSource {
+	/// Reads decoded samples into `sink`, writing no more than its capacity. The
+	/// samples a full sink can't take are kept for the next read.
 	fn read(&mut self, sink: &mut impl PcmSink, mut sample_count: usize) -> Result<usize, Error> {
 		let mut samples = 0;
-		while let Some(mut buf) = self.read().map_err(|err| Error::Read(err.into()))? {
-			if sample_count == 0 { break }
-
-			let channels = buf.spec().channels.count();
-			sink.set_descriptor(buf.spec().rate, channels)?;
-
-			let read = (0..min(channels, 255))
-				.map(|chn| {
-					let data = buf.chan(chn);
-					let len = min(sample_count, data.len());
-					sink.write(&data[..len], chn)
-				})
-				.reduce(|max_read, read| Ok(max(max_read?, read?))) // Ew
-				.unwrap()?;
+		while sample_count > 0 {
+			let Some(mut buf) = self.next_buffer().map_err(|err|
+				match err {
+					SymError::IoError(err) => Error::Io(err),
+					err => Error::Read(err.into())
+				}
+			)? else { break };
+
+			let order = channel_order(buf.spec().channels);
+			sink.set_descriptor(buf.spec().rate, order.len())?;
+
+			// Offer every channel the same number of samples, so a full sink can't
+			// leave the channels unevenly written.
+			let len = min(min(sample_count, buf.frames()), sink.sample_capacity());
+			let read = order.iter().enumerate().try_fold(len, |min_read, (chn, &plane)|
+				Ok::<_, Error>(min(min_read, sink.write(&buf.chan(plane)[..len], chn)?))
+			)?;
 			samples      += read;
 			sample_count -= read;
 
-			buf.truncate(buf.frames() - read);
+			// Keep the unread samples for the next read.
+			buf.shift(read);
 
 			if buf.frames() > 0 {
 				let _ = self.buffer.insert(buf);
 			}
+
+			if read < len || len == 0 { break }
 		}
 		Ok(samples)
 	}
 
-	fn sample_count(&self) -> usize { self.samples }
+	/// Returns the number of samples per channel left in the stream, including
+	/// those decoded but not yet read.
+	fn sample_count(&self) -> usize {
+		self.samples + self.buffer.as_ref().map_or(0, AudioBuffer::frames)
+	}
+}
+
+/// A [`PcmSink`] implementation writing samples into a planar Symphonia
+/// [`AudioBuffer`]. The buffer's signal spec is fixed; setting a different
+/// descriptor fails with [`Error::DescriptorSet`].
+pub struct AudioBufferSink {
+	buffer: AudioBuffer<i16>,
+	/// The number of samples written to each channel.
+	fill: Vec<usize>,
+}
+
+impl AudioBufferSink {
+	/// Creates a sink writing after any frames already in `buffer`.
+	pub fn new(buffer: AudioBuffer<i16>) -> Self {
+		let fill = vec![buffer.frames(); buffer.spec().channels.count()];
+		Self { buffer, fill }
+	}
+
+	/// Returns the underlying buffer.
+	pub fn buffer(&self) -> &AudioBuffer<i16> { &self.buffer }
+
+	/// Unwraps the underlying buffer.
+	pub fn unwrap(self) -> AudioBuffer<i16> { self.buffer }
+
+	/// Renders frames up to `len`, padding unwritten channels with silence.
+	fn reserve(&mut self, len: usize) {
+		let frames = self.buffer.frames();
+		if len > frames {
+			self.buffer.render_reserved(Some(len - frames));
+		}
+	}
+}
+
+impl PcmStream for AudioBufferSink {
+	fn channel_count(&self) -> usize { self.fill.len() }
+
+	fn sample_rate(&self) -> u32 { self.buffer.spec().rate }
+}
+
+impl PcmSink for AudioBufferSink {
+	fn write(&mut self, buf: &[i16], chn: usize) -> Result<usize, Error> {
+		assert!(chn < self.fill.len(), "channel index out of bounds");
+
+		let fill = self.fill[chn];
+		let samples = min(buf.len(), self.buffer.capacity().saturating_sub(fill));
+		let end = fill + samples;
+		self.reserve(end);
+		self.buffer.chan_mut(chn)[fill..end].copy_from_slice(&buf[..samples]);
+		self.fill[chn] = end;
+		Ok(samples)
+	}
+
+	fn write_interleaved(&mut self, buf: &[i16]) -> Result<usize, Error> {
+		let chan = self.fill.len();
+		let len = self.fill.iter().copied().max().unwrap_or_default();
+		let samples = min(buf.len() / chan, self.buffer.capacity().saturating_sub(len));
+		let end = len + samples;
+		self.reserve(end);
+
+		for chn in 0..chan {
+			let plane = &mut self.buffer.chan_mut(chn)[len..end];
+			for (dst, &src) in plane.iter_mut().zip(buf[chn..].iter().step_by(chan)) {
+				*dst = src;
+			}
+		}
+
+		self.fill.fill(end);
+		Ok(samples * chan)
+	}
+
+	fn sample_capacity(&self) -> usize {
+		let len = self.fill.iter().copied().min().unwrap_or_default();
+		self.buffer.capacity().saturating_sub(len)
+	}
+
+	fn set_descriptor(&mut self, sample_rate: u32, channel_count: usize) -> Result<(), Error> {
+		(sample_rate != self.sample_rate() || channel_count != self.channel_count())
+			.then_err(Error::DescriptorSet)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::env::temp_dir;
+	use std::fs::remove_file;
+	use std::io::Cursor;
+	use symphonia::core::audio::{AudioBuffer, Channels, Signal, SignalSpec};
+	use symphonia::core::codecs::DecoderOptions;
+	use symphonia::core::formats::FormatOptions;
+	use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+	use symphonia::core::meta::MetadataOptions;
+	use symphonia::core::probe::Hint;
+	use symphonia::default::{get_codecs, get_probe};
+	use std::f64::consts::TAU;
+	use crate::{byte_decoder, Encoder, FRAME_LEN, metrics, PcmFrame, PcmSource, StreamDescriptor};
+	use crate::decoder::Decoder;
+	use crate::io::Buffer;
+	use crate::reference::encode_ref;
+	use crate::wav::{write_pcm16, write_pcm16_to};
+	use super::{AudioBufferSink, channel_order, CODEC_TYPE_QOA, encode_file, FileError, FormatSource, probe_qoa};
+
+	fn assert_send_sync<T: Send + Sync>() { }
+
+	#[test]
+	fn thread_safety() {
+		assert_send_sync::<FormatSource>();
+		assert_send_sync::<AudioBufferSink>();
+	}
+
+	#[test]
+	fn decode_to_audio_buffer() {
+		const LEN: usize = 2000;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 37 % 8192) as i16 - 4096).collect();
+		let data = encode_ref(&samples, 2);
+
+		let expected = Decoder::new(PcmFrame::new(LEN, 44100, 2))
+			.decode(&mut Buffer::decode(&mut data.clone()))
+			.unwrap()
+			.unwrap();
+
+		let spec = SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+		let sink = AudioBufferSink::new(AudioBuffer::new(FRAME_LEN as u64, spec));
+		let mut source = Buffer::decode(&mut data.clone());
+		let buffer = Decoder::new(sink).decode(&mut source).unwrap().unwrap();
+
+		assert_eq!(buffer.frames(), LEN);
+		let actual: Vec<i16> = (0..LEN).flat_map(|i| [buffer.chan(0)[i], buffer.chan(1)[i]]).collect();
+		assert_eq!(actual, expected);
+	}
+
+	/// Opens channel-interleaved `samples` as a WAV [`FormatSource`].
+	fn wav_source(samples: &[i16], channels: u16) -> FormatSource {
+		let mut wav = Vec::new();
+		write_pcm16_to(&mut wav, samples, 44100, channels).unwrap();
+		format_source(wav)
+	}
+
+	/// Opens channel-interleaved `samples` as an extensible WAV [`FormatSource`]
+	/// with the speaker positions in `channels`, which plain WAV files can't give
+	/// for more than two channels.
+	fn ext_wav_source(samples: &[i16], channels: Channels) -> FormatSource {
+		const SUBTYPE_PCM: [u8; 16] = [
+			0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+			0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+		];
+
+		let count = channels.count() as u16;
+		let data_len = samples.len() as u32 * 2;
+		let mut wav = Vec::new();
+		wav.extend_from_slice(b"RIFF");
+		wav.extend_from_slice(&(data_len + 60).to_le_bytes());
+		wav.extend_from_slice(b"WAVEfmt ");
+		wav.extend_from_slice(&40u32.to_le_bytes());
+		wav.extend_from_slice(&0xFFFEu16.to_le_bytes());
+		wav.extend_from_slice(&count.to_le_bytes());
+		wav.extend_from_slice(&44100u32.to_le_bytes());
+		wav.extend_from_slice(&(44100 * count as u32 * 2).to_le_bytes());
+		wav.extend_from_slice(&(count * 2).to_le_bytes());
+		wav.extend_from_slice(&16u16.to_le_bytes());
+		wav.extend_from_slice(&22u16.to_le_bytes());
+		wav.extend_from_slice(&16u16.to_le_bytes());
+		wav.extend_from_slice(&channels.bits().to_le_bytes());
+		wav.extend_from_slice(&SUBTYPE_PCM);
+		wav.extend_from_slice(b"data");
+		wav.extend_from_slice(&data_len.to_le_bytes());
+		wav.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+		format_source(wav)
+	}
+
+	fn format_source(wav: Vec<u8>) -> FormatSource {
+		let source = MediaSourceStream::new(
+			Box::new(Cursor::new(wav)),
+			MediaSourceStreamOptions::default()
+		);
+		let format = get_probe().format(
+			Hint::new().with_extension("wav"),
+			source,
+			&FormatOptions::default(),
+			&MetadataOptions::default()
+		).unwrap().format;
+		let track = format.default_track().unwrap().clone();
+		let decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default()).unwrap();
+		FormatSource::new(track, format, decoder)
+	}
+
+	#[test]
+	fn read_into_small_sink() {
+		const LEN: usize = 4000;
+		const CAPACITY: usize = 100;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 37 % 8192) as i16 - 4096).collect();
+		let mut source = wav_source(&samples, 2);
+
+		// Each packet holds more samples than the sink can take, so every read must
+		// stop at the sink's capacity and keep the rest.
+		let mut actual = Vec::with_capacity(samples.len());
+		loop {
+			let mut sink = PcmFrame::new(CAPACITY, 44100, 2);
+			let read = PcmSource::read(&mut source, &mut sink, usize::MAX).unwrap();
+			assert!(read <= CAPACITY, "read {read} samples into a sink of {CAPACITY}");
+			assert_eq!(sink.len(), read);
+			assert!(sink.validate());
+
+			if read == 0 { break }
+			actual.extend(sink.unwrap());
+		}
+
+		assert_eq!(actual, samples);
+	}
+
+	#[test]
+	fn probe_encoded() {
+		const LEN: usize = FRAME_LEN * 2 + 1000;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 53 % 12000) as i16 - 6000).collect();
+		let mut enc = Encoder::new_fixed(LEN, 22050, 2, Buffer::default()).unwrap();
+		enc.set_loop_points(0, 100).unwrap();
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let data = enc.close().unwrap().unwrap().encode();
+
+		let (mut format, track) = probe_qoa(data.clone()).unwrap();
+		assert_eq!(track.codec_params.codec, CODEC_TYPE_QOA);
+		assert_eq!(track.codec_params.sample_rate, Some(22050));
+		assert_eq!(track.codec_params.channels.map(Channels::count), Some(2));
+		assert_eq!(track.codec_params.n_frames, Some(LEN as u64));
+
+		// The packets are the frames, ending at the loop point trailer.
+		let mut frames = Vec::new();
+		let mut ts = 0;
+		while let Ok(packet) = format.next_packet() {
+			assert_eq!(packet.ts, ts);
+			ts += packet.dur;
+			frames.extend_from_slice(&packet.data);
+		}
+		assert_eq!(ts, LEN as u64);
+		assert_eq!(frames, data[8..data.len() - 16]);
+	}
+
+	#[test]
+	fn encode_format() {
+		const LEN: usize = FRAME_LEN * 2 + 1000;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 53 % 12000) as i16 - 6000).collect();
+		let mut enc = Encoder::new_fixed(LEN, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let expected = enc.close().unwrap().unwrap().encode();
+
+		let mut enc = Encoder::new_fixed(LEN, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_format(&mut wav_source(&samples, 2)).unwrap();
+		assert_eq!(enc.summary().samples_encoded, LEN);
+		let actual = enc.close().unwrap().unwrap().encode();
+
+		assert_eq!(actual, expected);
+	}
+
+	#[test]
+	fn surround_channel_order() {
+		const SIDE_5_1: Channels = Channels::FRONT_LEFT
+			.union(Channels::FRONT_RIGHT)
+			.union(Channels::FRONT_CENTRE)
+			.union(Channels::LFE1)
+			.union(Channels::SIDE_LEFT)
+			.union(Channels::SIDE_RIGHT);
+		const MIXED_4_0: Channels = Channels::FRONT_LEFT
+			.union(Channels::FRONT_RIGHT)
+			.union(Channels::REAR_RIGHT)
+			.union(Channels::SIDE_LEFT);
+
+		assert_eq!(channel_order(SIDE_5_1), [0, 1, 2, 3, 4, 5]);
+		assert_eq!(channel_order(MIXED_4_0), [0, 1, 3, 2]);
+		// Layouts QOA doesn't define keep their plane order.
+		assert_eq!(channel_order(Channels::FRONT_LEFT | Channels::FRONT_RIGHT | Channels::LFE1), [0, 1, 2]);
+	}
+
+	#[test]
+	fn encode_surround() {
+		const LEN: usize = FRAME_LEN + 300;
+
+		for channels in [
+			Channels::FRONT_LEFT | Channels::FRONT_RIGHT | Channels::FRONT_CENTRE |
+			Channels::LFE1 | Channels::SIDE_LEFT | Channels::SIDE_RIGHT,
+			Channels::FRONT_LEFT | Channels::FRONT_RIGHT | Channels::REAR_RIGHT | Channels::SIDE_LEFT,
+		] {
+			let order = channel_order(channels);
+			let count = order.len();
+
+			// Each channel holds a distinct tone, so a reordering changes the output.
+			let wav_order: Vec<i16> = (0..LEN * count).map(|i|
+				((i / count) * (i % count + 3) * 31 % 8000) as i16 - 4000
+			).collect();
+			let qoa_order: Vec<i16> = wav_order.chunks(count)
+											.flat_map(|row| order.iter().map(|&plane| row[plane]))
+											.collect();
+
+			let mut enc = Encoder::new_fixed(LEN, 44100, count, Buffer::default()).unwrap();
+			enc.encode_interleaved(&qoa_order, StreamDescriptor::default()).unwrap();
+			let expected = enc.close().unwrap().unwrap().encode();
+
+			let mut enc = Encoder::new_fixed(LEN, 44100, count, Buffer::default()).unwrap();
+			enc.encode(&mut ext_wav_source(&wav_order, channels)).unwrap();
+			assert!(enc.close().unwrap().unwrap().encode() == expected, "{channels:?} read out of order");
+
+			let mut enc = Encoder::new_fixed(LEN, 44100, count, Buffer::default()).unwrap();
+			enc.encode_format(&mut ext_wav_source(&wav_order, channels)).unwrap();
+			let actual = enc.close().unwrap().unwrap().encode();
+			assert!(actual == expected, "{channels:?} encoded out of order");
+
+			let mut decoded = Vec::new();
+			crate::byte_decoder::Decoder::default().decode(&actual, &mut decoded).unwrap();
+			assert_eq!(decoded.len(), qoa_order.len());
+		}
+	}
+
+	#[test]
+	fn encode_wav_file() {
+		const LEN: usize = FRAME_LEN + 1234;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 23 % 6000) as i16 - 3000).collect();
+		let path = temp_dir().join(format!("qoar-encode-file-{}.wav", std::process::id()));
+		write_pcm16(&path, &samples, 44100, 2).unwrap();
+		let result = encode_file(&path);
+		remove_file(&path).unwrap();
+
+		let data = result.unwrap();
+		assert!(!data.is_empty());
+
+		let mut expected = Encoder::new_fixed(LEN, 44100, 2, Buffer::default()).unwrap();
+		expected.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		assert!(data == expected.close().unwrap().unwrap().encode(), "encoded file differs");
+
+		let mut decoded = Vec::new();
+		let (_, desc) = crate::byte_decoder::Decoder::default()
+			.decode_with_descriptor(&data, &mut decoded)
+			.unwrap();
+		assert_eq!(decoded.len(), samples.len());
+		assert_eq!(desc.rate(), Some(44100));
+		assert_eq!(desc.channels(), Some(2));
+	}
+
+	#[test]
+	fn encode_missing_file() {
+		let path = temp_dir().join(format!("qoar-missing-file-{}.wav", std::process::id()));
+		assert!(matches!(encode_file(path), Err(FileError::Io(_))));
+	}
+
+	/// Builds a plain PCM WAV file of stereo `data` with `bits` per sample.
+	fn pcm_wav(data: &[u8], bits: u16) -> Vec<u8> {
+		let block = 2 * bits / 8;
+		let mut wav = Vec::new();
+		wav.extend_from_slice(b"RIFF");
+		wav.extend_from_slice(&(data.len() as u32 + 36).to_le_bytes());
+		wav.extend_from_slice(b"WAVEfmt ");
+		wav.extend_from_slice(&16u32.to_le_bytes());
+		wav.extend_from_slice(&1u16.to_le_bytes());
+		wav.extend_from_slice(&2u16.to_le_bytes());
+		wav.extend_from_slice(&44100u32.to_le_bytes());
+		wav.extend_from_slice(&(44100 * block as u32).to_le_bytes());
+		wav.extend_from_slice(&block.to_le_bytes());
+		wav.extend_from_slice(&bits.to_le_bytes());
+		wav.extend_from_slice(b"data");
+		wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+		wav.extend_from_slice(data);
+		wav
+	}
+
+	#[test]
+	fn scale_sample_formats() {
+		const LEN: usize = FRAME_LEN + 500;
+
+		// A stereo tone at full scale, with distinct frequencies per channel.
+		let tone: Vec<f64> = (0..LEN * 2).map(|i| {
+			let t = (i / 2) as f64 / 44100.0;
+			let f = if i % 2 == 0 { 440.0 } else { 659.3 };
+			(t * f * TAU).sin() * 0.9
+		}).collect();
+
+		let u8_samples: Vec<u8> = tone.iter().map(|s| (s * 127.0 + 128.0) as u8).collect();
+		let s24_samples: Vec<i32> = tone.iter().map(|s| (s * 8388607.0) as i32).collect();
+		let s32_samples: Vec<i32> = tone.iter().map(|s| (s * 2147483647.0) as i32).collect();
+		let formats = [
+			(
+				"u8",
+				pcm_wav(&u8_samples, 8),
+				u8_samples.iter().map(|&s| (s as i16 - 128) << 8).collect::<Vec<_>>(),
+			),
+			(
+				"s24",
+				pcm_wav(&s24_samples.iter().flat_map(|s| {
+					let [a, b, c, _] = s.to_le_bytes();
+					[a, b, c]
+				}).collect::<Vec<_>>(), 24),
+				s24_samples.iter().map(|&s| (s >> 8) as i16).collect(),
+			),
+			(
+				"s32",
+				pcm_wav(&s32_samples.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>(), 32),
+				s32_samples.iter().map(|&s| (s >> 16) as i16).collect(),
+			),
+		];
+
+		for (name, wav, expected) in formats {
+			let pcm: Vec<i16> = format_source(wav.clone())
+				.read_all()
+				.unwrap()
+				.unwrap()
+				.into_iter()
+				.flat_map(PcmFrame::unwrap)
+				.collect();
+			assert_eq!(pcm.len(), expected.len(), "{name} sample count");
+			assert!(
+				pcm.iter().zip(&expected).all(|(&a, &b)| (a as i32 - b as i32).abs() <= 1),
+				"{name} samples are not scaled to 16 bits"
+			);
+
+			let decode = |data: &[u8]| {
+				let mut samples = Vec::new();
+				byte_decoder::Decoder::default().decode(data, &mut samples).unwrap();
+				samples
+			};
+
+			let mut enc = Encoder::new_fixed(LEN, 44100, 2, Buffer::default()).unwrap();
+			enc.encode_format(&mut format_source(wav)).unwrap();
+			let actual = decode(&enc.close().unwrap().unwrap().encode());
+
+			let mut enc = Encoder::new_fixed(LEN, 44100, 2, Buffer::default()).unwrap();
+			enc.encode_interleaved(&expected, StreamDescriptor::default()).unwrap();
+			let reference = decode(&enc.close().unwrap().unwrap().encode());
+
+			let error = metrics::rmse(&expected, &actual).unwrap();
+			let reference_error = metrics::rmse(&expected, &reference).unwrap();
+			assert!(
+				error <= reference_error + 1.0,
+				"{name} rmse {error:.2} exceeds that of the scaled reference, {reference_error:.2}"
+			);
+		}
+	}
 }
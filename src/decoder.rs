@@ -16,13 +16,15 @@ use std::error::Error;
 use std::result;
 use std::cmp::min;
 use amplify_derive::Display;
-use crate::{DEQUANT_TABLE, MAGIC, PcmSink, QoaLmsState, QoaSlice, SLICE_LEN};
+use crate::{DEQUANT_TABLE, MAGIC, PcmSink, QoaLmsState, QoaSlice, reconstruct, SLICE_LEN};
 
 use DecodeError::*;
 use DecodeWriteKind::*;
 use crate::io::{ReadError, SourceStream};
 
 pub mod bytes;
+#[cfg(all(test, feature = "fuzz"))]
+mod fuzz;
 
 type Result<T = ()> = result::Result<T, DecodeError>;
 
@@ -32,10 +34,19 @@ pub enum DecodeError {
 	UnknownMagic([u8; 4]),
 	#[display("end of stream reached prematurely")]
 	Eof,
+	#[display("invalid frame header with {0} channels at {1}Hz")]
+	InvalidFrameHeader(u8, u32),
+	#[display("stream truncated, expected {expected} samples but got {got}")]
+	Truncated {
+		expected: u32,
+		got: u32,
+	},
 	#[display("could not read data ({0})")]
 	Read(ReadError),
 	#[display("could not {0} sink")]
 	Write(DecodeWriteKind, Box<dyn Error>),
+	#[display("sink is full, cannot write samples to channel {0}")]
+	SinkFull(usize),
 	#[display("could not close sink")]
 	SinkClose(Box<dyn Error>),
 }
@@ -69,8 +80,14 @@ impl From<ReadError> for DecodeError {
 	}
 }
 
+/// A QOA decoder writing samples into a [`PcmSink`]. It's [`Send`] and [`Sync`]
+/// when the sink is, and [`Clone`] when the sink is. To fork the decoder with any
+/// sink, use [`Decoder::fork`].
+#[derive(Clone)]
 pub struct Decoder<S: PcmSink> {
 	samples: Option<u32>,
+	/// The sample count from the file header, or `0` in streaming mode.
+	expected: u32,
 	sink: S,
 	header: bool,
 	lms: Vec<QoaLmsState>,
@@ -82,6 +99,7 @@ impl<Sn: PcmSink> Decoder<Sn> {
 	pub fn new(sink: Sn) -> Self {
 		Self {
 			samples: None,
+			expected: 0,
 			sink,
 			header: true,
 			lms: Vec::new(),
@@ -97,14 +115,35 @@ impl<Sn: PcmSink> Decoder<Sn> {
 	}
 
 	/// Decodes a QOA frame from `source`, returning `true` if a frame was decoded.
+	///
+	/// # Errors
+	///
+	/// [`DecodeError::Truncated`]: in fixed mode, the stream ended before all
+	/// samples given in the file header were decoded.
+	///
+	/// [`DecodeError::Read`]: in streaming mode, the stream ended partway through
+	/// a long, with [`ReadError::Partial`]. Only an end between frames stops
+	/// decoding cleanly.
 	pub fn decode_frame<S: SourceStream>(&mut self, source: &mut S) -> Result<bool> {
-		let Self { samples, sink, header, lms, slice, slice_buf } = self;
+		match self.decode_frame_inner(source) {
+			Err(Eof | Read(ReadError::Partial(_))) if self.samples.is_some_and(|n| n > 0) => {
+				let expected = self.expected;
+				let got = expected - self.samples.unwrap_or_default();
+				Err(Truncated { expected, got })
+			}
+			result => result
+		}
+	}
+
+	fn decode_frame_inner<S: SourceStream>(&mut self, source: &mut S) -> Result<bool> {
+		let Self { samples, expected, sink, header, lms, slice, slice_buf } = self;
 		let streaming_mode;
 		let samples = {
 			if *header {
 				*header = false;
 				let header_samples = source.dec_file_header()?;
 				streaming_mode = header_samples == 0;
+				*expected = header_samples;
 
 				if !streaming_mode {
 					let _ = samples.insert(header_samples);
@@ -126,7 +165,9 @@ impl<Sn: PcmSink> Decoder<Sn> {
 
 			// The EOF isn't an error in streaming mode, it's the break signal for
 			// frame decoding. In contrast with fixed mode, where ending before we
-			// read the number of samples given in the header is an error.
+			// read the number of samples given in the header is an error. An EOF
+			// partway through the header is a truncated stream in either mode,
+			// which the source reports as a partial read rather than an EOF.
 			if streaming_mode {
 				if let Err(Eof) = header {
 					return Ok(false)
@@ -153,15 +194,23 @@ impl<Sn: PcmSink> Decoder<Sn> {
 					let qr = resid[si];
 					let predicted = lms[chn as usize].predict();
 					let dequantized = DEQUANT_TABLE[*quant as usize][qr as usize];
-					let reconst = (predicted + dequantized) as i16;
+					let reconst = reconstruct(predicted, dequantized);
 
 					slice_buf[si] = reconst;
 
 					lms[chn as usize].update(reconst, dequantized);
 				}
 
-				sink.write(&slice_buf[..slice_width], chn as usize)
-					.map_err(|err| Write(Sample, err.into()))?;
+				// Retry short writes while the sink makes progress.
+				let mut buf = &slice_buf[..slice_width];
+				while !buf.is_empty() {
+					let written = sink.write(buf, chn as usize)
+									  .map_err(|err| Write(Sample, err.into()))?;
+					if written == 0 {
+						return Err(SinkFull(chn as usize))
+					}
+					buf = &buf[written..];
+				}
 			}
 		}
 
@@ -169,6 +218,38 @@ impl<Sn: PcmSink> Decoder<Sn> {
 		Ok(true)
 	}
 	
+	/// Returns a copy of the decode state with `sink` in place of the underlying
+	/// sink. Forked at a frame boundary, both decoders continue from the same
+	/// source position identically.
+	pub fn fork<T: PcmSink>(&self, sink: T) -> Decoder<T> {
+		let Self { samples, expected, header, ref lms, slice, slice_buf, .. } = *self;
+		Decoder { samples, expected, sink, header, lms: lms.clone(), slice, slice_buf }
+	}
+
+	/// Returns a copy of the LMS predictor state of each channel after the last
+	/// decoded frame.
+	pub fn snapshot_lms(&self) -> Vec<QoaLmsState> { self.lms.clone() }
+
+	/// Restores the LMS predictor state of each channel from a snapshot. Each frame
+	/// header carries the state its slices start from, so the next frame decoded
+	/// replaces it; decoding resumes identically from the source position the
+	/// snapshot was taken at. To branch decoding with another sink, use
+	/// [`Self::fork`].
+	pub fn restore_lms(&mut self, lms: &[QoaLmsState]) {
+		self.lms.clear();
+		self.lms.extend_from_slice(lms);
+	}
+
+	/// Returns a reference to the underlying sink.
+	pub fn sink(&self) -> &Sn { &self.sink }
+
+	/// Returns a mutable reference to the underlying sink, to drain samples
+	/// between frames.
+	pub fn sink_mut(&mut self) -> &mut Sn { &mut self.sink }
+
+	/// Returns the underlying sink without closing it.
+	pub fn into_sink(self) -> Sn { self.sink }
+
 	/// Flushes and closes the underlying sink, then returns it.
 	pub fn close(mut self) -> Result<Sn> {
 		self.sink
@@ -188,7 +269,7 @@ impl<S: PcmSink> From<S> for Decoder<S> {
 	fn from(value: S) -> Self { Self::new(value) }
 }
 
-pub(crate) trait QoaSource: SourceStream {
+pub(crate) trait QoaSource: SourceStream + Sized {
 	fn dec_file_header(&mut self) -> Result<u32> {
 		let v = self.read_long()?;
 
@@ -206,13 +287,17 @@ pub(crate) trait QoaSource: SourceStream {
 		let rate = ((v >> 32) & 0xFFFFFF) as u32;
 		let samples = (v >> 16) as u16;
 		let size = v as u16;
+
+		if channels == 0 || rate == 0 {
+			return Err(InvalidFrameHeader(channels, rate))
+		}
+
 		Ok((channels, rate, samples, size))
 	}
 
 	fn dec_lms(&mut self, lms: &mut [QoaLmsState]) -> Result {
 		for lms in lms {
-			let mut history = self.read_long()?;
-			let mut weights = self.read_long()?;
+			let [mut history, mut weights] = self.read_longs::<2>()?;
 			for i in 0..4 {
 				lms.history[i] = (history >> 48) as i16 as i32;
 				history <<= 16;
@@ -231,20 +316,65 @@ pub(crate) trait QoaSource: SourceStream {
 
 impl<S: SourceStream> QoaSource for S { }
 
-impl QoaSlice {
-	fn unpack(&mut self, mut v: u64) {
-		let ref mut resid = self.resid;
-		for i in (0..20).rev() {
-			resid[i] = (v & 0b111) as u8;
-			v >>= 3;
+#[cfg(test)]
+mod test {
+	use std::mem;
+	use quickcheck::{Arbitrary, Gen, TestResult};
+	use quickcheck_macros::quickcheck;
+	use std::io::Cursor;
+	use crate::{byte_decoder, frame_byte_size, FRAME_LEN, MAGIC, PcmBuffer, PcmFrame, PcmSink, PcmStream, qc_assert_eq, QoaSlice, SLICE_LEN};
+	use crate::pcm_io;
+	use crate::io::{Buffer, ReadError};
+	use crate::reference::encode_ref;
+	use super::{DecodeError, Decoder};
+
+	#[derive(Clone, Debug)]
+	struct Samples(Vec<i16>);
+
+	impl Arbitrary for Samples {
+		fn arbitrary(g: &mut Gen) -> Self {
+			let len = (usize::arbitrary(g) % 64 + 1) * SLICE_LEN;
+			Self((0..len).map(|_| i16::arbitrary(g)).collect())
 		}
-		self.quant = v as u8;
 	}
-}
 
-#[cfg(test)]
-mod test {
-	use crate::QoaSlice;
+	/// A frame header biased toward zero channels and sample rates, followed by
+	/// zeroed frame data.
+	#[derive(Clone, Debug)]
+	struct MalformedFrame {
+		channels: u8,
+		rate: u32,
+		samples: u16,
+		size: u16,
+		longs: u8,
+	}
+
+	impl Arbitrary for MalformedFrame {
+		fn arbitrary(g: &mut Gen) -> Self {
+			let channels = u8::arbitrary(g);
+			let rate = u32::arbitrary(g) & 0xFFFFFF;
+			Self {
+				channels: *g.choose(&[0, 1, channels]).unwrap(),
+				rate: *g.choose(&[0, 44100, rate]).unwrap(),
+				samples: u16::arbitrary(g),
+				size: u16::arbitrary(g),
+				longs: u8::arbitrary(g),
+			}
+		}
+	}
+
+	impl MalformedFrame {
+		fn encode(&self) -> Vec<u8> {
+			let Self { channels, rate, samples, size, longs } = *self;
+			let header = (channels as u64) << 56 | (rate as u64) << 32 | (samples as u64) << 16 | size as u64;
+
+			let mut data = Vec::new();
+			data.extend_from_slice(&((MAGIC as u64) << 32 | samples as u64).to_be_bytes());
+			data.extend_from_slice(&header.to_be_bytes());
+			data.resize(data.len() + longs as usize * 8, 0);
+			data
+		}
+	}
 
 	#[test]
 	fn unpack_slice() {
@@ -273,4 +403,280 @@ mod test {
 
 		assert_eq!(slice, QoaSlice { quant: 9, resid: UNPACKED });
 	}
+
+	#[quickcheck]
+	fn decoder_parity(Samples(samples): Samples) -> TestResult {
+		let data = encode_ref(&samples, 1);
+
+		let mut expected = Vec::new();
+		if let Err(error) = byte_decoder::Decoder::default().decode(&data, &mut expected) {
+			return TestResult::error(format!("{error}"))
+		}
+
+		let mut source = Buffer::decode(&mut data.clone());
+		let actual: Vec<i16> = match Decoder::new(PcmBuffer::default()).decode(&mut source) {
+			Ok(buf) => buf.unwrap().into_iter().flat_map(PcmFrame::unwrap).collect(),
+			Err(error) => return TestResult::error(format!("{error}"))
+		};
+
+		qc_assert_eq!(actual, expected)
+	}
+
+	fn assert_send_sync<T: Send + Sync>() { }
+
+	#[test]
+	fn thread_safety() {
+		assert_send_sync::<Decoder<PcmBuffer>>();
+		assert_send_sync::<byte_decoder::Decoder>();
+	}
+
+	#[test]
+	fn drain_between_frames() {
+		const LEN: usize = FRAME_LEN * 2 + 300;
+
+		let samples: Vec<i16> = (0..LEN).map(|i| (i * 13 % 5000) as i16).collect();
+		let data = encode_ref(&samples, 1);
+
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut expected).unwrap();
+
+		let mut source = Buffer::decode(&mut data.clone());
+		let mut decoder = Decoder::new(PcmBuffer::default());
+		let mut actual = Vec::new();
+		let mut lens = Vec::new();
+		while decoder.decode_frame(&mut source).unwrap() {
+			lens.push(decoder.sink().len());
+			let frames = mem::take(decoder.sink_mut()).unwrap();
+			actual.extend(frames.into_iter().flat_map(PcmFrame::unwrap));
+		}
+
+		assert_eq!(lens, [FRAME_LEN, FRAME_LEN, 300]);
+		assert_eq!(actual, expected);
+		assert!(decoder.into_sink().is_empty());
+	}
+
+	#[test]
+	fn partial_slice() {
+		for channels in [1, 2] {
+			let samples: Vec<i16> = [1000, -2000, 3000].repeat(channels);
+			let data = encode_ref(&samples, channels);
+			// The file header, frame header, LMS states, and one slice per channel.
+			assert_eq!(data.len(), 16 + channels * 24);
+
+			let mut expected = Vec::new();
+			byte_decoder::Decoder::default().decode(&data, &mut expected).unwrap();
+
+			let mut source = Buffer::decode(&mut data.clone());
+			let actual: Vec<i16> = Decoder::new(PcmBuffer::default())
+				.decode(&mut source)
+				.unwrap()
+				.unwrap()
+				.into_iter()
+				.flat_map(PcmFrame::unwrap)
+				.collect();
+
+			assert_eq!(actual.len(), 3 * channels, "{channels} channels");
+			assert_eq!(actual, expected, "{channels} channels");
+		}
+	}
+
+	#[test]
+	fn truncated() {
+		const LEN: usize = 5120 * 2;
+
+		let samples: Vec<i16> = (0..LEN).map(|i| (i * 31 % 4096) as i16).collect();
+		let mut data = encode_ref(&samples, 1);
+		// Drop the second frame, leaving the file header and the first frame.
+		data.truncate(8 + 8 + 16 + 256 * 8);
+
+		let mut source = Buffer::decode(&mut data);
+		match Decoder::new(PcmBuffer::default()).decode(&mut source) {
+			Err(DecodeError::Truncated { expected, got }) => {
+				assert_eq!(expected, LEN as u32);
+				assert_eq!(got, 5120);
+			}
+			Err(error) => panic!("expected truncation error, got {error}"),
+			Ok(_) => panic!("expected truncation error")
+		}
+	}
+
+	#[test]
+	fn truncated_header() {
+		const FRAME_SIZE: usize = frame_byte_size(2, FRAME_LEN / SLICE_LEN);
+
+		let mut data = stereo_file();
+		data.truncate(8 + FRAME_SIZE + 4);
+		// Fixed mode.
+		let result = Decoder::new(PcmBuffer::default()).decode(&mut Cursor::new(data.clone()));
+		assert!(
+			matches!(result, Err(DecodeError::Truncated { got: 5120, .. })),
+			"expected truncation error, got {:?}", result.err()
+		);
+
+		// Streaming mode.
+		data[4..8].fill(0);
+		let result = Decoder::new(PcmBuffer::default()).decode(&mut Cursor::new(data.clone()));
+		assert!(
+			matches!(result, Err(DecodeError::Read(ReadError::Partial(4)))),
+			"expected partial read error, got {:?}", result.err()
+		);
+
+		// Ending between frames is the clean streaming break.
+		data.truncate(8 + FRAME_SIZE);
+		let sink = Decoder::new(PcmBuffer::default()).decode(&mut Cursor::new(data)).unwrap();
+		assert_eq!(sink.len(), FRAME_LEN);
+	}
+
+	#[test]
+	fn fork() {
+		const LEN: usize = FRAME_LEN * 3 + 200;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 19 % 7000) as i16).collect();
+		let mut data = encode_ref(&samples, 2);
+
+		let mut source = Buffer::decode(&mut data);
+		let mut decoder = Decoder::new(PcmBuffer::default());
+		assert!(decoder.decode_frame(&mut source).unwrap());
+
+		let lms = decoder.snapshot_lms();
+		assert_eq!(lms.len(), 2);
+
+		let forked = decoder.fork(PcmBuffer::default());
+		let fork_source = source.clone();
+
+		assert!(decoder.decode_frame(&mut source).unwrap());
+		assert_ne!(decoder.snapshot_lms(), lms);
+		assert_eq!(forked.snapshot_lms(), lms);
+
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&encode_ref(&samples, 2), &mut expected).unwrap();
+
+		let mut source = fork_source;
+		let actual: Vec<i16> = forked.decode(&mut source)
+									 .unwrap()
+									 .unwrap()
+									 .into_iter()
+									 .flat_map(PcmFrame::unwrap)
+									 .collect();
+		assert_eq!(actual, expected[FRAME_LEN * 2..]);
+	}
+
+	#[test]
+	fn restore_lms() {
+		const LEN: usize = FRAME_LEN * 3 + 200;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 23 % 9000) as i16 - 4500).collect();
+		let mut data = encode_ref(&samples, 2);
+
+		let mut source = Buffer::decode(&mut data);
+		let mut decoder = Decoder::new(PcmBuffer::default());
+		let take_samples = |decoder: &mut Decoder<PcmBuffer>| -> Vec<i16> {
+			mem::take(decoder.sink_mut()).unwrap()
+										 .into_iter()
+										 .flat_map(PcmFrame::unwrap)
+										 .collect()
+		};
+		assert!(decoder.decode_frame(&mut source).unwrap());
+		take_samples(&mut decoder);
+
+		// Save the state mid-stream, then decode the next frame.
+		let lms = decoder.snapshot_lms();
+		let mut saved = source.clone();
+		assert!(decoder.decode_frame(&mut source).unwrap());
+		let next_lms = decoder.snapshot_lms();
+		let first = take_samples(&mut decoder);
+		assert_ne!(next_lms, lms);
+
+		// Resume from the saved state and position.
+		decoder.restore_lms(&lms);
+		assert_eq!(decoder.snapshot_lms(), lms);
+		assert!(decoder.decode_frame(&mut saved).unwrap());
+		assert_eq!(decoder.snapshot_lms(), next_lms);
+		assert_eq!(take_samples(&mut decoder), first);
+	}
+
+	/// A mono sink accepting at most a few samples per write, fewer than a slice.
+	struct ShortSink(Vec<i16>);
+
+	impl PcmStream for ShortSink {
+		fn channel_count(&self) -> usize { 1 }
+		fn sample_rate(&self) -> u32 { 44100 }
+	}
+
+	impl PcmSink for ShortSink {
+		fn write(&mut self, buf: &[i16], _: usize) -> Result<usize, pcm_io::Error> {
+			let len = buf.len().min(self.sample_capacity());
+			self.0.extend_from_slice(&buf[..len]);
+			Ok(len)
+		}
+
+		fn write_interleaved(&mut self, buf: &[i16]) -> Result<usize, pcm_io::Error> {
+			self.write(buf, 0)
+		}
+
+		fn sample_capacity(&self) -> usize { 7 }
+
+		fn set_descriptor(&mut self, _: u32, _: usize) -> Result<(), pcm_io::Error> { Ok(()) }
+	}
+
+	#[test]
+	fn short_writes() {
+		const LEN: usize = FRAME_LEN + 150;
+
+		let samples: Vec<i16> = (0..LEN).map(|i| (i * 17 % 6000) as i16).collect();
+		let data = encode_ref(&samples, 1);
+
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut expected).unwrap();
+
+		let mut source = Buffer::decode(&mut data.clone());
+		let ShortSink(actual) = Decoder::new(ShortSink(Vec::new())).decode(&mut source).unwrap();
+		assert_eq!(actual, expected);
+	}
+
+	#[test]
+	fn sink_full() {
+		let samples = vec![0; FRAME_LEN];
+		let mut data = encode_ref(&samples, 1);
+
+		let mut source = Buffer::decode(&mut data);
+		let result = Decoder::new(PcmFrame::new(100, 44100, 1)).decode(&mut source);
+		assert!(matches!(result, Err(DecodeError::SinkFull(0))));
+	}
+
+	#[quickcheck]
+	fn decode_malformed_frame(frame: MalformedFrame) -> TestResult {
+		let mut source = Buffer::decode(&mut frame.encode());
+		let result = Decoder::new(PcmFrame::new(FRAME_LEN, 44100, 1)).decode(&mut source);
+
+		if frame.channels == 0 || frame.rate == 0 {
+			TestResult::from_bool(matches!(result, Err(DecodeError::InvalidFrameHeader(..))))
+		} else {
+			TestResult::passed()
+		}
+	}
+
+	/// Encodes ten frames of a stereo tone with the reference encoder.
+	fn stereo_file() -> Vec<u8> {
+		const LEN: usize = FRAME_LEN * 10;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| {
+			let t = (i / 2) as f32 / 44100.0;
+			let f = if i % 2 == 0 { 440.0 } else { 554.4 };
+			((t * f * std::f32::consts::TAU).sin() * 16000.0) as i16
+		}).collect();
+		encode_ref(&samples, 2)
+	}
+
+	#[test]
+	fn decode_pcm_buffer() {
+		let data = stereo_file();
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut expected).unwrap();
+
+		let mut source = Buffer::decode(&mut data.clone());
+		let buf = Decoder::new(PcmBuffer::default()).decode(&mut source).unwrap();
+		let actual: Vec<i16> = buf.unwrap().into_iter().flat_map(PcmFrame::unwrap).collect();
+		assert_eq!(actual, expected);
+	}
 }
@@ -12,19 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::assert_matches::debug_assert_matches;
-use std::cmp::min;
-use std::result;
-use std::io::Read;
-use amplify_derive::{Display, Error};
+use core::cmp::{max, min};
+use core::iter::FusedIterator;
+use core::result;
+use alloc::string::String;
+use alloc::vec::Vec;
+use amplify_derive::Display;
+#[cfg(feature = "std")]
+use amplify_derive::Error;
 use Error::{Eos, UnknownMagic};
-use crate::{DEQUANT_TABLE, MAGIC, SLICE_LEN};
-use crate::byte_decoder::Error::DescriptorChange;
-use crate::util::Zip;
+use crate::{CHECKSUM_MAGIC, DescriptorError, frame_byte_size, FRAME_LEN, LOOP_MAGIC, MAGIC, META_MAGIC, SLICE_LEN, StreamDescriptor};
+#[cfg(not(all(feature = "std", feature = "simd")))]
+use crate::DEQUANT_TABLE;
+use crate::byte_decoder::Error::{ChecksumMismatch, Descriptor, DescriptorChange, FrameSize, InvalidFrameHeader, SinkFull};
+use crate::util::Crc32;
 
 type Result<T = ()> = result::Result<T, Error>;
 
-#[derive(Debug, Display, Error)]
+#[derive(Debug, Display)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum Error {
 	#[display("unknown magic bytes {0:?}")]
 	UnknownMagic([u8; 4]),
@@ -33,32 +39,331 @@ pub enum Error {
 		sample rate to {0} and channel to {1}"
 	)]
 	DescriptorChange(u32, usize),
+	#[cfg(feature = "std")]
 	#[display("{0}")]
 	IO(crate::Error),
 	#[display("unexpected end-of-stream")]
 	Eos,
+	#[display("frame size {0} does not match the expected size {1}")]
+	FrameSize(usize, usize),
+	#[display("{0}")]
+	Descriptor(DescriptorError),
+	#[display("sample buffer is full")]
+	SinkFull,
+	#[display("checksum mismatch in frame {0}")]
+	ChecksumMismatch(usize),
+	#[display("invalid frame header with {0} channels at {1}Hz")]
+	InvalidFrameHeader(usize, u32),
 }
 
+#[cfg(feature = "std")]
 impl From<crate::Error> for Error {
 	fn from(value: crate::Error) -> Self { Self::IO(value) }
 }
 
+/// A QOA decoder reading from byte slices. It holds no shared state, so it's
+/// [`Send`] and [`Sync`].
 #[derive(Clone, Debug, Default)]
 pub struct Decoder {
 	lms: Vec<LmsState>,
-	buf: [i16; SLICE_LEN],
+	/// The interleaved samples of one slice of each channel.
+	buf: Vec<i16>,
+	cursor: Cursor,
+	loop_points: Option<(u32, u32)>,
+	/// The key-value tags from the metadata trailer.
+	metadata: Vec<(String, String)>,
+	verify: bool,
+	/// The checksum of each decoded frame, if verifying.
+	checksums: Vec<u32>,
+	/// The packed slices of each channel, decoded together.
+	#[cfg(all(feature = "std", feature = "simd"))]
+	slices: Vec<u64>,
+	/// The decoded samples of each channel.
+	#[cfg(all(feature = "std", feature = "simd"))]
+	planes: Vec<[i16; SLICE_LEN]>,
+}
+
+/// The position of an incremental decode.
+#[derive(Copy, Clone, Debug, Default)]
+struct Cursor {
+	/// The byte offset to resume decoding from.
+	offset: usize,
+	/// The number of samples per channel remaining in the stream, or `None` if
+	/// the file header hasn't been read.
+	samples: Option<usize>,
+	streaming: bool,
+	/// The channel count of the current frame.
+	channels: usize,
+	/// The number of samples per channel remaining in the current frame.
+	frame: usize,
 }
 
 impl Decoder {
-	pub fn decode(&mut self, mut source: &[u8], sink: &mut Vec<i16>) -> Result<usize> {
+	/// Decodes samples from `source` into `sink`, returning the number of bytes
+	/// read.
+	pub fn decode(&mut self, source: &[u8], sink: &mut Vec<i16>) -> Result<usize> {
+		self.decode_with_descriptor(source, sink)
+			.map(|(bytes, _)| bytes)
+	}
+
+	/// Decodes samples from the `len` bytes of `source` starting at `offset` into
+	/// `sink`, returning the number of bytes read. This decodes a stream embedded
+	/// in a larger container without copying it out.
+	///
+	/// # Errors
+	///
+	/// [`Error::Eos`]: the range extends past the end of `source`.
+	pub fn decode_at(
+		&mut self,
+		source: &[u8],
+		offset: usize,
+		len: usize,
+		sink: &mut Vec<i16>
+	) -> Result<usize> {
+		let end = offset.checked_add(len).ok_or(Eos)?;
+		let source = source.get(offset..end).ok_or(Eos)?;
+		self.decode(source, sink)
+	}
+
+	/// Decodes samples from `source` into `sink`, returning the number of bytes
+	/// read and a descriptor of the decoded stream. Space is reserved for the
+	/// samples claimed by the file header, at most as many as `source` can hold.
+	pub fn decode_with_descriptor(
+		&mut self,
+		source: &[u8],
+		sink: &mut Vec<i16>
+	) -> Result<(usize, StreamDescriptor)> {
+		if let Ok(len) = Self::estimate_output_len(source) {
+			sink.reserve(min(len, source.len() / 8 * SLICE_LEN));
+		}
+
+		self.decode_to(source, sink)
+	}
+
+	/// Returns the number of samples the stream in `source` claims to hold across
+	/// all channels, from its file header and first frame header, or `0` in
+	/// streaming mode. The header isn't checked against the stream length.
+	///
+	/// # Errors
+	///
+	/// See [`QoaHeader::try_from`]. Additionally:
+	///
+	/// [`Error::Descriptor`]: the sample count overflows `usize`.
+	pub fn estimate_output_len(source: &[u8]) -> Result<usize> {
+		let QoaHeader { sample_count, channel_count, .. } = QoaHeader::try_from(source)?;
+		(sample_count as usize)
+			.checked_mul(channel_count as usize)
+			.ok_or(Descriptor(DescriptorError::TooManySamples(sample_count as usize)))
+	}
+
+	/// Decodes samples from the start of `source` into the `sink` slice without
+	/// allocating, until `sink` can't hold another slice from each channel or the
+	/// source ends. Returns the number of samples written, `0` once the stream is
+	/// fully decoded. Decoding continues from the stopping point with
+	/// [`Self::resume_into`].
+	///
+	/// # Errors
+	///
+	/// [`Error::SinkFull`]: `sink` is too small to hold a slice from each channel.
+	pub fn decode_into(&mut self, source: &[u8], sink: &mut [i16]) -> Result<usize> {
+		self.cursor = Cursor::default();
+		self.loop_points = None;
+		self.metadata.clear();
+		self.resume_into(source, sink)
+	}
+
+	/// Continues decoding `source` into `sink` from the byte offset the previous
+	/// call to [`Self::decode_into`] or [`Self::resume_into`] stopped at. `source`
+	/// must be the same stream passed to those calls.
+	///
+	/// # Errors
+	///
+	/// See [`Self::decode_into`].
+	pub fn resume_into(&mut self, source: &[u8], sink: &mut [i16]) -> Result<usize> {
+		let mut cursor = self.cursor;
+		let mut input = &source[min(cursor.offset, source.len())..];
+		let mut output = SliceOutput { buf: sink, len: 0 };
+
+		let mut remaining = match cursor.samples {
+			Some(samples) => samples,
+			None => {
+				let samples = input.decode_file_header()? as usize;
+				cursor.streaming = samples == 0;
+				samples
+			}
+		};
+
+		loop {
+			if cursor.frame == 0 {
+				if (cursor.streaming || remaining == 0) && self.decode_trailers(&mut input, false)? > 0 {
+					break
+				}
+
+				if remaining == 0 && !(cursor.streaming && !input.is_empty()) {
+					break
+				}
+
+				let (chan, _, samples, _) = input.decode_frame_header()?;
+				self.lms.resize_with(chan, Default::default);
+				input.decode_lms(&mut self.lms)?;
+				cursor.channels = chan;
+				cursor.frame = samples;
+				remaining = remaining.saturating_sub(samples);
+
+				if samples == 0 { continue }
+			}
+
+			let len = min(SLICE_LEN, cursor.frame);
+			if output.buf.len() - output.len < len * cursor.channels {
+				if output.len == 0 {
+					return Err(SinkFull)
+				}
+
+				break
+			}
+
+			self.decode_slices(&mut input, &mut output, len, cursor.channels)?;
+			cursor.frame -= len;
+		}
+
+		cursor.samples = Some(remaining);
+		cursor.offset = source.len() - input.len();
+		self.cursor = cursor;
+		Ok(output.len)
+	}
+
+	/// Returns a lazy iterator over the interleaved samples of `source`, consuming
+	/// the decoder. Samples are decoded a frame at a time as the iterator advances,
+	/// so taking a prefix of the stream only decodes the frames it spans.
+	pub fn into_iter_over(mut self, source: &[u8]) -> Samples<'_> {
+		self.cursor = Cursor::default();
+		self.loop_points = None;
+		self.metadata.clear();
+		Samples {
+			decoder: self,
+			source,
+			buf: Vec::new(),
+			pos: 0,
+			len: 0,
+			done: false,
+		}
+	}
+
+	/// Returns the byte offset the next call to [`Self::resume_into`] will decode
+	/// from.
+	pub fn offset(&self) -> usize { self.cursor.offset }
+
+	/// Returns the loop start and end sample offsets read from the trailer of the
+	/// last decoded stream, if present.
+	pub fn loop_points(&self) -> Option<(u32, u32)> { self.loop_points }
+
+	/// Returns the key-value tags read from the metadata trailer of the last
+	/// decoded stream, empty if not present.
+	pub fn metadata(&self) -> &[(String, String)] { &self.metadata }
+
+	/// Sets whether frames are verified against the checksum trailer, if present,
+	/// when decoding with [`Self::decode`] or [`Self::decode_with_descriptor`].
+	/// Disabled by default.
+	pub fn verify_checksums(&mut self, verify: bool) {
+		self.verify = verify;
+	}
+
+	/// Clears the decoder state, keeping its allocations, so it can decode another
+	/// stream.
+	pub fn reset(&mut self) {
+		self.lms.clear();
+		self.buf.clear();
+		self.cursor = Cursor::default();
+		self.loop_points = None;
+		self.metadata.clear();
+		self.checksums.clear();
+	}
+
+	/// Reads any trailers `source` starts with, returning the number of bytes read.
+	/// If `verify` is `true` and checksum verification is enabled, the checksum
+	/// trailer is checked against the decoded frames.
+	///
+	/// # Errors
+	///
+	/// [`Error::ChecksumMismatch`]: a frame checksum doesn't match the trailer.
+	fn decode_trailers(&mut self, source: &mut &[u8], verify: bool) -> Result<usize> {
+		let start = source.len();
+		loop {
+			let mut input = *source;
+			let Ok(header) = input.read_long() else { break };
+			let size = header as u32 as usize;
+			let padded = (size + 7) & !7;
+
+			if input.len() < padded { break }
+
+			let (mut payload, rest) = input.split_at(padded);
+			match (header >> 32) as u32 {
+				LOOP_MAGIC if size == 8 => {
+					let points = payload.read_long()?;
+					self.loop_points = Some(((points >> 32) as u32, points as u32));
+				}
+				CHECKSUM_MAGIC if size % 4 == 0 => {
+					if verify && self.verify {
+						self.check_checksums(&payload[..size])?
+					}
+				}
+				META_MAGIC => {
+					let Some(tags) = parse_metadata(&payload[..size]) else { break };
+					self.metadata = tags;
+				}
+				_ => break
+			}
+
+			*source = rest;
+		}
+
+		Ok(start - source.len())
+	}
+
+	/// Compares big endian frame checksums from `payload` to those of the decoded
+	/// frames.
+	fn check_checksums(&self, payload: &[u8]) -> Result {
+		let count = payload.len() / 4;
+		for (index, bytes) in payload.chunks_exact(4).enumerate() {
+			let expected = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+			if self.checksums.get(index) != Some(&expected) {
+				return Err(ChecksumMismatch(index))
+			}
+		}
+
+		if self.checksums.len() != count {
+			return Err(ChecksumMismatch(count))
+		}
+
+		Ok(())
+	}
+
+	fn decode_to(
+		&mut self,
+		mut source: &[u8],
+		sink: &mut impl Output
+	) -> Result<(usize, StreamDescriptor)> {
 		let mut sample_count = source.decode_file_header()? as usize;
 		let streaming_mode = sample_count == 0;
+		let mut decoded = 0;
+		self.loop_points = None;
+		self.metadata.clear();
+		self.checksums.clear();
 
 		let mut sample_rate = 0;
 		let mut channels    = 0;
 
 		let mut bytes = 8;
 		while sample_count > 0 || (streaming_mode && !source.is_empty()) {
+			if streaming_mode {
+				let trailers = self.decode_trailers(&mut source, true)?;
+				if trailers > 0 {
+					bytes += trailers;
+					break
+				}
+			}
+
+			let frame = source;
 			let (chan, rate, samples, _) = source.decode_frame_header()?;
 
 			if sample_rate == 0  {
@@ -76,63 +381,619 @@ impl Decoder {
 			let size = self.decode_frame(source, sink, samples, chan)?;
 			source = &source[size..];
 			bytes += size + 8;
-			sample_count -= samples;
+			sample_count = sample_count.saturating_sub(samples);
+			decoded += samples;
+
+			if self.verify {
+				self.checksums.push(Crc32::checksum(&frame[..size + 8]));
+			}
+		}
+
+		if !streaming_mode {
+			bytes += self.decode_trailers(&mut source, true)?;
+		}
+
+		let desc = StreamDescriptor {
+			sample_count : (decoded     > 0).then_some(decoded    ),
+			sample_rate  : (sample_rate > 0).then_some(sample_rate),
+			channel_count: (channels    > 0).then_some(channels   ),
+		};
+		Ok((bytes, desc))
+	}
+
+	/// Decodes samples from a `source` whose sample rate or channel count may change
+	/// between frames into `sink`, returning the number of bytes read. Each run of
+	/// frames sharing a sample rate and channel count is written as a separate
+	/// [`PcmFrame`](crate::PcmFrame).
+	///
+	/// # Errors
+	///
+	/// [`Error::InvalidFrameHeader`]: a frame has a sample rate or channel count of
+	/// `0`.
+	#[cfg(feature = "std")]
+	pub fn decode_variable(&mut self, mut source: &[u8], sink: &mut crate::PcmBuffer) -> Result<usize> {
+		use crate::{PcmFrame, PcmSink};
+
+		let mut sample_count = source.decode_file_header()? as usize;
+		let streaming_mode = sample_count == 0;
+		self.loop_points = None;
+		self.metadata.clear();
+
+		let mut frame: Option<PcmFrame> = None;
+		let mut samples_buf = Vec::new();
+		let mut bytes = 8;
+		while sample_count > 0 || (streaming_mode && !source.is_empty()) {
+			if streaming_mode {
+				let trailers = self.decode_trailers(&mut source, false)?;
+				if trailers > 0 {
+					bytes += trailers;
+					break
+				}
+			}
+
+			let (chan, rate, samples, _) = source.decode_frame_header()?;
+
+			samples_buf.clear();
+			let size = self.decode_frame(source, &mut samples_buf, samples, chan)?;
+			source = &source[size..];
+			bytes += size + 8;
+			sample_count = sample_count.saturating_sub(samples);
+
+			let mut current = match frame.take() {
+				Some(mut cur) if cur.rate() == rate && cur.channels() == chan => {
+					cur.grow(samples);
+					cur
+				}
+				prev => {
+					if let Some(prev) = prev {
+						sink.write_frame(prev)?;
+					}
+					PcmFrame::new(samples, rate, chan)
+				}
+			};
+			current.write_interleaved(&samples_buf)?;
+			frame = Some(current);
+		}
+
+		if !streaming_mode {
+			bytes += self.decode_trailers(&mut source, false)?;
+		}
+
+		if let Some(frame) = frame {
+			sink.write_frame(frame)?;
 		}
 
 		Ok(bytes)
 	}
 
+	/// Decodes samples like [`Self::decode`], but recovers from corrupt frames by
+	/// writing silence in their place, then returns the number of frames skipped.
+	/// A frame with an invalid header is assumed to be a full frame in the stream's
+	/// format, ending at the next frame boundary. Before a valid frame gives the
+	/// stream's format, corrupt data is skipped up to the next valid frame header
+	/// and silenced as the nearest number of full frames. A frame cut off by the
+	/// end of the stream is silenced for its declared sample count. Trailers are
+	/// read, but checksums aren't verified.
+	pub fn decode_lossy(&mut self, mut source: &[u8], sink: &mut Vec<i16>) -> Result<usize> {
+		let mut sample_count = source.decode_file_header()? as usize;
+		let streaming_mode = sample_count == 0;
+		self.loop_points = None;
+		self.metadata.clear();
+
+		let valid_header = |(chan, rate, samples, size): (usize, u32, usize, usize)|
+			chan > 0                           &&
+			rate > 0                           &&
+			(1..=FRAME_LEN).contains(&samples) &&
+			size == frame_size(chan, samples);
+
+		let mut desc = None;
+		let mut skipped = 0;
+		// The number of bytes skipped before the stream's format was known.
+		let mut lost = 0;
+		while sample_count > 0 || (streaming_mode && !source.is_empty()) {
+			if streaming_mode && self.decode_trailers(&mut source, false)? > 0 {
+				break
+			}
+
+			let frame = source;
+			let header = match source.read_frame_header() {
+				Ok(header) => header,
+				Err(_) => {
+					// The stream ended mid-header; silence the remaining samples.
+					if let Some((_, chan)) = desc {
+						sink.resize(sink.len() + sample_count * chan, 0);
+					}
+					skipped += 1;
+					break
+				}
+			};
+			let (mut chan, rate, mut samples, mut size) = header;
+
+			let (stream_rate, stream_chan) = match desc {
+				Some(desc) => desc,
+				None if valid_header(header) => {
+					if lost > 0 {
+						let full = frame_size(chan, FRAME_LEN);
+						let frames = max(1, (lost + full / 2) / full);
+						let mut silent = frames * FRAME_LEN;
+						if !streaming_mode {
+							silent = min(silent, sample_count.saturating_sub(samples));
+							sample_count -= silent;
+						}
+
+						sink.resize(sink.len() + silent * chan, 0);
+						skipped += frames;
+					}
+
+					*desc.insert((rate, chan))
+				}
+				None => {
+					// The frame's end is unknown without the stream's format, so skip
+					// to the next valid frame header.
+					let next = (8..frame.len()).step_by(8).find(|&off|
+						(&frame[off..]).read_frame_header().is_ok_and(valid_header)
+					);
+					let Some(next) = next else {
+						source = &[];
+						skipped += 1;
+						break
+					};
+
+					lost += next;
+					source = &frame[next..];
+					continue
+				}
+			};
+
+			let valid = valid_header(header) &&
+						chan == stream_chan  &&
+						rate == stream_rate;
+
+			if !valid {
+				chan = stream_chan;
+				samples = if streaming_mode {
+					FRAME_LEN
+				} else {
+					min(FRAME_LEN, sample_count)
+				};
+				size = frame_size(chan, samples);
+			}
+
+			let start = sink.len();
+			let decoded = valid && frame.len() >= size &&
+				self.decode_frame(&frame[8..size], sink, samples, chan).is_ok();
+
+			if !decoded {
+				sink.truncate(start);
+				sink.resize(start + samples * chan, 0);
+				skipped += 1;
+			}
+
+			source = &frame[min(size, frame.len())..];
+			sample_count = sample_count.saturating_sub(samples);
+		}
+
+		if !streaming_mode {
+			self.decode_trailers(&mut source, false)?;
+		}
+
+		Ok(skipped)
+	}
+
 	fn decode_frame(
 		&mut self,
 		mut source: &[u8],
-		sink: &mut Vec<i16>,
+		sink: &mut impl Output,
 		samples: usize,
 		channels: usize,
 	) -> Result<usize> {
-		let Self { ref mut lms, ref mut buf } = self;
-		lms.resize_with(channels, Default::default);
-		source.decode_lms(lms)?;
+		self.lms.resize_with(channels, Default::default);
+		source.decode_lms(&mut self.lms)?;
+
+		let slices = min((samples + SLICE_LEN - 1) / SLICE_LEN, 256);
 
-		let slices = min(samples / SLICE_LEN, 256);
+		for slice in 0..slices {
+			let len = min(SLICE_LEN, samples - slice * SLICE_LEN);
+			self.decode_slices(&mut source, sink, len, channels)?;
+		}
 
-		for _ in 0..slices {
-			for chn in 0..channels {
-				let ref mut lms = lms[chn];
-				let mut slice = source.read_long()?;
-				let len = min(SLICE_LEN, samples);
-				let sf = ((slice >> 60) & 0xF) as usize;
+		Ok(frame_byte_size(channels, slices) - 8)
+	}
 
-				for si in 0..len {
-					let qr = ((slice >> 57) & 0x7) as usize;
-					slice <<= 3;
-					let dq = DEQUANT_TABLE[sf][qr];
-					let pr = lms.predict();
-					let re = (pr + dq).clamp(-32768, 32767) as i16;
+	/// Decodes one slice of `len` samples for each channel, with all channels in
+	/// parallel lanes.
+	#[cfg(all(feature = "std", feature = "simd"))]
+	fn decode_slices(
+		&mut self,
+		source: &mut &[u8],
+		sink: &mut impl Output,
+		len: usize,
+		channels: usize,
+	) -> Result {
+		let Self { ref mut lms, ref mut buf, ref mut slices, ref mut planes, .. } = self;
 
-					buf[si] = re;
+		slices.clear();
+		for _ in 0..channels {
+			slices.push(source.read_long()?);
+		}
+
+		planes.resize(channels, [0; SLICE_LEN]);
+		crate::simd::decode_slice_simd(slices, lms, len, planes);
+
+		buf.resize(len * channels, 0);
+		for (chn, plane) in planes.iter().enumerate() {
+			for si in 0..len {
+				buf[si * channels + chn] = plane[si];
+			}
+		}
 
-					lms.update(re, dq);
+		sink.push(&buf[..len * channels])
+	}
+
+	/// Decodes one slice of `len` samples for each channel, interleaving them.
+	#[cfg(not(all(feature = "std", feature = "simd")))]
+	fn decode_slices(
+		&mut self,
+		source: &mut &[u8],
+		sink: &mut impl Output,
+		len: usize,
+		channels: usize,
+	) -> Result {
+		let Self { ref mut lms, ref mut buf, .. } = self;
+		buf.resize(len * channels, 0);
+
+		for chn in 0..channels {
+			let ref mut lms = lms[chn];
+			let mut slice = source.read_long()?;
+			let sf = ((slice >> 60) & 0xF) as usize;
+
+			for si in 0..len {
+				let qr = ((slice >> 57) & 0x7) as usize;
+				slice <<= 3;
+				let dq = DEQUANT_TABLE[sf][qr];
+				let pr = lms.predict();
+				let re = crate::reconstruct(pr, dq);
+
+				buf[si * channels + chn] = re;
+
+				lms.update(re, dq);
+			}
+		}
+
+		sink.push(&buf[..len * channels])
+	}
+}
+
+/// A lazy iterator over the interleaved samples of a stream, created with
+/// [`Decoder::into_iter_over`]. Iteration ends after the first error.
+#[derive(Clone, Debug)]
+pub struct Samples<'a> {
+	decoder: Decoder,
+	source: &'a [u8],
+	/// Samples decoded from the current frame.
+	buf: Vec<i16>,
+	/// The position of the next sample in `buf`.
+	pos: usize,
+	/// The number of samples decoded into `buf`.
+	len: usize,
+	done: bool,
+}
+
+impl Samples<'_> {
+	/// Returns the decoder, for its byte offset or trailers.
+	pub fn decoder(&self) -> &Decoder { &self.decoder }
+
+	/// Decodes up to a frame of samples into the buffer, returning `false` at the
+	/// end of the stream.
+	fn refill(&mut self) -> Result<bool> {
+		if self.buf.is_empty() {
+			// A frame of the first channel count always holds a slice of up to 255
+			// channels, should it change. The decoder reports an invalid header.
+			let channels = QoaHeader::try_from(self.source).map_or(1, |header| header.channel_count);
+			self.buf.resize(FRAME_LEN * max(channels as usize, 1), 0);
+		}
+
+		self.len = self.decoder.resume_into(self.source, &mut self.buf)?;
+		self.pos = 0;
+		Ok(self.len > 0)
+	}
+}
+
+impl Iterator for Samples<'_> {
+	type Item = Result<i16>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.pos == self.len {
+			if self.done { return None }
+
+			match self.refill() {
+				Ok(true) => { }
+				Ok(false) => {
+					self.done = true;
+					return None
+				}
+				Err(err) => {
+					self.done = true;
+					return Some(Err(err))
 				}
+			}
+		}
+
+		let sample = self.buf[self.pos];
+		self.pos += 1;
+		Some(Ok(sample))
+	}
+}
 
-				sink.extend_from_slice(&buf[..len]);
+impl FusedIterator for Samples<'_> { }
+
+/// Stream information parsed from the file header and first frame header of a QOA
+/// stream, without decoding.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct QoaHeader {
+	/// The number of samples per channel, or `0` in streaming mode.
+	pub sample_count: u32,
+	/// The sample rate of the first frame.
+	pub sample_rate: u32,
+	/// The channel count of the first frame.
+	pub channel_count: u8,
+	/// The number of frames, assuming all but the last are as long as the first,
+	/// or `0` in streaming mode.
+	pub frame_count: u32,
+}
+
+impl TryFrom<&[u8]> for QoaHeader {
+	type Error = Error;
+
+	/// Parses the first 16 bytes of a QOA stream.
+	///
+	/// # Errors
+	///
+	/// [`Error::UnknownMagic`]: the stream doesn't start with the QOA magic bytes.
+	///
+	/// [`Error::Eos`]: the stream is shorter than 16 bytes.
+	///
+	/// [`Error::InvalidFrameHeader`]: the first frame has a sample rate or channel
+	/// count of `0`.
+	fn try_from(mut value: &[u8]) -> Result<Self> {
+		let sample_count = value.decode_file_header()?;
+		let (channels, sample_rate, frame_len, _) = value.decode_frame_header()?;
+		let frame_count = if frame_len == 0 {
+			0
+		} else {
+			(sample_count as usize + frame_len - 1) / frame_len
+		};
+
+		Ok(Self {
+			sample_count,
+			sample_rate,
+			channel_count: channels as u8,
+			frame_count: frame_count as u32,
+		})
+	}
+}
+
+/// The mode of a QOA stream, given by the sample count in its file header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StreamMode {
+	/// A stream of a known number of samples per channel.
+	Fixed(u32),
+	/// A stream of unknown length, ending with the last frame.
+	Streaming,
+}
+
+/// Parses the file header of `source`, returning the stream mode.
+///
+/// # Errors
+///
+/// [`Error::UnknownMagic`]: the stream doesn't start with the QOA magic bytes.
+///
+/// [`Error::Eos`]: `source` is shorter than the file header.
+pub fn probe_mode(mut source: &[u8]) -> Result<StreamMode> {
+	Ok(match source.decode_file_header()? {
+		0 => StreamMode::Streaming,
+		samples => StreamMode::Fixed(samples)
+	})
+}
+
+/// Concatenates QOA `sources` into `sink` without decoding their slices, then
+/// returns the total number of samples per channel. Frames are validated and
+/// copied verbatim; only the file header and, if `sample_rate` is set, the frame
+/// sample rates are rewritten. All sources must share a sample rate and channel
+/// count.
+pub fn transcode(sources: &[&[u8]], sink: &mut Vec<u8>, sample_rate: Option<u32>) -> Result<usize> {
+	match sample_rate {
+		Some(0) => return Err(Descriptor(DescriptorError::ZeroRate)),
+		Some(rate @ 16777216..) => return Err(Descriptor(DescriptorError::UnsupportedRate(rate))),
+		_ => { }
+	}
+
+	let header = sink.len();
+	sink.extend_from_slice(&[0; 8]);
+
+	let mut total = 0;
+	let mut desc = None;
+	for mut source in sources.iter().copied() {
+		let mut sample_count = source.decode_file_header()? as usize;
+		let streaming_mode = sample_count == 0;
+
+		while sample_count > 0 || (streaming_mode && !source.is_empty()) {
+			let frame = source;
+			let (chan, rate, samples, size) = source.decode_frame_header()?;
+
+			if *desc.get_or_insert((rate, chan)) != (rate, chan) {
+				return Err(DescriptorChange(rate, chan))
+			}
+
+			let expected = frame_size(chan, samples);
+			if size != expected {
+				return Err(FrameSize(size, expected))
 			}
+
+			if frame.len() < size {
+				return Err(Eos)
+			}
+
+			let start = sink.len();
+			sink.extend_from_slice(&frame[..size]);
+
+			if let Some(rate) = sample_rate {
+				sink[start + 1..start + 4].copy_from_slice(&rate.to_be_bytes()[1..]);
+			}
+
+			source = &frame[size..];
+			total += samples;
+			sample_count = sample_count.saturating_sub(samples);
 		}
+	}
 
-		Ok(8 * (channels * 2 + slices))
+	if total > u32::MAX as usize {
+		return Err(Descriptor(DescriptorError::TooManySamples(total)))
 	}
+
+	let value = (MAGIC as u64) << 32 | total as u64;
+	sink[header..header + 8].copy_from_slice(&value.to_be_bytes());
+	Ok(total)
 }
 
-trait Source: Read {
-	fn read_long(&mut self) -> Result<u64> {
-		let mut bytes = [0; 8];
+/// Relabels the QOA stream in `source` with `sample_rate` without resampling,
+/// rewriting the sample rate of every frame header in place, then returns the
+/// number of frames rewritten. Checksum trailers are updated to match. The stream
+/// is only modified if all its frames are valid.
+///
+/// # Errors
+///
+/// [`Error::Descriptor`]: `sample_rate` is `0` or `2^24` or greater.
+///
+/// [`Error::FrameSize`]: a frame size doesn't match its sample and channel count.
+///
+/// [`Error::Eos`]: a frame is cut short.
+///
+/// Otherwise, any error parsing the file header or a frame header.
+pub fn set_sample_rate(source: &mut [u8], sample_rate: u32) -> Result<usize> {
+	StreamDescriptor { sample_rate: Some(sample_rate), ..StreamDescriptor::default() }
+		.validate()
+		.map_err(Descriptor)?;
+
+	let mut input = &source[..];
+	let mut sample_count = input.decode_file_header()? as usize;
+	let streaming_mode = sample_count == 0;
+
+	let mut frames = Vec::new();
+	let mut offset = 8;
+	while sample_count > 0 || (streaming_mode && !input.is_empty()) {
+		if streaming_mode && is_trailer(input) { break }
+
+		let (chan, _, samples, size) = input.decode_frame_header()?;
+		let expected = frame_size(chan, samples);
+		if size != expected {
+			return Err(FrameSize(size, expected))
+		}
 
-		// The only error returned from the read_exact implementation on slices is
-		// Eof, so this is fine.
-		self.read_exact(&mut bytes).map_err(|_| Eos)?;
+		if source.len() < offset + size {
+			return Err(Eos)
+		}
+
+		frames.push((offset, size));
+		offset += size;
+		input = &source[offset..];
+		sample_count = sample_count.saturating_sub(samples);
+	}
 
-		Ok(u64::from_be_bytes(bytes))
+	for &(start, _) in &frames {
+		source[start + 1..start + 4].copy_from_slice(&sample_rate.to_be_bytes()[1..]);
 	}
 
+	// Recompute the checksums of rewritten frames.
+	while let Some(header) = source.get(offset..offset + 8) {
+		let header = u64::from_be_bytes(header.try_into().unwrap());
+		let size = header as u32 as usize;
+		let start = offset + 8;
+		let Some(end) = start.checked_add((size + 7) & !7).filter(|&end| end <= source.len()) else { break };
+
+		match (header >> 32) as u32 {
+			CHECKSUM_MAGIC if size % 4 == 0 => {
+				for (index, &(frame, len)) in frames.iter().enumerate().take(size / 4) {
+					let checksum = Crc32::checksum(&source[frame..frame + len]);
+					let at = start + index * 4;
+					source[at..at + 4].copy_from_slice(&checksum.to_be_bytes());
+				}
+			}
+			LOOP_MAGIC | META_MAGIC => { }
+			_ => break
+		}
+
+		offset = end;
+	}
+
+	Ok(frames.len())
+}
+
+/// Returns `true` if `source` starts with trailer magic bytes.
+fn is_trailer(source: &[u8]) -> bool {
+	matches!(
+		source.first_chunk().map(|&magic| u32::from_be_bytes(magic)),
+		Some(LOOP_MAGIC | CHECKSUM_MAGIC | META_MAGIC)
+	)
+}
+
+/// Parses the key-value tags of a metadata trailer payload, returning `None` if
+/// it's malformed.
+fn parse_metadata(mut payload: &[u8]) -> Option<Vec<(String, String)>> {
+	fn read_str(payload: &mut &[u8]) -> Option<String> {
+		let len = payload.get(..4)?;
+		let len = u32::from_be_bytes(len.try_into().ok()?) as usize;
+		let str = payload.get(4..4usize.checked_add(len)?)?.to_vec();
+		*payload = &payload[4 + len..];
+		String::from_utf8(str).ok()
+	}
+
+	let mut tags = Vec::new();
+	while !payload.is_empty() {
+		tags.push((read_str(&mut payload)?, read_str(&mut payload)?));
+	}
+	Some(tags)
+}
+
+/// Returns the size in bytes of a frame with `channels` and `samples` per channel,
+/// including its header.
+fn frame_size(channels: usize, samples: usize) -> usize {
+	frame_byte_size(channels, (samples + SLICE_LEN - 1) / SLICE_LEN)
+}
+
+/// An output buffer of decoded samples.
+trait Output {
+	fn push(&mut self, samples: &[i16]) -> Result;
+}
+
+impl Output for Vec<i16> {
+	fn push(&mut self, samples: &[i16]) -> Result {
+		self.extend_from_slice(samples);
+		Ok(())
+	}
+}
+
+/// A fixed-capacity output writing into a borrowed slice.
+struct SliceOutput<'a> {
+	buf: &'a mut [i16],
+	len: usize,
+}
+
+impl Output for SliceOutput<'_> {
+	fn push(&mut self, samples: &[i16]) -> Result {
+		let end = self.len + samples.len();
+		self.buf
+			.get_mut(self.len..end)
+			.ok_or(SinkFull)?
+			.copy_from_slice(samples);
+		self.len = end;
+		Ok(())
+	}
+}
+
+trait Source {
+	fn read_long(&mut self) -> Result<u64>;
+
 	fn read_longs<const N: usize>(&mut self) -> Result<[u64; N]> {
 		let mut longs = [0; N];
 		for long in &mut longs {
@@ -153,7 +1014,19 @@ trait Source: Read {
 		Ok(value as u32)
 	}
 
+	/// Reads a frame header, rejecting a channel count or sample rate of `0`.
 	fn decode_frame_header(&mut self) -> Result<(usize, u32, usize, usize)> {
+		let (channels, rate, samples, size) = self.read_frame_header()?;
+
+		if channels == 0 || rate == 0 {
+			return Err(InvalidFrameHeader(channels, rate))
+		}
+
+		Ok((channels, rate, samples, size))
+	}
+
+	/// Reads a frame header without validation.
+	fn read_frame_header(&mut self) -> Result<(usize, u32, usize, usize)> {
 		let value    = self.read_long()?;
 		let channels = (value >> 56) as u8  as usize;
 		let rate     = (value >> 32) as u32 & 0xFFFFFF;
@@ -168,47 +1041,421 @@ trait Source: Read {
 	}
 }
 
-impl Source for &[u8] { }
+impl Source for &[u8] {
+	fn read_long(&mut self) -> Result<u64> {
+		let (bytes, rest) = self.split_first_chunk().ok_or(Eos)?;
+		*self = rest;
+		Ok(u64::from_be_bytes(*bytes))
+	}
+}
 
 #[derive(Copy, Clone, Debug, Default)]
-struct LmsState {
-	history: [i16; 4],
-	weights: [i16; 4]
+pub(crate) struct LmsState {
+	pub(crate) history: [i32; 4],
+	pub(crate) weights: [i32; 4]
 }
 
 impl LmsState {
 	fn unpack(&mut self, [mut history, mut weights]: [u64; 2]) {
 		self.history.fill_with(|| {
-			let val = (history >> 48) as i16;
+			let val = (history >> 48) as i16 as i32;
 			history <<= 16;
 			val
 		});
 		self.weights.fill_with(|| {
-			let val = (weights >> 48) as i16;
+			let val = (weights >> 48) as i16 as i32;
 			weights <<= 16;
 			val
 		})
 	}
 
-	fn predict(&self) -> i32 {
-		let history = self.history.into_iter().map(|h| h as i32);
-		let weights = self.weights.into_iter().map(|w| w as i32);
-		history.zip(weights).mul().sum::<i32>() >> 13
+	#[cfg(any(test, not(all(feature = "std", feature = "simd"))))]
+	pub(crate) fn predict(&self) -> i32 {
+		let history = self.history.into_iter();
+		let weights = self.weights.into_iter();
+		history.zip(weights)
+			   .map(|(h, w)| h.wrapping_mul(w))
+			   .fold(0i32, i32::wrapping_add) >> 13
 	}
 
-	fn update(&mut self, sample: i16, residual: i32) {
-		debug_assert_matches!(
-			residual >> 4,
-			r @ -32768..=32767,
-			"residual larger than expected"
-		);
-		let delta = (residual >> 4) as i16;
+	#[cfg(any(test, not(all(feature = "std", feature = "simd"))))]
+	pub(crate) fn update(&mut self, sample: i16, residual: i32) {
+		let delta = residual >> 4;
 
 		for i in 0..4 {
-			self.weights[i] = if self.history[i] < 0 { -delta } else { delta };
+			self.weights[i] = self.weights[i].wrapping_add(
+				if self.history[i] < 0 { -delta } else { delta }
+			);
 		}
 
 		self.history.copy_within(1..4, 0);
-		self.history[3] = sample;
+		self.history[3] = sample as i32;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use alloc::vec::Vec;
+	use quickcheck_macros::quickcheck;
+	use quickcheck::TestResult;
+	use qoa_ref_sys::QoaDesc;
+	use crate::{Encoder, FRAME_LEN, MAGIC, PcmBuffer, SLICE_LEN, StreamDescriptor};
+	use crate::io::Buffer;
+	use crate::reference::{encode_ref, encode_ref_at};
+	use super::{Decoder, Error, frame_size, probe_mode, QoaHeader, set_sample_rate, StreamMode, transcode};
+
+	fn decode(data: &[u8]) -> Vec<i16> {
+		let mut buf = Vec::new();
+		Decoder::default().decode(data, &mut buf).unwrap();
+		buf
+	}
+
+	#[test]
+	fn iter_over() {
+		const FRAME_BYTES: usize = 8 + 16 + 256 * 8;
+
+		let samples: Vec<i16> = (0..FRAME_LEN * 4).map(|i| (i * 23 % 6000) as i16).collect();
+		let data = encode_ref(&samples, 1);
+		let expected = decode(&data);
+
+		let mut iter = Decoder::default().into_iter_over(&data);
+		let head: Vec<i16> = iter.by_ref()
+								 .take(FRAME_LEN + 10)
+								 .collect::<Result<_, _>>()
+								 .unwrap();
+		assert_eq!(head, expected[..FRAME_LEN + 10]);
+		// Only the first two frames were decoded.
+		assert!(iter.decoder().offset() < 8 + FRAME_BYTES * 3, "decoded past the second frame");
+
+		let tail: Vec<i16> = iter.collect::<Result<_, _>>().unwrap();
+		assert_eq!(tail, expected[FRAME_LEN + 10..]);
+	}
+
+	#[test]
+	fn iter_over_error() {
+		let mut iter = Decoder::default().into_iter_over(b"qoaX\0\0\0\0");
+		assert!(matches!(iter.next(), Some(Err(Error::UnknownMagic(_)))));
+		assert!(iter.next().is_none());
+	}
+
+	#[test]
+	fn transcode_concat() {
+		let a: Vec<i16> = (0..FRAME_LEN as i32 + 400).map(|i| ((i * 37) % 2000 - 1000) as i16).collect();
+		let b: Vec<i16> = (0..FRAME_LEN as i32 * 2).map(|i| ((i * 91) % 6000 - 3000) as i16).collect();
+		let a = encode_ref(&a, 1);
+		let b = encode_ref(&b, 1);
+
+		let mut joined = Vec::new();
+		let samples = transcode(&[&*a, &*b], &mut joined, None).unwrap();
+		assert_eq!(samples, FRAME_LEN * 3 + 400);
+		assert_eq!(joined.len(), a.len() + b.len() - 8);
+
+		let mut expected = decode(&a);
+		expected.extend(decode(&b));
+		assert_eq!(decode(&joined), expected);
+	}
+
+	#[test]
+	fn transcode_rate() {
+		let data = encode_ref(&[0; 400], 1);
+		let mut sink = Vec::new();
+		transcode(&[&*data], &mut sink, Some(8000)).unwrap();
+		assert_eq!(&sink[9..12], &8000u32.to_be_bytes()[1..]);
+		assert_eq!(&sink[12..], &data[12..]);
+	}
+
+	#[test]
+	fn relabel_rate() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 * 2 + 300).map(|i| ((i * 29) % 5000 - 2500) as i16).collect();
+		let mut data = encode_ref(&samples, 1);
+		let expected = decode(&data);
+
+		assert_eq!(set_sample_rate(&mut data, 48000).unwrap(), 3);
+		let mut actual = Vec::new();
+		let (_, desc) = Decoder::default().decode_with_descriptor(&data, &mut actual).unwrap();
+		assert_eq!(desc.rate(), Some(48000));
+		assert_eq!(actual, expected);
+
+		let copy = data.clone();
+		assert!(matches!(set_sample_rate(&mut data, 0), Err(Error::Descriptor(_))));
+		assert!(matches!(set_sample_rate(&mut data, 1 << 24), Err(Error::Descriptor(_))));
+		assert_eq!(data, copy);
+	}
+
+	#[test]
+	fn relabel_rate_checksums() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 + 300).map(|i| ((i * 43) % 3000 - 1500) as i16).collect();
+		for streaming in [false, true] {
+			let mut enc = if streaming {
+				Encoder::new_streaming(Buffer::default())
+			} else {
+				Encoder::new_fixed(samples.len(), 44100, 1, Buffer::default()).unwrap()
+			};
+			enc.set_checksums(true);
+			enc.encode_interleaved(&samples, StreamDescriptor::new(None, Some(44100), Some(1)).unwrap()).unwrap();
+			let mut data = enc.close().unwrap().unwrap().encode();
+
+			assert_eq!(set_sample_rate(&mut data, 22050).unwrap(), 2);
+			let mut decoder = Decoder::default();
+			decoder.verify_checksums(true);
+			let mut actual = Vec::new();
+			let (bytes, desc) = decoder.decode_with_descriptor(&data, &mut actual).unwrap();
+			assert_eq!(bytes, data.len());
+			assert_eq!(desc.rate(), Some(22050));
+			assert_eq!(actual.len(), samples.len());
+		}
+	}
+
+	#[test]
+	fn decode_lossy() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 * 3).map(|i| ((i * 53) % 4000 - 2000) as i16).collect();
+		let mut data = encode_ref(&samples, 1);
+		let clean = decode(&data);
+
+		// Corrupt the channel count of the second frame.
+		let second = 8 + frame_size(1, FRAME_LEN);
+		data[second] = 0xFF;
+
+		let mut lossy = Vec::new();
+		let skipped = Decoder::default().decode_lossy(&data, &mut lossy).unwrap();
+		assert_eq!(skipped, 1);
+		assert_eq!(lossy.len(), clean.len());
+		assert_eq!(lossy[..FRAME_LEN], clean[..FRAME_LEN]);
+		assert!(lossy[FRAME_LEN..FRAME_LEN * 2].iter().all(|&s| s == 0));
+		assert_eq!(lossy[FRAME_LEN * 2..], clean[FRAME_LEN * 2..]);
+	}
+
+	#[test]
+	fn decode_lossy_first_frame() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 * 3).map(|i| ((i * 53) % 4000 - 2000) as i16).collect();
+		let mut data = encode_ref(&samples, 1);
+		let clean = decode(&data);
+
+		// Zero the channel count of the first frame, before the format is known.
+		data[8] = 0;
+
+		let mut lossy = Vec::new();
+		let skipped = Decoder::default().decode_lossy(&data, &mut lossy).unwrap();
+		assert_eq!(skipped, 1);
+		assert_eq!(lossy.len(), clean.len());
+		assert!(lossy[..FRAME_LEN].iter().all(|&s| s == 0));
+		assert_eq!(lossy[FRAME_LEN..], clean[FRAME_LEN..]);
+	}
+
+	#[test]
+	fn decode_lossy_truncated() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 * 2).map(|i| ((i * 53) % 4000 - 2000) as i16).collect();
+		let data = encode_ref(&samples, 1);
+		let clean = decode(&data);
+
+		let mut lossy = Vec::new();
+		let skipped = Decoder::default().decode_lossy(&data[..data.len() - 100], &mut lossy).unwrap();
+		assert_eq!(skipped, 1);
+		assert_eq!(lossy.len(), clean.len());
+		assert_eq!(lossy[..FRAME_LEN], clean[..FRAME_LEN]);
+		assert!(lossy[FRAME_LEN..].iter().all(|&s| s == 0));
+	}
+
+	#[test]
+	fn decode_lossy_trailers() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 * 2 + 300).map(|i| ((i * 53) % 4000 - 2000) as i16).collect();
+		for streaming in [false, true] {
+			let mut enc = if streaming {
+				Encoder::new_streaming(Buffer::default())
+			} else {
+				Encoder::new_fixed(samples.len(), 44100, 1, Buffer::default()).unwrap()
+			};
+			enc.set_loop_points(100, 2000).unwrap();
+			enc.set_checksums(true);
+			enc.encode_interleaved(&samples, StreamDescriptor::new(None, Some(44100), Some(1)).unwrap()).unwrap();
+			let data = enc.close().unwrap().unwrap().encode();
+			let clean = decode(&data);
+
+			let mut decoder = Decoder::default();
+			let mut lossy = Vec::new();
+			assert_eq!(decoder.decode_lossy(&data, &mut lossy).unwrap(), 0, "streaming: {streaming}");
+			assert_eq!(lossy, clean);
+			assert_eq!(decoder.loop_points(), Some((100, 2000)));
+		}
+	}
+
+	#[test]
+	fn decode_at() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 + 100).map(|i| ((i * 53) % 4000 - 2000) as i16).collect();
+		let data = encode_ref(&samples, 1);
+
+		let mut container = [0xAA; 37].to_vec();
+		container.extend_from_slice(&data);
+		container.extend_from_slice(&[0x55; 19]);
+
+		let mut buf = Vec::new();
+		let bytes = Decoder::default()
+			.decode_at(&container, 37, data.len(), &mut buf)
+			.unwrap();
+		assert_eq!(bytes, data.len());
+		assert_eq!(buf, decode(&data));
+
+		assert!(matches!(
+			Decoder::default().decode_at(&container, 37, container.len(), &mut Vec::new()),
+			Err(Error::Eos)
+		));
+	}
+
+	#[test]
+	fn hostile_sample_count() {
+		const SAMPLES: u64 = 4_000_000_000;
+
+		let mut data = Vec::new();
+		data.extend_from_slice(&((MAGIC as u64) << 32 | SAMPLES).to_be_bytes());
+		let header = 2u64 << 56 | 44100u64 << 32 | (FRAME_LEN as u64) << 16 | frame_size(2, FRAME_LEN) as u64;
+		data.extend_from_slice(&header.to_be_bytes());
+		data.resize(data.len() + 48, 0);
+
+		assert_eq!(Decoder::estimate_output_len(&data).unwrap(), SAMPLES as usize * 2);
+
+		let mut buf = Vec::new();
+		assert!(matches!(Decoder::default().decode(&data, &mut buf), Err(Error::Eos)));
+		assert!(buf.capacity() <= data.len() / 8 * SLICE_LEN);
+	}
+
+	#[test]
+	fn decode_descriptor() {
+		let data = encode_ref(&[0; 400], 1);
+		let (bytes, desc) = Decoder::default()
+			.decode_with_descriptor(&data, &mut Vec::new())
+			.unwrap();
+		assert_eq!(bytes, data.len());
+		assert_eq!(desc.samples(), Some(400));
+		assert_eq!(desc.rate(), Some(44100));
+		assert_eq!(desc.channels(), Some(1));
+	}
+
+	#[test]
+	fn decode_into_chunks() {
+		let samples: Vec<i16> = (0..FRAME_LEN as i32 * 2 + 333).map(|i| ((i * 71) % 5000 - 2500) as i16).collect();
+		let data = encode_ref(&samples, 1);
+		let expected = decode(&data);
+
+		let mut decoder = Decoder::default();
+		let mut chunk = [0; 4096];
+		let mut actual = Vec::new();
+		let mut n = decoder.decode_into(&data, &mut chunk).unwrap();
+		while n > 0 {
+			actual.extend_from_slice(&chunk[..n]);
+			n = decoder.resume_into(&data, &mut chunk).unwrap();
+		}
+
+		assert_eq!(decoder.offset(), data.len());
+		assert_eq!(actual, expected);
+	}
+
+	#[quickcheck]
+	fn decode_channels_parity(samples: Vec<i16>, channels: u8) -> TestResult {
+		let channels = [1, 2, 4, 6, 8][channels as usize % 5];
+		let len = samples.len() / channels;
+		if len == 0 { return TestResult::discard() }
+
+		let data = encode_ref(&samples[..len * channels], channels);
+		let expected = qoa_ref_sys::decode(&data, &mut QoaDesc::default()).unwrap().into_vec();
+		TestResult::from_bool(decode(&data) == expected)
+	}
+
+	#[test]
+	fn reset() {
+		let mono: Vec<i16> = (0..FRAME_LEN as i32 + 200).map(|i| ((i * 29) % 3000 - 1500) as i16).collect();
+		let stereo: Vec<i16> = (0..800).map(|i| ((i * 43) % 7000 - 3500) as i16).collect();
+		let mono = encode_ref(&mono, 1);
+		let stereo = encode_ref(&stereo, 2);
+
+		let mut decoder = Decoder::default();
+		let mut actual = Vec::new();
+		decoder.decode(&mono, &mut actual).unwrap();
+		assert_eq!(actual, decode(&mono));
+
+		decoder.reset();
+		actual.clear();
+		decoder.decode(&stereo, &mut actual).unwrap();
+		assert_eq!(actual, decode(&stereo));
+	}
+
+	#[test]
+	fn probe_stream_mode() {
+		let fixed = encode_ref(&[0; 100], 1);
+		assert_eq!(probe_mode(&fixed).unwrap(), StreamMode::Fixed(100));
+
+		let mut streaming = fixed.to_vec();
+		streaming[4..8].fill(0);
+		assert_eq!(probe_mode(&streaming[..8]).unwrap(), StreamMode::Streaming);
+
+		assert!(matches!(probe_mode(b"riff0000"), Err(Error::UnknownMagic(..))));
+		assert!(matches!(probe_mode(&fixed[..4]), Err(Error::Eos)));
+	}
+
+	#[test]
+	fn header() {
+		let data = encode_ref(&[0; FRAME_LEN * 2 + 1], 1);
+		assert_eq!(
+			QoaHeader::try_from(&data[..16]).unwrap(),
+			QoaHeader {
+				sample_count: FRAME_LEN as u32 * 2 + 1,
+				sample_rate: 44100,
+				channel_count: 1,
+				frame_count: 3,
+			}
+		);
+
+		let mut bad = data[..16].to_vec();
+		bad[..4].copy_from_slice(b"riff");
+		assert!(matches!(QoaHeader::try_from(&*bad), Err(Error::UnknownMagic(magic)) if magic == *b"riff"));
+		assert!(matches!(QoaHeader::try_from(&data[..12]), Err(Error::Eos)));
+	}
+
+	#[test]
+	fn decode_variable() {
+		let mono: Vec<i16> = (0..400).map(|i| ((i * 37) % 2000 - 1000) as i16).collect();
+		let stereo: Vec<i16> = (0..800).map(|i| ((i * 91) % 6000 - 3000) as i16).collect();
+		let mono = encode_ref(&mono, 1);
+		let stereo = encode_ref_at(&stereo, 2, 22050);
+
+		// Join the frames of both streams under one file header.
+		let mut data = Vec::new();
+		data.extend_from_slice(b"qoaf");
+		data.extend_from_slice(&800u32.to_be_bytes());
+		data.extend_from_slice(&mono[8..]);
+		data.extend_from_slice(&stereo[8..]);
+
+		let mut strict = Vec::new();
+		assert!(Decoder::default().decode(&data, &mut strict).is_err());
+
+		let mut sink = PcmBuffer::default();
+		let bytes = Decoder::default().decode_variable(&data, &mut sink).unwrap();
+		assert_eq!(bytes, data.len());
+
+		let frames = sink.unwrap();
+		assert_eq!(frames.len(), 2);
+		assert_eq!((frames[0].rate(), frames[0].channels()), (44100, 1));
+		assert_eq!((frames[1].rate(), frames[1].channels()), (22050, 2));
+		assert_eq!(frames[0].data(), decode(&mono));
+		assert_eq!(frames[1].data(), decode(&stereo));
+	}
+
+	#[quickcheck]
+	fn decode_zero_header(channels: u8, rate: u32, samples: u16) -> bool {
+		let channels = channels % 2;
+		let rate = if rate % 2 == 0 { 0 } else { 44100 };
+		let header = (channels as u64) << 56 | (rate as u64) << 32 | (samples as u64) << 16;
+
+		let mut data = Vec::new();
+		data.extend_from_slice(b"qoaf");
+		data.extend_from_slice(&(samples as u32).to_be_bytes());
+		data.extend_from_slice(&header.to_be_bytes());
+		data.resize(data.len() + 64, 0);
+
+		let result = Decoder::default().decode(&data, &mut Vec::new());
+		if channels == 0 || rate == 0 {
+			matches!(result, Err(Error::InvalidFrameHeader(..)))
+		} else {
+			true
+		}
 	}
 }
@@ -0,0 +1,126 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fuzz tests feeding arbitrary and mutated bytes into both decoders, which
+//! must return errors rather than panic. Enabled by the `fuzz` feature. The seed
+//! corpus is built from reference-encoded streams, plus the test samples' `.qoa`
+//! files if they were downloaded by the integration tests.
+
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use quickcheck::{Arbitrary, Gen};
+use quickcheck_macros::quickcheck;
+use crate::{byte_decoder, FRAME_LEN, MAGIC, PcmBuffer};
+use crate::io::Buffer;
+use crate::reference::encode_ref;
+use super::Decoder;
+
+const SAMPLE_DIR: &str = "run/qoa_test_samples_2023_02_18";
+/// The maximum number of bytes kept from each sample file.
+const SEED_LEN: usize = 64 * 1024;
+
+/// Decodes `data` with both decoders, discarding the results.
+fn decode(data: &[u8]) {
+	let _ = byte_decoder::Decoder::default().decode(data, &mut Vec::new());
+	let mut source = Buffer::decode(&mut data.to_vec());
+	let _ = Decoder::new(PcmBuffer::default()).decode(&mut source);
+}
+
+fn seeds() -> &'static [Vec<u8>] {
+	static SEEDS: OnceLock<Vec<Vec<u8>>> = OnceLock::new();
+	SEEDS.get_or_init(|| {
+		let mut seeds = Vec::new();
+		for channels in [1, 2, 6] {
+			let len = FRAME_LEN + 300;
+			let samples: Vec<i16> = (0..len * channels)
+				.map(|i| ((i * 37) % 8000) as i16 - 4000)
+				.collect();
+			seeds.push(encode_ref(&samples, channels));
+		}
+
+		collect_samples(Path::new(SAMPLE_DIR), &mut seeds);
+		seeds
+	})
+}
+
+fn collect_samples(dir: &Path, seeds: &mut Vec<Vec<u8>>) {
+	let Ok(entries) = fs::read_dir(dir) else { return };
+	for path in entries.flatten().map(|entry| entry.path()) {
+		if path.is_dir() {
+			collect_samples(&path, seeds);
+		} else if path.extension().is_some_and(|ext| ext == "qoa") {
+			if let Ok(mut data) = fs::read(path) {
+				data.truncate(SEED_LEN);
+				seeds.push(data);
+			}
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+enum Mutation {
+	Flip(usize, u8),
+	Set(usize, u8),
+	Insert(usize, u8),
+	Remove(usize),
+	Truncate(usize),
+}
+
+impl Arbitrary for Mutation {
+	fn arbitrary(g: &mut Gen) -> Self {
+		let index = usize::arbitrary(g);
+		match u8::arbitrary(g) % 5 {
+			0 => Self::Flip(index, 1 << (u8::arbitrary(g) % 8)),
+			1 => Self::Set(index, u8::arbitrary(g)),
+			2 => Self::Insert(index, u8::arbitrary(g)),
+			3 => Self::Remove(index),
+			_ => Self::Truncate(index),
+		}
+	}
+}
+
+impl Mutation {
+	fn apply(&self, data: &mut Vec<u8>) {
+		if data.is_empty() { return }
+
+		let len = data.len();
+		match *self {
+			Self::Flip  (i, bit) => data[i % len] ^= bit,
+			Self::Set   (i, val) => data[i % len]  = val,
+			Self::Insert(i, val) => data.insert(i % len, val),
+			Self::Remove(i)      => { data.remove(i % len); }
+			Self::Truncate(i)    => data.truncate(i % len),
+		}
+	}
+}
+
+#[quickcheck]
+fn arbitrary_bytes(data: Vec<u8>) {
+	decode(&data);
+
+	let mut prefixed = MAGIC.to_be_bytes().to_vec();
+	prefixed.extend(data);
+	decode(&prefixed);
+}
+
+#[quickcheck]
+fn mutated_seeds(seed: usize, mutations: Vec<Mutation>) {
+	let seeds = seeds();
+	let mut data = seeds[seed % seeds.len()].clone();
+	for mutation in &mutations {
+		mutation.apply(&mut data);
+	}
+	decode(&data);
+}
@@ -13,14 +13,19 @@
 // limitations under the License.
 
 mod slice_scaler;
-use slice_scaler::{LinearScaler, VectorScaler};
+use slice_scaler::Dither;
+pub use slice_scaler::LinearScaler;
+#[cfg(feature = "simd")]
+pub use slice_scaler::VectorScaler;
 
 use std::cmp::min;
-use std::result;
+use std::{mem, result};
 use std::error::Error;
+use std::io::Seek;
 use amplify_derive::Display;
-use crate::{DescriptorError, FRAME_LEN, MAGIC, PcmBuffer, PcmSink, PcmSource, QoaLmsState, SLICE_LEN, StreamDescriptor};
-use crate::io::{SinkStream, WriteError};
+use crate::{CHECKSUM_MAGIC, DEQUANT_TABLE, DescriptorError, frame_byte_size, LOOP_MAGIC, MAGIC, META_MAGIC, PcmFrame, PcmSource, QoaHeader, QoaLmsState, reconstruct, SLICE_LEN, StreamDescriptor};
+use crate::io::{SinkStream, WriteError, WriteResult};
+use crate::util::Crc32;
 use EncodeError::*;
 use WriteKind::*;
 
@@ -32,12 +37,18 @@ pub enum EncodeError {
 	InvalidDescriptor(DescriptorError),
 	#[display("stream descriptor cannot be set in a fixed encoder")]
 	InvalidDescriptorChange,
+	#[display("frames must have between 1 and 256 slices, but was {0}")]
+	InvalidFrameSlices(u16),
+	#[display("loop start {0} must be before the loop end {1}")]
+	InvalidLoopPoints(u32, u32),
 	#[display("could not read samples")]
 	SampleRead(Box<dyn Error>),
 	#[display("could not write {0} ({1})")]
 	Write(WriteKind, WriteError),
 	#[display("could not flush the sink ({0})")]
 	Flush(WriteError),
+	#[display("encoding has already begun")]
+	Started,
 	#[display("closed")]
 	Closed,
 }
@@ -52,6 +63,12 @@ pub enum WriteKind {
 	LmsState(&'static str),
 	#[display("slice data on channel {0}")]
 	SliceData(u8),
+	#[display("loop points")]
+	LoopPoints,
+	#[display("checksums")]
+	Checksums,
+	#[display("metadata")]
+	Metadata,
 }
 
 impl Error for EncodeError {
@@ -64,45 +81,18 @@ impl Error for EncodeError {
 	}
 }
 
-pub(crate) struct Frame {
-	slice_width: usize,
-	slice_count: u16,
-	slice_index: u16,
-	buffer: PcmBuffer,
-}
-
-impl Frame {
-	fn new(channel_count: usize) -> Self {
-		Self {
-			slice_width: SLICE_LEN * channel_count,
-			slice_count: 0,
-			slice_index: 0,
-			buffer: PcmBuffer::default(),
-		}
-	}
-
-	/// Returns `true` if the frame header has been written.
-	fn start(&mut self, samples: usize, channels: usize) -> bool {
-		if self.complete() {
-			self.buffer.clear();
-			let samples = min(FRAME_LEN, samples);
-			self.slice_width = SLICE_LEN * channels;
-			self.slice_count = ((samples + SLICE_LEN - 1) / SLICE_LEN) as u16;
-			self.slice_index = 0;
-			self.buffer.set_descriptor(SLICE_LEN as u32, channels).unwrap();
-			true
-		} else {
-			false
-		}
-	}
-
-	/// Returns `true` if all slices have been written.
-	fn complete(&self) -> bool { self.slice_index >= self.slice_count }
-
-	fn reset(&mut self) {
-		self.slice_count = 0;
-		self.slice_index = 0;
-	}
+/// Statistics of an encoded stream.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EncodeSummary {
+	/// The number of bytes written to the sink, including the file header and
+	/// trailers.
+	pub bytes_written: usize,
+	/// The number of frames written.
+	pub frames_written: usize,
+	/// The number of samples per channel encoded.
+	pub samples_encoded: usize,
+	/// The size of the encoded PCM16 samples divided by the bytes written.
+	pub compression_ratio: f64,
 }
 
 pub trait SliceScaler: slice_scaler::SliceScaler { }
@@ -112,12 +102,50 @@ impl<S: slice_scaler::SliceScaler> SliceScaler for S { }
 #[cfg(feature = "simd")]
 pub type SimdEncoder<S> = Encoder<S, VectorScaler>;
 
+/// A QOA encoder writing into a [`SinkStream`].
+///
+/// # Thread safety
+///
+/// The encoder is [`Send`] when the sink `S` is, so it can be moved to a worker
+/// thread; the scalers are stateless markers, and the progress callback must be
+/// [`Send`]. It isn't [`Sync`], as encoding needs exclusive access anyway.
 pub struct Encoder<S: SinkStream, Sc: SliceScaler = LinearScaler> {
 	desc: StreamDescriptor,
+	/// The sample rate and channel count set before encoding in streaming mode,
+	/// which sources must match.
+	pinned: StreamDescriptor,
 	sink: Option<S>,
 	has_header: bool,
 	lms_states: Vec<QoaLmsState>,
-	frame: Frame,
+	/// Interleaved samples waiting for a complete frame.
+	buffer: Vec<i16>,
+	/// The number of samples per channel written.
+	encoded: usize,
+	/// The sample count and byte length of the existing stream being appended to.
+	appended_to: Option<(usize, usize)>,
+	/// The number of slices per frame.
+	frame_slices: u16,
+	/// The loop start and end sample offsets written after the final frame.
+	loop_points: Option<(u32, u32)>,
+	/// The checksum of each frame written, if enabled.
+	checksums: Option<Vec<u32>>,
+	/// The dither noise source of each channel, if enabled.
+	dither: Option<Vec<Dither>>,
+	/// The reconstruction error of each channel, if enabled.
+	errors: Option<ChannelErrors>,
+	/// The LMS state of each channel at the start of the stream, if not default.
+	initial_lms: Option<Vec<QoaLmsState>>,
+	/// The key-value tags written after the final frame.
+	metadata: Vec<(String, String)>,
+	/// Whether declared sample counts must be covered by the samples given.
+	strict: bool,
+	/// The number of bytes written to the sink.
+	bytes_written: usize,
+	/// The number of frames written.
+	frames_written: usize,
+	/// The size of the PCM16 samples encoded, in bytes.
+	pcm_bytes: usize,
+	progress: Option<Box<dyn FnMut(usize, usize) + Send>>,
 	_scaler: Sc,
 }
 
@@ -131,6 +159,26 @@ impl<S: SinkStream> Encoder<S> {
 	}
 }
 
+impl<S: SinkStream + Seek> Encoder<S> {
+	/// Creates an encoder appending frames to the existing stream described by
+	/// `header`, with `sink` positioned at its end. The stream must not have
+	/// trailers. Its file header is rewritten with the new sample count on close,
+	/// unless it's in streaming mode.
+	///
+	/// Appended frames start from the default LMS state, unless set with
+	/// [`Self::set_initial_lms`].
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidDescriptor`]: the header has an invalid sample rate or
+	/// channel count.
+	///
+	/// [`EncodeError::Write`]: the sink position couldn't be read.
+	pub fn open_append(sink: S, header: QoaHeader) -> Result<Self> {
+		Self::_open_append(sink, header, LinearScaler)
+	}
+}
+
 #[cfg(feature = "simd")]
 impl<S: SinkStream> SimdEncoder<S> {
 	pub fn new_fixed_simd(sample_count: usize, sample_rate: u32, channel_count: usize, sink: S) -> Result<Self> {
@@ -148,6 +196,13 @@ impl<S: SinkStream> SimdEncoder<S> {
 	}
 }
 
+#[cfg(feature = "simd")]
+impl<S: SinkStream + Seek> SimdEncoder<S> {
+	pub fn open_append_simd(sink: S, header: QoaHeader) -> Result<Self> {
+		Self::_open_append(sink, header, VectorScaler)
+	}
+}
+
 impl<S: SinkStream, Sc: SliceScaler> Encoder<S, Sc> {
 	fn _new_fixed(sample_count: usize, sample_rate: u32, channel_count: usize, sink: S, scaler: Sc) -> Result<Self> {
 		Ok(Self {
@@ -156,10 +211,25 @@ impl<S: SinkStream, Sc: SliceScaler> Encoder<S, Sc> {
 				Some(sample_rate),
 				Some(channel_count)
 			).map_err(InvalidDescriptor)?,
+			pinned: StreamDescriptor::default(),
 			sink: Some(sink),
 			has_header: false,
 			lms_states: vec![QoaLmsState::default(); channel_count as usize],
-			frame: Frame::new(channel_count),
+			buffer: Vec::new(),
+			encoded: 0,
+			appended_to: None,
+			frame_slices: 256,
+			loop_points: None,
+			checksums: None,
+			dither: None,
+			errors: None,
+			initial_lms: None,
+			metadata: Vec::new(),
+			strict: false,
+			bytes_written: 0,
+			frames_written: 0,
+			pcm_bytes: 0,
+			progress: None,
 			_scaler: scaler,
 		})
 	}
@@ -167,118 +237,518 @@ impl<S: SinkStream, Sc: SliceScaler> Encoder<S, Sc> {
 	fn _new_streaming(sink: S, scaler: Sc) -> Self {
 		Self {
 			desc: StreamDescriptor::default(),
+			pinned: StreamDescriptor::default(),
 			sink: Some(sink),
 			has_header: false,
 			lms_states: Vec::new(),
-			frame: Frame::new(0),
+			buffer: Vec::new(),
+			encoded: 0,
+			appended_to: None,
+			frame_slices: 256,
+			loop_points: None,
+			checksums: None,
+			dither: None,
+			errors: None,
+			initial_lms: None,
+			metadata: Vec::new(),
+			strict: false,
+			bytes_written: 0,
+			frames_written: 0,
+			pcm_bytes: 0,
+			progress: None,
 			_scaler: scaler,
 		}
 	}
 
-	/// Encodes samples from a [`Vec`].
-	pub fn encode_vec(&mut self, source: &mut Vec<i16>, mut desc: StreamDescriptor) -> Result {
-		let Self { desc: this_desc, has_header, lms_states, frame, .. } = self;
-		desc.infer_from_vec(source, this_desc);
+	fn _open_append(mut sink: S, header: QoaHeader, scaler: Sc) -> Result<Self> where S: Seek {
+		let QoaHeader { sample_count, sample_rate, channel_count, .. } = header;
+		let desc = StreamDescriptor::new(
+			None,
+			Some(sample_rate),
+			Some(channel_count as usize)
+		).map_err(InvalidDescriptor)?;
+		let len = sink.stream_position()
+					  .map_err(|err| Write(FileHeader, err.into()))?;
+
+		let mut enc = Self::_new_streaming(sink, scaler);
+		enc.desc = desc;
+		enc.pinned = desc;
+		enc.has_header = true;
+		enc.lms_states = vec![QoaLmsState::default(); channel_count as usize];
+		enc.appended_to = Some((sample_count as usize, len as usize));
+		Ok(enc)
+	}
 
-		if this_desc.is_streaming() {
-			this_desc.sample_rate   = desc.sample_rate;
-			this_desc.channel_count = desc.channel_count;
+	/// Sets a callback invoked after each frame is written, with the number of
+	/// samples per channel encoded so far and the total sample count, or `0` in
+	/// streaming mode.
+	pub fn set_progress(&mut self, progress: impl FnMut(usize, usize) + Send + 'static) {
+		self.progress = Some(Box::new(progress));
+	}
+
+	/// Sets the number of slices per frame, at most 256. Shorter frames lower the
+	/// decode latency of a stream, at the cost of a larger size.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidFrameSlices`]: `slices` is `0` or greater than `256`.
+	pub fn set_frame_slices(&mut self, slices: u16) -> Result {
+		if !(1..=256).contains(&slices) {
+			return Err(InvalidFrameSlices(slices))
+		}
+
+		self.frame_slices = slices;
+		Ok(())
+	}
+
+	/// Sets loop start and end sample offsets, written in a trailer after the final
+	/// frame when the encoder is closed. Decoders following the spec ignore the
+	/// trailer.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidLoopPoints`]: `start` isn't before `end`.
+	pub fn set_loop_points(&mut self, start: u32, end: u32) -> Result {
+		if start >= end {
+			return Err(InvalidLoopPoints(start, end))
+		}
+
+		self.loop_points = Some((start, end));
+		Ok(())
+	}
+
+	/// Sets the sample rate of a streaming encoder before encoding begins. Sources
+	/// with a different sample rate are rejected.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidDescriptorChange`]: the encoder is in fixed mode, or
+	/// encoding has begun.
+	///
+	/// [`EncodeError::InvalidDescriptor`]: `sample_rate` is outside the range
+	/// `[1,2^24)`.
+	pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result {
+		self.check_pinnable()?;
+		StreamDescriptor::new(None, Some(sample_rate), None).map_err(InvalidDescriptor)?;
+		self.desc.sample_rate   = Some(sample_rate);
+		self.pinned.sample_rate = Some(sample_rate);
+		Ok(())
+	}
+
+	/// Sets the channel count of a streaming encoder before encoding begins.
+	/// Sources with a different channel count are rejected.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidDescriptorChange`]: the encoder is in fixed mode, or
+	/// encoding has begun.
+	///
+	/// [`EncodeError::InvalidDescriptor`]: `channel_count` is `0`.
+	pub fn set_channels(&mut self, channel_count: usize) -> Result {
+		self.check_pinnable()?;
+		StreamDescriptor::new(None, None, Some(channel_count)).map_err(InvalidDescriptor)?;
+		self.desc.channel_count   = Some(channel_count);
+		self.pinned.channel_count = Some(channel_count);
+		Ok(())
+	}
+
+	/// Checks that the encoder is in streaming mode and hasn't begun encoding.
+	fn check_pinnable(&self) -> Result {
+		if self.has_header || !self.desc.is_streaming() || !self.buffer.is_empty() {
+			Err(InvalidDescriptorChange)
 		} else {
-			if desc.sample_rate   != this_desc.sample_rate ||
-				desc.channel_count != this_desc.channel_count {
-				return Err(InvalidDescriptorChange)
-			}
+			Ok(())
 		}
+	}
 
-		let (samples, rate, channels) = desc.unwrap_all();
+	/// Sets key-value tags, such as the title or artist, written in a trailer after
+	/// the final frame when the encoder is closed. Decoders following the spec
+	/// ignore the trailer.
+	pub fn set_metadata(&mut self, tags: Vec<(String, String)>) {
+		self.metadata = tags;
+	}
 
-		if samples == 0 || rate == 0 || channels == 0 {
+	/// Sets the tags of a Symphonia metadata revision, as [`Self::set_metadata`].
+	#[cfg(feature = "conv")]
+	pub fn set_metadata_revision(&mut self, revision: &symphonia::core::meta::MetadataRevision) {
+		self.set_metadata(crate::conv::revision_tags(revision));
+	}
+
+	/// Sets whether a CRC-32 checksum of each frame is written in a trailer after
+	/// the final frame when the encoder is closed. Must be set before any frames
+	/// are written to cover the whole stream.
+	pub fn set_checksums(&mut self, enabled: bool) {
+		self.checksums = enabled.then(Vec::new);
+	}
+
+	/// Sets whether triangular dither noise is added to residuals before they're
+	/// quantized, which can mask banding on quiet material. The noise is seeded
+	/// the same for each stream, so the output is deterministic. Disabled by
+	/// default, matching the reference encoder.
+	pub fn set_dither(&mut self, enabled: bool) {
+		self.dither = enabled.then(Vec::new);
+	}
+
+	/// Sets the LMS state of each channel at the start of the stream, such as the
+	/// final state of a previous stream, to improve prediction in the first frame.
+	/// The state is written into the first frame header, so decoding needs no
+	/// knowledge of it; later frames continue from the state adapted during the
+	/// first. Channels without a state start from the default.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::Started`]: encoding has begun.
+	pub fn set_initial_lms(&mut self, states: Vec<QoaLmsState>) -> Result {
+		if self.has_header || !self.buffer.is_empty() {
+			return Err(Started)
+		}
+
+		self.lms_states = states.clone();
+		self.initial_lms = Some(states);
+		Ok(())
+	}
+
+	/// Sets whether samples passed to [`Self::encode_vec`] or
+	/// [`Self::encode_interleaved`] must cover the declared sample count: the count
+	/// of the descriptor passed, or else the rest of a fixed stream. Otherwise,
+	/// short input is encoded as-is, shortening the stream. Disabled by default.
+	pub fn set_strict(&mut self, strict: bool) {
+		self.strict = strict;
+	}
+
+	/// Sets whether the mean squared error between the input and reconstructed
+	/// samples of each channel is measured while encoding, read with
+	/// [`Self::channel_errors`]. Disabled by default.
+	pub fn set_channel_errors(&mut self, enabled: bool) {
+		self.errors = enabled.then(ChannelErrors::default);
+	}
+
+	/// Returns the mean squared error of each channel over the samples encoded so
+	/// far, or an empty slice if not measured.
+	pub fn channel_errors(&self) -> &[f64] {
+		self.errors
+			.as_ref()
+			.map_or(&[], |errors| &errors.mse)
+	}
+
+	/// Resets the encoder to re-encode a stream from the start, discarding buffered
+	/// samples and encoding state. The sink is kept as-is; clearing it is left to
+	/// the caller.
+	pub fn reset(&mut self) {
+		match self.initial_lms.as_ref() {
+			Some(states) => self.lms_states.clone_from(states),
+			None => self.lms_states.fill(QoaLmsState::default())
+		}
+		self.buffer.clear();
+		self.has_header = false;
+		self.encoded = 0;
+		self.appended_to = None;
+		self.bytes_written = 0;
+		self.frames_written = 0;
+		self.pcm_bytes = 0;
+
+		if let Some(checksums) = self.checksums.as_mut() {
+			checksums.clear();
+		}
+
+		if let Some(dither) = self.dither.as_mut() {
+			dither.clear();
+		}
+
+		if let Some(errors) = self.errors.as_mut() {
+			*errors = ChannelErrors::default();
+		}
+	}
+
+	/// Encodes samples from a [`Vec`], removing the samples consumed.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidDescriptorChange`]: in strict mode, `source` holds
+	/// fewer samples than declared.
+	pub fn encode_vec(&mut self, source: &mut Vec<i16>, desc: StreamDescriptor) -> Result {
+		let count = self.encode_slice(source, desc)?;
+		source.drain(..count);
+		Ok(())
+	}
+
+	/// Encodes interleaved samples from a slice, leaving it unchanged.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidDescriptorChange`]: in strict mode, `samples` holds
+	/// fewer samples than declared.
+	pub fn encode_interleaved(&mut self, samples: &[i16], desc: StreamDescriptor) -> Result {
+		self.encode_slice(samples, desc).map(|_| ())
+	}
+
+	/// Encodes interleaved samples pulled from an iterator, a frame at a time. The
+	/// sample rate and channel count must be given by `desc` or the encoder.
+	pub fn encode_iter(&mut self, samples: impl Iterator<Item = i16>, mut desc: StreamDescriptor) -> Result {
+		desc.infer(&self.desc);
+
+		if !self.set_descriptor(&desc)? {
 			return Ok(())
 		}
 
+		let channels = desc.channels().unwrap_or_default();
+		let limit = desc.samples().map_or(usize::MAX, |n| n.saturating_mul(channels));
+		let width = self.frame_len() * channels;
+		let mut samples = samples.take(limit);
+		let mut scratch = Vec::with_capacity(width);
 
-		let mut samples = samples as usize;
+		loop {
+			scratch.clear();
+			scratch.extend(samples.by_ref().take(width));
 
-		{
-			let sink = self.sink.as_mut().ok_or(Closed)?;
+			let count = self.buffer_samples(&scratch);
+			self.write_frames(false)?;
 
-			if *has_header {
-				sink.enc_file_header(this_desc.sample_count.unwrap_or_default())?;
-				*has_header = true;
+			if scratch.len() < width || count * channels < scratch.len() {
+				break
 			}
+		}
 
-			lms_states.resize(channels as usize, QoaLmsState::default());
+		self.flush_sink()
+	}
 
-			while let n @ 1.. = sink.enc_frame::<Sc>(source, samples, channels, rate, lms_states, frame)? {
-				source.truncate(source.len().saturating_sub(n * channels as usize));
-				samples = samples.saturating_sub(n);
-			}
+	/// Encodes per-channel samples, interleaving them a frame at a time.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::InvalidDescriptor`]: the channels differ in length.
+	pub fn encode_planar(&mut self, channels: &[Vec<i16>], mut desc: StreamDescriptor) -> Result {
+		desc.infer_from_planar(channels, &self.desc).map_err(InvalidDescriptor)?;
+
+		let len = desc.samples().unwrap_or_default();
+		let samples = (0..len).flat_map(|i| channels.iter().map(move |chn| chn[i]));
+		self.encode_iter(samples, desc)
+	}
 
-			sink.flush().map_err(Flush)?
+	/// Encodes interleaved samples, returning the number of samples consumed.
+	fn encode_slice(&mut self, source: &[i16], mut desc: StreamDescriptor) -> Result<usize> {
+		let declared = desc.samples();
+		desc.infer_from_vec(source, &self.desc);
+
+		if !self.set_descriptor(&desc)? {
+			return Ok(0)
 		}
 
-		self.set_sample_count(samples as u32);
-		Ok(())
+		let (samples, _, channels) = desc.unwrap_all();
+		self.check_strict(declared, source.len() / channels)?;
+		let samples = min(samples, source.len() / channels);
+		let count = self.buffer_samples(&source[..samples * channels]);
+
+		self.write_frames(false)?;
+		self.flush_sink()?;
+		Ok(count * channels)
 	}
 
-	/// Encodes samples from a [`Pcm16Source`].
+	/// Encodes samples from a [`Pcm16Source`]. Whole frames read while no samples
+	/// are buffered are encoded straight from the read, without buffering.
 	pub fn encode(&mut self, source: &mut impl PcmSource) -> Result {
 		let mut desc = source.descriptor();
-		let Self { desc: this_desc, has_header, lms_states, frame, .. } = self;
-		desc.infer(this_desc);
+		desc.infer(&self.desc);
 
-		if !this_desc.is_streaming() {
-			if desc.sample_rate   != this_desc.sample_rate ||
-				desc.channel_count != this_desc.channel_count {
-				return Err(InvalidDescriptorChange)
+		if !self.set_descriptor(&desc)? {
+			return Ok(())
+		}
+
+		let (_, rate, channels) = desc.unwrap_all();
+
+		loop {
+			let mut frame = PcmFrame::new(self.frame_len(), rate, channels);
+			source.read(&mut frame, self.frame_len())
+				  .map_err(|err| SampleRead(err.into()))?;
+
+			if frame.is_empty() {
+				break
+			}
+
+			if !self.write_aligned(frame.data(), rate, channels)? {
+				if self.buffer_samples(frame.data()) == 0 {
+					break
+				}
+
+				self.write_frames(false)?;
 			}
 		}
 
-		let (samples, rate, channels) = desc.unwrap_all();
+		self.flush_sink()
+	}
 
-		if samples == 0 || rate == 0 || channels == 0 {
-			return Ok(())
+	/// Encodes a planar Symphonia buffer, interleaving it straight into the sample
+	/// buffer rather than through a [`PcmFrame`]. Returns the number of samples per
+	/// channel buffered, which is less than the buffer length when it ends a fixed
+	/// stream. Complete frames are written, but the sink isn't flushed.
+	#[cfg(feature = "conv")]
+	pub fn encode_audio_buffer(&mut self, buf: &symphonia::core::audio::AudioBuffer<i16>) -> Result<usize> {
+		use symphonia::core::audio::Signal;
+
+		let spec = buf.spec();
+		let channels = spec.channels.count();
+		let mut desc = StreamDescriptor::new(None, Some(spec.rate), Some(channels))
+			.map_err(InvalidDescriptor)?;
+		desc.infer(&self.desc);
+
+		if !self.set_descriptor(&desc)? {
+			return Ok(0)
 		}
 
-		let mut samples = samples as usize;
+		let mut count = buf.frames();
+		if let Some(total) = self.desc.sample_count {
+			let buffered = self.encoded + self.buffer.len() / channels;
+			count = min(count, total.saturating_sub(buffered));
+		}
 
-		{
-			let sink = self.sink.as_mut().ok_or(Closed)?;
+		let start = self.buffer.len();
+		self.buffer.resize(start + count * channels, 0);
+		for (chn, plane) in crate::conv::channel_order(spec.channels).into_iter().enumerate() {
+			let plane = &buf.chan(plane)[..count];
+			for (dst, &src) in self.buffer[start..].iter_mut().skip(chn).step_by(channels).zip(plane) {
+				*dst = src;
+			}
+		}
+
+		self.write_frames(false)?;
+		Ok(count)
+	}
 
-			if *has_header {
-				sink.enc_file_header(this_desc.sample_count.unwrap_or_default())?;
-				*has_header = true;
+	/// Encodes samples from a [`FormatSource`](crate::conv::FormatSource), taking
+	/// its decoded buffers directly with [`Self::encode_audio_buffer`]. Faster than
+	/// [`Self::encode`], which copies samples through an intermediate frame.
+	#[cfg(feature = "conv")]
+	pub fn encode_format(&mut self, source: &mut crate::conv::FormatSource) -> Result {
+		use symphonia::core::audio::Signal;
+
+		while let Some(buf) = source.next_buffer().map_err(|err| SampleRead(err.into()))? {
+			if buf.frames() > 0 && self.encode_audio_buffer(&buf)? == 0 {
+				break
 			}
+		}
 
-			lms_states.resize(channels as usize, QoaLmsState::default());
+		self.flush_sink()
+	}
 
-			frame.start(samples, channels as usize);
+	/// Encodes samples from a [`Vec`], scaling each channel on the Rayon thread
+	/// pool. Frames can't be scaled independently, because the LMS state carries
+	/// over from one frame into the next, but channels never share state, so the
+	/// output is identical to serial encoding.
+	#[cfg(feature = "rayon")]
+	pub fn encode_parallel(&mut self, source: &mut Vec<i16>, mut desc: StreamDescriptor) -> Result {
+		use rayon::prelude::*;
 
-			while !source.read(&mut frame.buffer, frame.slice_width)
-						 .map_err(|err| SampleRead(err.into()))? > 0 {
-				let n = sink.enc_frame::<Sc>(&[], samples, channels, rate, lms_states, frame)?;
-				samples = samples.saturating_sub(n);
+		desc.infer_from_vec(source, &self.desc);
 
-				if n == 0 {
-					break
+		if !self.set_descriptor(&desc)? {
+			return Ok(())
+		}
+
+		let (samples, _, channels) = desc.unwrap_all();
+		let samples = min(samples, source.len() / channels);
+		let count = self.buffer_samples(&source[..samples * channels]);
+		source.drain(..count * channels);
+
+		let (total, rate, channels) = self.desc.unwrap_all();
+		let frame_width = self.frame_len() * channels;
+		let slice_width = SLICE_LEN * channels;
+
+		// Only encode whole frames, unless the last frame of a fixed stream.
+		let buffer = mem::take(&mut self.buffer);
+		let len = if self.encoded + buffer.len() / channels == total {
+			buffer.len()
+		} else {
+			buffer.len() - buffer.len() % frame_width
+		};
+		let data = &buffer[..len];
+
+		self.lms_states.resize(channels, QoaLmsState::default());
+		let mut dither = Self::channel_dither(&mut self.dither, channels).map(|dither| dither.iter_mut());
+		let mut errors = self.errors.as_mut().map(|errors| errors.sums(channels).iter_mut());
+		let states: Vec<_> = self.lms_states
+								 .iter_mut()
+								 .map(|lms| (
+									 lms,
+									 dither.as_mut().and_then(Iterator::next),
+									 errors.as_mut().and_then(Iterator::next),
+								 ))
+								 .collect();
+
+		// Scale each channel in parallel, keeping the LMS state at the start of
+		// each frame for its header.
+		let scaled: Vec<Vec<(QoaLmsState, Vec<u64>)>> =
+			states.into_par_iter()
+				  .enumerate()
+				  .map(|(chn, (lms, mut dither, mut error))|
+					  data.chunks(frame_width).map(|frame| {
+						  let start = *lms;
+						  let slices = frame.chunks(slice_width)
+											.map(|slice| scale_slice::<Sc>(
+												slice,
+												lms,
+												chn,
+												channels,
+												dither.as_deref_mut(),
+												error.as_deref_mut()
+											))
+											.collect();
+						  (start, slices)
+					  }).collect()
+				  ).collect();
+
+		self.write_header()?;
+
+		for (fi, frame) in data.chunks(frame_width).enumerate() {
+			let sink = self.sink.as_mut().ok_or(Closed)?;
+			let ref mut sink = ChecksumSink::new(sink);
+			let frame_len = frame.len() / channels;
+			let slice_count = (frame_len + SLICE_LEN - 1) / SLICE_LEN;
+			let size = frame_byte_size(channels, slice_count);
+			sink.enc_frame_header(channels, rate, frame_len as u16, size as u16)?;
+
+			for chn in &scaled { sink.enc_lms_state(&chn[fi].0)? }
+
+			for si in 0..slice_count {
+				for (chn, frames) in scaled.iter().enumerate() {
+					sink.write_long(frames[fi].1[si])
+						.map_err(|err| Write(SliceData(chn as u8), err))?;
 				}
 			}
 
-			sink.flush().map_err(Flush)?;
+			let (checksum, bytes) = (sink.checksum(), sink.len());
+			if let Some(checksums) = self.checksums.as_mut() {
+				checksums.push(checksum);
+			}
+
+			self.count_frame(frame_len, channels, bytes);
+			self.report_progress(frame_len);
 		}
 
-		self.set_sample_count(samples as u32);
-		Ok(())
+		self.buffer = buffer[len..].to_vec();
+		self.flush_sink()
 	}
 
 	/// Flushes buffered samples to the inner sink.
 	pub fn flush(&mut self) -> Result<()> {
-		self.encode_vec(&mut Vec::new(), StreamDescriptor::default())
+		if self.sink.is_none() {
+			return Err(Closed)
+		}
+
+		self.write_frames(true)?;
+		self.flush_sink()
 	}
 
-	/// Closes the encoder, returning the inner sink if not already closed.
+	/// Closes the encoder, writing the loop point trailer if set, then returns the
+	/// inner sink if not already closed. If a fixed stream ended before its sample
+	/// count, the file header is rewritten with the number of samples encoded, if
+	/// the sink can seek back. If no samples were encoded, a file header with a
+	/// sample count of `0` is written, giving a valid empty stream.
 	pub fn close(&mut self) -> Option<Result<S>> {
-		match self.flush() {
+		let result = self.flush()
+						 .and_then(|_| self.write_empty_header())
+						 .and_then(|_| self.backfill_header())
+						 .and_then(|_| self.write_trailer());
+		match result {
 			Err(Closed) => return None,
 			Err(err) => return Some(Err(err)),
 			_ => { }
@@ -287,11 +757,275 @@ impl<S: SinkStream, Sc: SliceScaler> Encoder<S, Sc> {
 		self.sink.take().map(Ok)
 	}
 
-	/// Sets the sample count after encoding, if in fixed mode.
-	fn set_sample_count(&mut self, n: u32) {
-		if let Some(ref mut samples) = self.desc.sample_count {
-			*samples = n as usize;
+	/// Closes the encoder, returning the inner sink and statistics of the encoded
+	/// stream.
+	///
+	/// # Errors
+	///
+	/// [`EncodeError::Closed`]: the encoder was already closed.
+	pub fn finish(mut self) -> Result<(S, EncodeSummary)> {
+		let sink = self.close().ok_or(Closed)??;
+		Ok((sink, self.summary()))
+	}
+
+	/// Returns statistics of the stream encoded so far.
+	pub fn summary(&self) -> EncodeSummary {
+		let Self { bytes_written, frames_written, pcm_bytes, encoded, .. } = *self;
+		EncodeSummary {
+			bytes_written,
+			frames_written,
+			samples_encoded: encoded,
+			compression_ratio: if bytes_written > 0 {
+				pcm_bytes as f64 / bytes_written as f64
+			} else {
+				0.0
+			},
+		}
+	}
+
+	/// Checks `desc` against the stream descriptor. In streaming mode, the sample
+	/// rate and channel count are taken from `desc`, writing buffered samples in
+	/// the previous format first. Returns `false` if either is unknown.
+	fn set_descriptor(&mut self, desc: &StreamDescriptor) -> Result<bool> {
+		let StreamDescriptor { sample_rate, channel_count, .. } = *desc;
+
+		if sample_rate   != self.desc.sample_rate ||
+		   channel_count != self.desc.channel_count {
+			let StreamDescriptor { sample_rate: rate, channel_count: channels, .. } = self.pinned;
+			if !self.desc.is_streaming()                           ||
+			   rate    .is_some_and(|_| rate     != sample_rate  ) ||
+			   channels.is_some_and(|_| channels != channel_count) {
+				return Err(InvalidDescriptorChange)
+			}
+
+			self.write_frames(true)?;
+			self.desc.sample_rate   = sample_rate;
+			self.desc.channel_count = channel_count;
+		}
+
+		Ok(matches!((sample_rate, channel_count), (Some(1..), Some(1..))))
+	}
+
+	/// Checks that `available` samples per channel cover the `declared` count, or
+	/// else the rest of a fixed stream, in strict mode.
+	fn check_strict(&self, declared: Option<usize>, available: usize) -> Result {
+		if !self.strict { return Ok(()) }
+
+		let channels = self.desc.channel_count.unwrap_or(1);
+		let expected = declared.or_else(|| {
+			let buffered = self.encoded + self.buffer.len() / channels;
+			self.desc.sample_count.map(|total| total.saturating_sub(buffered))
+		});
+
+		match expected {
+			Some(expected) if available < expected => Err(InvalidDescriptorChange),
+			_ => Ok(())
+		}
+	}
+
+	/// Buffers interleaved `samples`, up to the remaining sample count in fixed
+	/// mode. Returns the number of samples per channel buffered.
+	fn buffer_samples(&mut self, samples: &[i16]) -> usize {
+		let channels = self.desc.channel_count.unwrap_or_default();
+		if channels == 0 { return 0 }
+
+		let mut count = samples.len() / channels;
+		if let Some(total) = self.desc.sample_count {
+			let buffered = self.encoded + self.buffer.len() / channels;
+			count = min(count, total.saturating_sub(buffered));
+		}
+
+		self.buffer.extend_from_slice(&samples[..count * channels]);
+		count
+	}
+
+	/// Writes interleaved `samples` as a frame without buffering them, if they
+	/// fill exactly one frame, nothing is buffered, and they don't run past the end
+	/// of a fixed stream. Returns `false` if the samples must be buffered instead.
+	fn write_aligned(&mut self, samples: &[i16], rate: u32, channels: usize) -> Result<bool> {
+		let frame_len = self.frame_len();
+		let fits = self.desc.sample_count.map_or(true, |total| self.encoded + frame_len <= total);
+		if !self.buffer.is_empty() || samples.len() != frame_len * channels || !fits {
+			return Ok(false)
+		}
+
+		self.write_frame(samples, rate, channels)?;
+		Ok(true)
+	}
+
+	/// Writes buffered samples as complete frames. If `flush` is `true`, or if the
+	/// samples end a fixed stream, remaining samples are written as a partial
+	/// frame.
+	fn write_frames(&mut self, flush: bool) -> Result {
+		let (total, rate, channels) = self.desc.unwrap_all();
+		if channels == 0 || self.buffer.is_empty() { return Ok(()) }
+
+		let frame_width = self.frame_len() * channels;
+		let buffer = mem::take(&mut self.buffer);
+		let mut off = 0;
+		let result = loop {
+			let len = min(buffer.len() - off, frame_width);
+			let last = self.encoded + len / channels == total;
+
+			if len == 0 || (len < frame_width && !flush && !last) {
+				break Ok(())
+			}
+
+			if let Err(err) = self.write_frame(&buffer[off..off + len], rate, channels) {
+				break Err(err)
+			}
+
+			off += len;
+		};
+
+		self.buffer = buffer;
+		self.buffer.drain(..off);
+		result
+	}
+
+	/// Writes a frame of interleaved `samples`, writing the file header first if
+	/// needed.
+	fn write_frame(&mut self, samples: &[i16], rate: u32, channels: usize) -> Result {
+		self.write_header()?;
+		self.lms_states.resize(channels, QoaLmsState::default());
+
+		let frame_len = self.frame_len();
+		let dither = Self::channel_dither(&mut self.dither, channels);
+		let errors = self.errors.as_mut().map(|errors| errors.sums(channels));
+		let sink = self.sink.as_mut().ok_or(Closed)?;
+		let ref mut sink = ChecksumSink::new(sink);
+		let count = sink.enc_frame::<Sc>(samples, frame_len, channels, rate, &mut self.lms_states, dither, errors)?;
+
+		let (checksum, bytes) = (sink.checksum(), sink.len());
+		if let Some(checksums) = self.checksums.as_mut() {
+			checksums.push(checksum);
+		}
+
+		self.count_frame(count, channels, bytes);
+		self.report_progress(count);
+		Ok(())
+	}
+
+	/// Returns the number of samples per channel in a full frame.
+	fn frame_len(&self) -> usize { self.frame_slices as usize * SLICE_LEN }
+
+	/// Returns the dither noise source of each of `channels`, if enabled.
+	fn channel_dither(dither: &mut Option<Vec<Dither>>, channels: usize) -> Option<&mut [Dither]> {
+		let dither = dither.as_mut()?;
+		while dither.len() < channels {
+			dither.push(Dither::new(dither.len()));
+		}
+		Some(&mut dither[..channels])
+	}
+
+	fn write_header(&mut self) -> Result {
+		let sink = self.sink.as_mut().ok_or(Closed)?;
+
+		if !self.has_header {
+			sink.enc_file_header(self.desc.sample_count.unwrap_or_default())?;
+			self.has_header = true;
+			self.bytes_written += 8;
+		}
+
+		Ok(())
+	}
+
+	/// Writes a file header with a sample count of `0` if no frames were written,
+	/// rather than leaving the sink empty. Fixed streams are included, since a
+	/// header declaring samples would be followed by no frames.
+	fn write_empty_header(&mut self) -> Result {
+		if self.has_header { return Ok(()) }
+
+		let sink = self.sink.as_mut().ok_or(Closed)?;
+		sink.enc_file_header(0)?;
+		self.has_header = true;
+		self.bytes_written += 8;
+		self.flush_sink()
+	}
+
+	/// Rewrites the file header of a fixed stream that ended early with the number
+	/// of samples encoded, or of an appended fixed stream with its new length. Must
+	/// be called before the trailers are written.
+	fn backfill_header(&mut self) -> Result {
+		if let Some((samples, len)) = self.appended_to {
+			if samples == 0 || self.encoded == 0 { return Ok(()) }
+
+			let header = (MAGIC as u64) << 32 | (samples + self.encoded) as u64;
+			let sink = self.sink.as_mut().ok_or(Closed)?;
+			sink.rewrite_long((len + self.bytes_written) / 8, header)
+				.map_err(|err| Write(FileHeader, err))?;
+			return self.flush_sink()
+		}
+
+		let Some(total) = self.desc.sample_count else { return Ok(()) };
+		if !self.has_header || self.encoded == 0 || self.encoded >= total {
+			return Ok(())
+		}
+
+		let header = (MAGIC as u64) << 32 | self.encoded as u64;
+		let sink = self.sink.as_mut().ok_or(Closed)?;
+		if sink.rewrite_long(self.bytes_written / 8, header)
+			   .map_err(|err| Write(FileHeader, err))? {
+			self.desc.sample_count = Some(self.encoded);
+		}
+
+		self.flush_sink()
+	}
+
+	/// Writes the loop point, checksum, and metadata trailers after the final
+	/// frame, if set.
+	fn write_trailer(&mut self) -> Result {
+		if self.loop_points.is_none() && self.checksums.is_none() && self.metadata.is_empty() {
+			return Ok(())
+		}
+
+		self.write_header()?;
+		let sink = self.sink.as_mut().ok_or(Closed)?;
+
+		if let Some((start, end)) = self.loop_points {
+			sink.enc_loop_points(start, end)?;
+			self.bytes_written += 16;
+		}
+
+		if let Some(checksums) = self.checksums.as_ref() {
+			sink.enc_checksums(checksums)?;
+			self.bytes_written += 8 + 8 * ((checksums.len() + 1) / 2);
 		}
+
+		if !self.metadata.is_empty() {
+			self.bytes_written += sink.enc_metadata(&self.metadata)?;
+		}
+
+		self.flush_sink()
+	}
+
+	/// Counts a frame of `count` samples per channel, written as `bytes` bytes.
+	fn count_frame(&mut self, count: usize, channels: usize, bytes: usize) {
+		self.frames_written += 1;
+		self.bytes_written  += bytes;
+		self.pcm_bytes      += count * channels * 2;
+	}
+
+	/// Adds `count` to the number of samples encoded, then invokes the progress
+	/// callback.
+	fn report_progress(&mut self, count: usize) {
+		self.encoded += count;
+
+		if let Some(errors) = self.errors.as_mut() {
+			errors.update(self.encoded);
+		}
+
+		if let Some(progress) = self.progress.as_mut() {
+			progress(self.encoded, self.desc.sample_count.unwrap_or_default());
+		}
+	}
+
+	fn flush_sink(&mut self) -> Result {
+		self.sink
+			.as_mut()
+			.ok_or(Closed)?
+			.flush()
+			.map_err(Flush)
 	}
 }
 
@@ -338,14 +1072,62 @@ pub(crate) trait QoaSink: SinkStream {
 		self.write_long(weights).map_err(|err| Write(LmsState("weights"), err))
 	}
 
+	fn enc_loop_points(&mut self, start: u32, end: u32) -> Result {
+		self.write_long((LOOP_MAGIC as u64) << 32 | 8)
+			.and_then(|_| self.write_long((start as u64) << 32 | end as u64))
+			.map_err(|err| Write(LoopPoints, err))
+	}
+
+	fn enc_checksums(&mut self, checksums: &[u32]) -> Result {
+		let size = checksums.len() as u64 * 4;
+		self.write_long((CHECKSUM_MAGIC as u64) << 32 | size)
+			.map_err(|err| Write(Checksums, err))?;
+
+		for pair in checksums.chunks(2) {
+			let value = (pair[0] as u64) << 32 | pair.get(1).copied().unwrap_or_default() as u64;
+			self.write_long(value)
+				.map_err(|err| Write(Checksums, err))?;
+		}
+
+		Ok(())
+	}
+
+	/// Writes the metadata trailer, returning the number of bytes written.
+	fn enc_metadata(&mut self, tags: &[(String, String)]) -> Result<usize> {
+		let mut payload = Vec::new();
+		for (key, value) in tags {
+			for str in [key, value] {
+				payload.extend_from_slice(&(str.len() as u32).to_be_bytes());
+				payload.extend_from_slice(str.as_bytes());
+			}
+		}
+
+		let size = payload.len();
+		payload.resize((size + 7) & !7, 0);
+
+		self.write_long((META_MAGIC as u64) << 32 | size as u64)
+			.map_err(|err| Write(Metadata, err))?;
+
+		for long in payload.iter().copied().array_chunks::<8>() {
+			self.write_long(u64::from_be_bytes(long))
+				.map_err(|err| Write(Metadata, err))?;
+		}
+
+		Ok(8 + payload.len())
+	}
+
 	fn enc_slice<Scaler: SliceScaler>(
 		&mut self,
 		samples: &[i16],
 		channel_count: usize,
-		lms: &mut [QoaLmsState]
+		lms: &mut [QoaLmsState],
+		mut dither: Option<&mut [Dither]>,
+		mut errors: Option<&mut [f64]>,
 	) -> Result {
 		for chn in 0..channel_count {
-			self.write_long(Scaler::scale(samples, &mut lms[chn], chn, channel_count))
+			let dither = dither.as_deref_mut().map(|dither| &mut dither[chn]);
+			let error = errors.as_deref_mut().map(|errors| &mut errors[chn]);
+			self.write_long(scale_slice::<Scaler>(samples, &mut lms[chn], chn, channel_count, dither, error))
 				.map_err(|err|
 					Write(SliceData(chn as u8), err)
 				)?;
@@ -354,66 +1136,793 @@ pub(crate) trait QoaSink: SinkStream {
 		Ok(())
 	}
 
+	/// Encodes a frame of interleaved `samples`, at most `frame_len` per channel,
+	/// returning the number of samples per channel encoded.
 	fn enc_frame<Scaler: SliceScaler>(
 		&mut self,
-		sample_buf: &[i16],
-		sample_cnt: usize,
+		samples: &[i16],
+		frame_len: usize,
 		channels: usize,
 		rate: u32,
 		lms: &mut [QoaLmsState],
-		frame: &mut Frame,
+		mut dither: Option<&mut [Dither]>,
+		mut errors: Option<&mut [f64]>,
 	) -> Result<usize> {
-		if sample_buf.is_empty() && frame.buffer.is_empty() {
-			return Ok(0)
+		let len = min(samples.len() / channels, frame_len);
+		let slices = (len + SLICE_LEN - 1) / SLICE_LEN;
+		let size = frame_byte_size(channels, slices);
+		self.enc_frame_header(channels, rate, len as u16, size as u16)?;
+
+		for lms in lms.iter() { self.enc_lms_state(lms)? }
+
+		for slice in samples[..len * channels].chunks(SLICE_LEN * channels) {
+			self.enc_slice::<Scaler>(slice, channels, lms, dither.as_deref_mut(), errors.as_deref_mut())?;
 		}
 
-		let mut consumed = 0;
-		let mut off = 0;
-		let mut len = sample_buf.len();
+		Ok(len)
+	}
+}
+
+impl<S: SinkStream> QoaSink for S { }
+
+/// Scales a slice of channel `chn`, adding noise from `dither` if given. The
+/// squared reconstruction error is added to `error`, if given.
+fn scale_slice<Sc: SliceScaler>(
+	samples: &[i16],
+	lms: &mut QoaLmsState,
+	chn: usize,
+	channel_count: usize,
+	dither: Option<&mut Dither>,
+	error: Option<&mut f64>,
+) -> u64 {
+	let start = *lms;
+	let slice = match dither {
+		Some(dither) => Sc::scale_dithered(samples, lms, chn, channel_count, &dither.slice_noise()),
+		None => Sc::scale(samples, lms, chn, channel_count)
+	};
+
+	if let Some(error) = error {
+		*error += slice_error(samples, chn, channel_count, start, slice);
+	}
 
-		if frame.start(sample_cnt, channels as usize) {
-			let size = 24 * channels as u16 + 8 * frame.slice_count as u16 * channels as u16;
-			self.enc_frame_header(channels, rate, sample_cnt as u16, size)?;
+	slice
+}
+
+/// Reconstructs a scaled slice of channel `chn` from the LMS state it started
+/// from, returning the sum of squared errors from the input samples.
+fn slice_error(samples: &[i16], chn: usize, channel_count: usize, mut lms: QoaLmsState, slice: u64) -> f64 {
+	let len = min(SLICE_LEN, samples.len() / channel_count);
+	let sf = (slice >> 60) as usize;
+	(0..len).map(|i| {
+		let quantized = (slice >> (57 - 3 * i)) as usize & 7;
+		let predicted = lms.predict();
+		let dequantized = DEQUANT_TABLE[sf][quantized];
+		let reconst = reconstruct(predicted, dequantized);
+		lms.update(reconst, dequantized);
+
+		let diff = samples[i * channel_count + chn] as f64 - reconst as f64;
+		diff * diff
+	}).sum()
+}
+
+/// The reconstruction error of each channel.
+#[derive(Clone, Debug, Default)]
+struct ChannelErrors {
+	/// The sum of squared errors of each channel.
+	sums: Vec<f64>,
+	/// The mean squared error of each channel.
+	mse: Vec<f64>,
+}
 
-			for lms in lms.iter() { self.enc_lms_state(lms)? }
+impl ChannelErrors {
+	/// Returns the error sums of `channels`.
+	fn sums(&mut self, channels: usize) -> &mut [f64] {
+		if self.sums.len() < channels {
+			self.sums.resize(channels, 0.0);
 		}
+		&mut self.sums[..channels]
+	}
 
-		{
-			let Frame { slice_width, slice_count, slice_index, buffer, .. } = frame;
+	/// Updates the mean errors over `samples` per channel.
+	fn update(&mut self, samples: usize) {
+		self.mse.clear();
+		self.mse.extend(self.sums.iter().map(|sum| sum / samples as f64));
+	}
+}
 
-			len = min(len, *slice_width * *slice_count as usize);
+/// A sink computing the CRC-32 checksum and byte length of longs written to its
+/// inner sink.
+struct ChecksumSink<'a, S: SinkStream> {
+	sink: &'a mut S,
+	crc: Crc32,
+	len: usize,
+}
 
-			if !buffer.is_empty() || len <= *slice_width {
-				off = min((*slice_width).saturating_sub(buffer.len()), len);
-				consumed = SLICE_LEN;
-				buffer.write_interleaved(&sample_buf[..off]).unwrap();
+impl<'a, S: SinkStream> ChecksumSink<'a, S> {
+	fn new(sink: &'a mut S) -> Self {
+		Self { sink, crc: Crc32::default(), len: 0 }
+	}
 
-				if buffer.len() <= SLICE_LEN {
-					//self.enc_slice(buffer., channels, lms)?;
-					*slice_index += 1;
-					buffer.clear();
-				}
+	fn checksum(&self) -> u32 { self.crc.finish() }
+
+	fn len(&self) -> usize { self.len }
+}
+
+impl<S: SinkStream> SinkStream for ChecksumSink<'_, S> {
+	fn write_long(&mut self, value: u64) -> WriteResult {
+		self.sink.write_long(value)?;
+		self.crc.update(&value.to_be_bytes());
+		self.len += 8;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::fs::{File, read, remove_file};
+	use std::io::{BufWriter, Cursor, Write};
+	use std::sync::{Arc, Mutex};
+	use std::thread;
+	use quickcheck::TestResult;
+	use quickcheck_macros::quickcheck;
+	use qoa_ref_sys::{encode, QoaDesc};
+	use crate::{byte_decoder, Encoder, EncodeError, FRAME_LEN, PcmFrame, PcmSink, PcmSource, PcmStream, pcm_io, qc_assert_eq, QoaHeader, QoaLmsState, StreamDescriptor};
+	use crate::io::Buffer;
+	use crate::reference::encode_ref;
+
+	#[test]
+	fn progress() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 100;
+
+		let calls = Arc::new(Mutex::new(Vec::new()));
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.set_progress({
+			let calls = calls.clone();
+			move |done, total| calls.lock().unwrap().push((done, total))
+		});
+
+		let mut data = vec![0; SAMPLES * 2];
+		enc.encode_vec(&mut data, StreamDescriptor::default()).unwrap();
+		enc.close().unwrap().unwrap();
+
+		assert_eq!(
+			*calls.lock().unwrap(),
+			[(FRAME_LEN, SAMPLES), (FRAME_LEN * 2, SAMPLES), (SAMPLES, SAMPLES)]
+		);
+	}
+
+	#[test]
+	fn encode_interleaved() {
+		const SAMPLES: usize = FRAME_LEN + 100;
+
+		let data: Vec<i16> = (0..SAMPLES as i32 * 2).map(|i| (i * 13 % 3000) as i16).collect();
+		let original = data.clone();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		let interleaved = enc.close().unwrap().unwrap();
+		assert_eq!(data, original);
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_vec(&mut data.clone(), StreamDescriptor::default()).unwrap();
+		assert_eq!(interleaved, enc.close().unwrap().unwrap());
+	}
+
+	#[cfg(feature = "rayon")]
+	#[test]
+	fn encode_parallel_parity() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 100;
+
+		for channels in [1, 2, 6] {
+			let data: Vec<i16> = (0..(SAMPLES * channels) as i32).map(|i| (i * 13 % 3000) as i16).collect();
+
+			let mut enc = Encoder::new_fixed(SAMPLES, 44100, channels, Buffer::default()).unwrap();
+			enc.encode_vec(&mut data.clone(), StreamDescriptor::default()).unwrap();
+			let serial = enc.close().unwrap().unwrap();
+
+			let mut enc = Encoder::new_fixed(SAMPLES, 44100, channels, Buffer::default()).unwrap();
+			enc.encode_parallel(&mut data.clone(), StreamDescriptor::default()).unwrap();
+			assert_eq!(enc.close().unwrap().unwrap(), serial, "{channels} channels");
+		}
+	}
+
+	#[quickcheck]
+	fn encode_channels_parity(samples: Vec<i16>, channels: u8) -> TestResult {
+		let channels = [1, 4, 6, 8][channels as usize % 4];
+		let len = samples.len() / channels;
+		if len == 0 { return TestResult::discard() }
+
+		let samples = &samples[..len * channels];
+		let expected = encode_ref(samples, channels);
+
+		let mut enc = Encoder::new_fixed(len, 44100, channels, Buffer::default()).unwrap();
+		enc.encode_interleaved(samples, StreamDescriptor::default()).unwrap();
+		qc_assert_eq!(enc.close().unwrap().unwrap().encode(), expected)
+	}
+
+	#[test]
+	fn initial_lms() {
+		const SAMPLES: usize = FRAME_LEN + 100;
+		const FRAME_SIZE: usize = 8 + 16 + 256 * 8;
+
+		let samples: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 37 % 9000 - 4500) as i16).collect();
+		let encode_with = |lms: Option<Vec<QoaLmsState>>| {
+			let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+			if let Some(lms) = lms {
+				enc.set_initial_lms(lms).unwrap();
 			}
+			enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+			assert!(matches!(enc.set_initial_lms(Vec::new()), Err(EncodeError::Started)));
+			enc.close().unwrap().unwrap().encode()
+		};
+
+		let default = encode_with(None);
+		assert_eq!(encode_with(Some(vec![QoaLmsState::default()])), default);
+
+		let warm = QoaLmsState::new([100, -200, 300, -400], [1 << 12, -(1 << 12), 1 << 13, 1 << 13]);
+		let custom = encode_with(Some(vec![warm]));
+		assert_ne!(custom[8..8 + FRAME_SIZE], default[8..8 + FRAME_SIZE]);
+
+		let mut decoded = Vec::new();
+		byte_decoder::Decoder::default().decode(&custom, &mut decoded).unwrap();
+		assert_eq!(decoded.len(), SAMPLES);
+	}
+
+	/// A source reading at most 1000 samples at a time, never aligned to frames.
+	struct ShortReads(PcmFrame);
+
+	impl PcmStream for ShortReads {
+		fn channel_count(&self) -> usize { self.0.channel_count() }
+		fn sample_rate(&self) -> u32 { self.0.sample_rate() }
+	}
+
+	impl PcmSource for ShortReads {
+		fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, pcm_io::Error> {
+			self.0.read(buf, sample_count.min(1000))
+		}
+
+		fn sample_count(&self) -> usize { self.0.sample_count() }
+	}
+
+	#[test]
+	fn aligned_reads() {
+		const SAMPLES: usize = FRAME_LEN * 3 + 500;
+
+		let data: Vec<i16> = (0..SAMPLES * 2).map(|i| (i as i16).wrapping_mul(37)).collect();
+		let frame = || PcmFrame::from_interleaved(data.clone(), 44100, 2);
+		let close = |mut enc: Encoder<Buffer>| enc.close().unwrap().unwrap().encode();
+
+		let mut aligned = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		aligned.encode(&mut frame()).unwrap();
+		let mut buffered = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		buffered.encode(&mut ShortReads(frame())).unwrap();
+		let mut streaming = Encoder::new_streaming(Buffer::default());
+		streaming.encode(&mut frame()).unwrap();
+		let mut interleaved = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		interleaved.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+
+		let aligned = close(aligned);
+		let streaming = close(streaming);
+		assert!(aligned == close(buffered));
+		assert!(aligned == close(interleaved));
+		assert!(aligned[8..] == streaming[8..]);
+	}
+
+	#[test]
+	fn empty_input() {
+		let empty = |enc: Encoder<Buffer>| {
+			let (sink, summary) = enc.finish().unwrap();
+			let data = sink.encode();
+			assert_eq!(data.len(), 8);
+			assert_eq!(summary.bytes_written, 8);
+			assert_eq!(QoaHeader::try_from(&*data).ok(), None);
+			assert_eq!(u64::from_be_bytes(data[..8].try_into().unwrap()), (crate::MAGIC as u64) << 32);
+
+			let mut samples = Vec::new();
+			byte_decoder::Decoder::default().decode(&data, &mut samples).unwrap();
+			assert!(samples.is_empty());
+		};
+
+		let mut enc = Encoder::new_fixed(1000, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_vec(&mut Vec::new(), StreamDescriptor::default()).unwrap();
+		empty(enc);
+
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		enc.encode_vec(&mut Vec::new(), StreamDescriptor::default()).unwrap();
+		empty(enc);
+
+		// A source with nothing to read.
+		let mut enc = Encoder::new_fixed(1000, 44100, 2, Buffer::default()).unwrap();
+		enc.encode(&mut PcmFrame::new(0, 44100, 2)).unwrap();
+		empty(enc);
+
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		enc.encode(&mut PcmFrame::new(0, 44100, 2)).unwrap();
+		empty(enc);
+	}
+
+	#[test]
+	fn append() {
+		const HALF: usize = FRAME_LEN + 100;
+
+		let samples: Vec<i16> = (0..HALF * 4).map(|i| (i * 41 % 9000) as i16 - 4500).collect();
+		let (first, second) = samples.split_at(HALF * 2);
+		let encode = |samples: &[i16]| {
+			let mut enc = Encoder::new_fixed(HALF, 44100, 2, Cursor::new(Vec::new())).unwrap();
+			enc.encode_interleaved(samples, StreamDescriptor::default()).unwrap();
+			enc.close().unwrap().unwrap()
+		};
+		let decode = |data: &[u8]| {
+			let mut samples = Vec::new();
+			byte_decoder::Decoder::default().decode(data, &mut samples).unwrap();
+			samples
+		};
+
+		let sink = encode(first);
+		let header = QoaHeader::try_from(&sink.get_ref()[..]).unwrap();
+		let mut enc = Encoder::open_append(sink, header).unwrap();
+		enc.encode_interleaved(second, StreamDescriptor::default()).unwrap();
+		let data = enc.close().unwrap().unwrap().into_inner();
+
+		assert_eq!(QoaHeader::try_from(&*data).unwrap().sample_count, HALF as u32 * 2);
+		let mut expected = decode(encode(first).get_ref());
+		expected.extend(decode(encode(second).get_ref()));
+		assert_eq!(decode(&data), expected);
+
+		// Appended sources must match the existing stream.
+		let sink = encode(first);
+		let mut enc = Encoder::open_append(sink, header).unwrap();
+		let mono = StreamDescriptor::new(None, Some(44100), Some(1)).unwrap();
+		assert!(matches!(enc.encode_interleaved(second, mono), Err(EncodeError::InvalidDescriptorChange)));
+	}
+
+	#[test]
+	fn strict() {
+		let samples = vec![0i16; 5000];
+		let fixed = || Encoder::new_fixed(10000, 44100, 1, Buffer::default()).unwrap();
+
+		// Lenient mode encodes the short input as a shorter stream.
+		let mut enc = fixed();
+		enc.encode_vec(&mut samples.clone(), StreamDescriptor::default()).unwrap();
+		enc.flush().unwrap();
+		assert_eq!(enc.summary().samples_encoded, 5000);
+
+		let mut enc = fixed();
+		enc.set_strict(true);
+		let mut source = samples.clone();
+		assert!(matches!(
+			enc.encode_vec(&mut source, StreamDescriptor::default()),
+			Err(EncodeError::InvalidDescriptorChange)
+		));
+		assert_eq!(source.len(), 5000);
+
+		// The rest of the stream is enough, once part of it is buffered.
+		enc.set_strict(false);
+		enc.encode_vec(&mut source, StreamDescriptor::default()).unwrap();
+		enc.set_strict(true);
+		enc.encode_vec(&mut samples.clone(), StreamDescriptor::default()).unwrap();
+
+		// A declared count is checked against the samples given.
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		enc.set_strict(true);
+		let declared = StreamDescriptor::new(Some(10000), Some(44100), Some(1)).unwrap();
+		assert!(matches!(
+			enc.encode_interleaved(&samples, declared),
+			Err(EncodeError::InvalidDescriptorChange)
+		));
+		let declared = StreamDescriptor::new(Some(5000), Some(44100), Some(1)).unwrap();
+		enc.encode_interleaved(&samples, declared).unwrap();
+	}
+
+	#[test]
+	fn channel_errors() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 100;
+
+		// A smooth left channel and a noisy right channel.
+		let mut noise = 0x2545F491u32;
+		let samples: Vec<i16> = (0..SAMPLES).flat_map(|i| {
+			noise ^= noise << 13;
+			noise ^= noise >> 17;
+			noise ^= noise << 5;
+			[(i % 200) as i16 * 8, noise as i16]
+		}).collect();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		assert!(enc.channel_errors().is_empty());
+		enc.set_channel_errors(true);
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let errors = enc.channel_errors().to_vec();
+		let data = enc.close().unwrap().unwrap().encode();
+
+		let mut decoded = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut decoded).unwrap();
+		let expected: Vec<f64> = (0..2).map(|chn| {
+			let sum: f64 = samples.iter().zip(&decoded)
+								  .skip(chn)
+								  .step_by(2)
+								  .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+								  .sum();
+			sum / SAMPLES as f64
+		}).collect();
+
+		assert_eq!(errors.len(), 2);
+		assert!(errors[1] > errors[0] * 10.0, "errors {errors:?}");
+		for (actual, expected) in errors.iter().zip(&expected) {
+			assert!((actual - expected).abs() <= expected * 1e-9, "errors {errors:?}, expected {expected:?}");
+		}
+	}
+
+	#[test]
+	fn reference_error() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 100;
+
+		let samples: Vec<i16> = (0..SAMPLES * 2).map(|i| (i * 7919 % 20000) as i16 - 10000).collect();
+		let ref mut desc = QoaDesc {
+			channels: 2,
+			samplerate: 44100,
+			samples: SAMPLES as u32,
+			..QoaDesc::default()
+		};
+		encode(&samples, desc).unwrap();
+		assert!(desc.error() > 0.0 && desc.error().is_finite(), "reference error {}", desc.error());
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.set_channel_errors(true);
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let mse = enc.channel_errors().iter().sum::<f64>() / 2.0;
+
+		let (expected, actual) = (desc.rmse(), mse.sqrt());
+		assert!((actual - expected).abs() <= expected * 0.01, "rmse {actual}, reference {expected}");
+	}
+
+	#[test]
+	fn dither() {
+		const SAMPLES: usize = FRAME_LEN + 300;
+
+		let samples: Vec<i16> = (0..SAMPLES as i32 * 2).map(|i| (i * 7 % 64 - 32) as i16).collect();
+		let expected = encode_ref(&samples, 2);
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.set_dither(false);
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		assert_eq!(enc.close().unwrap().unwrap().encode(), expected);
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.set_dither(true);
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let dithered = enc.close().unwrap().unwrap().encode();
+		assert_ne!(dithered, expected);
+
+		let mut decoded = Vec::new();
+		byte_decoder::Decoder::default().decode(&dithered, &mut decoded).unwrap();
+		assert_eq!(decoded.len(), samples.len());
+	}
+
+	fn assert_send<T: Send>() { }
+
+	#[test]
+	fn thread_safety() {
+		assert_send::<Encoder<Buffer>>();
+		assert_send::<Encoder<BufWriter<File>>>();
+		#[cfg(feature = "simd")]
+		assert_send::<crate::SimdEncoder<Buffer>>();
+
+		// Encode on a worker thread, then take the sink back.
+		let mut enc = Encoder::new_fixed(100, 44100, 1, Buffer::default()).unwrap();
+		let buf = thread::spawn(move || {
+			enc.encode_interleaved(&[0; 100], StreamDescriptor::default()).unwrap();
+			enc.close().unwrap().unwrap()
+		}).join().unwrap();
+		assert!(!buf.is_empty());
+	}
+
+	#[test]
+	fn encode_iter() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 300;
+
+		let sine = || (0..SAMPLES * 2).map(|i| ((i / 2) as f64 * 0.03).sin())
+									  .map(|v| (v * 12000.0) as i16);
+		let data: Vec<i16> = sine().collect();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_iter(sine(), StreamDescriptor::default()).unwrap();
+		let iter = enc.close().unwrap().unwrap();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		assert_eq!(iter, enc.close().unwrap().unwrap());
+
+		let desc = StreamDescriptor::new(None, Some(44100), Some(2)).unwrap();
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		enc.encode_iter(sine(), desc).unwrap();
+		let iter = enc.close().unwrap().unwrap();
+
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		enc.encode_interleaved(&data, desc).unwrap();
+		assert_eq!(iter, enc.close().unwrap().unwrap());
+	}
+
+	#[test]
+	fn metadata() {
+		const SAMPLES: usize = FRAME_LEN + 100;
+
+		let tags = vec![
+			("TITLE".to_string(), "Quite OK Song".to_string()),
+			("ARTIST".to_string(), "Ünïcödé".to_string()),
+		];
+		let samples: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 11 % 3000) as i16).collect();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+		enc.set_metadata(tags.clone());
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let (sink, summary) = enc.finish().unwrap();
+		let data = sink.encode();
+		assert_eq!(summary.bytes_written, data.len());
+
+		let mut decoder = byte_decoder::Decoder::default();
+		let mut tagged = Vec::new();
+		assert_eq!(decoder.decode(&data, &mut tagged).unwrap(), data.len());
+		assert_eq!(decoder.metadata(), tags);
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let mut untagged = Vec::new();
+		byte_decoder::Decoder::default().decode(&enc.close().unwrap().unwrap().encode(), &mut untagged).unwrap();
+		assert_eq!(tagged, untagged);
+	}
+
+	#[test]
+	fn encode_planar() {
+		const SAMPLES: usize = FRAME_LEN + 100;
+
+		let left:  Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 17 % 4000) as i16).collect();
+		let right: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 31 % 3000) as i16).collect();
+		let interleaved: Vec<i16> = left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_planar(&[left.clone(), right.clone()], StreamDescriptor::default()).unwrap();
+		let planar = enc.close().unwrap().unwrap();
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_interleaved(&interleaved, StreamDescriptor::default()).unwrap();
+		assert_eq!(planar, enc.close().unwrap().unwrap());
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		assert!(matches!(
+			enc.encode_planar(&[left, right[1..].to_vec()], StreamDescriptor::default()),
+			Err(EncodeError::InvalidDescriptor(..))
+		));
+	}
+
+	#[test]
+	fn summary() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 100;
+
+		let data: Vec<i16> = (0..SAMPLES as i32 * 2).map(|i| (i * 7 % 5000) as i16).collect();
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default()).unwrap();
+		enc.set_loop_points(0, 100).unwrap();
+		enc.set_checksums(true);
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+
+		let (buf, summary) = enc.finish().unwrap();
+		let bytes = buf.encode().len();
+		assert_eq!(summary.bytes_written, bytes);
+		assert_eq!(summary.frames_written, 3);
+		assert_eq!(summary.samples_encoded, SAMPLES);
+		assert_eq!(summary.compression_ratio, (SAMPLES * 4) as f64 / bytes as f64);
+	}
+
+	#[test]
+	fn reset() {
+		const SAMPLES: usize = FRAME_LEN + 300;
+
+		let data: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 11 % 4000) as i16).collect();
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		enc.flush().unwrap();
+		enc.reset();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+
+		let bytes = enc.close().unwrap().unwrap().encode();
+		let (first, second) = bytes.split_at(bytes.len() / 2);
+		assert_eq!(first, second);
+	}
+
+	#[test]
+	fn frame_slices() {
+		const SAMPLES: usize = FRAME_LEN * 3;
+
+		let data: Vec<i16> = (0..SAMPLES).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+		let encode = |slices| {
+			let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+			enc.set_frame_slices(slices).unwrap();
+			enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+			enc.close().unwrap().unwrap().encode()
+		};
+		let decode = |data: &[u8]| {
+			let mut buf = Vec::new();
+			byte_decoder::Decoder::default().decode(data, &mut buf).unwrap();
+			buf
+		};
+
+		let short = encode(32);
+		let full  = encode(256);
+		assert_eq!(short.len(), full.len() + 7 * 3 * 24);
+		assert_eq!(decode(&short), decode(&full));
+	}
+
+	#[test]
+	fn invalid_frame_slices() {
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		assert!(enc.set_frame_slices(0).is_err());
+		assert!(enc.set_frame_slices(257).is_err());
+	}
+
+	#[test]
+	fn loop_points() {
+		const SAMPLES: usize = FRAME_LEN + 100;
+
+		let data: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 17 % 5000) as i16).collect();
+		let desc = StreamDescriptor::new(None, Some(44100), Some(1)).unwrap();
+		let encode = |enc: &mut Encoder<Buffer>| {
+			enc.set_loop_points(100, FRAME_LEN as u32).unwrap();
+			enc.encode_interleaved(&data, desc).unwrap();
+			enc.close().unwrap().unwrap().encode()
+		};
+		let fixed = encode(&mut Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap());
+		let streaming = encode(&mut Encoder::new_streaming(Buffer::default()));
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		let plain = enc.close().unwrap().unwrap().encode();
+		assert_eq!(fixed.len(), plain.len() + 16);
+
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&plain, &mut expected).unwrap();
+
+		for data in [fixed, streaming] {
+			let mut decoder = byte_decoder::Decoder::default();
+			let mut buf = Vec::new();
+			assert_eq!(decoder.decode(&data, &mut buf).unwrap(), data.len());
+			assert_eq!(decoder.loop_points(), Some((100, FRAME_LEN as u32)));
+			assert_eq!(buf, expected);
 		}
+	}
+
+	#[test]
+	fn invalid_loop_points() {
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		assert!(enc.set_loop_points(10, 10).is_err());
+		assert!(enc.set_loop_points(20, 10).is_err());
+	}
+
+	/// A source of unknown length.
+	struct UnknownLength(PcmFrame);
+
+	impl PcmStream for UnknownLength {
+		fn channel_count(&self) -> usize { self.0.channel_count() }
+		fn sample_rate(&self) -> u32 { self.0.sample_rate() }
+	}
+
+	impl PcmSource for UnknownLength {
+		fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, pcm_io::Error> {
+			self.0.read(buf, sample_count)
+		}
+
+		fn sample_count(&self) -> usize { 0 }
+	}
 
-		let slices = sample_buf[off..len].chunks_exact(frame.slice_width);
-		let excess = slices.remainder();
-		for slice in slices {
-			if frame.complete() { break }
+	#[test]
+	fn backfill_sample_count() {
+		const SAMPLES: usize = FRAME_LEN + 300;
 
-			self.enc_slice::<Scaler>(slice, channels as usize, lms)?;
-			frame.slice_index += 1;
-			consumed += SLICE_LEN;
+		let data: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 29 % 5000 - 2500) as i16).collect();
+		let mut source = UnknownLength(PcmFrame::from_interleaved(data.clone(), 44100, 1));
+		let mut enc = Encoder::new_fixed(FRAME_LEN * 4, 44100, 1, Cursor::new(Vec::new())).unwrap();
+		enc.encode(&mut source).unwrap();
+		let (sink, summary) = enc.finish().unwrap();
+		assert_eq!(summary.samples_encoded, SAMPLES);
+
+		let bytes = sink.into_inner();
+		assert_eq!(QoaHeader::try_from(&*bytes).unwrap().sample_count, SAMPLES as u32);
+
+		let mut decoded = Vec::new();
+		byte_decoder::Decoder::default().decode(&bytes, &mut decoded).unwrap();
+		assert_eq!(decoded.len(), SAMPLES);
+
+		// Buffers are rewritten in place.
+		let mut enc = Encoder::new_fixed(FRAME_LEN * 4, 44100, 1, Buffer::default()).unwrap();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		assert_eq!(enc.close().unwrap().unwrap().encode(), bytes);
+	}
+
+	/// A writer tracking the most bytes written between flushes.
+	#[derive(Debug)]
+	struct Tracked<W: Write> {
+		inner: W,
+		pending: usize,
+		peak: usize,
+	}
+
+	impl<W: Write> Write for Tracked<W> {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			let n = self.inner.write(buf)?;
+			self.pending += n;
+			self.peak = self.peak.max(self.pending);
+			Ok(n)
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			self.pending = 0;
+			self.inner.flush()
 		}
+	}
+
+	#[test]
+	fn encode_to_file() {
+		const SAMPLES: usize = FRAME_LEN * 4 + 100;
 
-		frame.buffer.write_interleaved(excess).unwrap();
+		let data: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 23 % 6000 - 3000) as i16).collect();
+		let path = std::env::temp_dir().join(format!("qoar-encode-to-file-{}.qoa", std::process::id()));
 
-		if frame.complete() {
-			frame.reset();
+		let file = Tracked { inner: File::create(&path).unwrap(), pending: 0, peak: 0 };
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, BufWriter::new(file)).unwrap();
+		for chunk in data.chunks(FRAME_LEN) {
+			enc.encode_interleaved(chunk, StreamDescriptor::default()).unwrap();
 		}
+		let file = enc.close().unwrap().unwrap().into_inner().unwrap();
 
-		Ok(consumed)
+		// The file header and at most one frame are written before each flush.
+		assert!(file.peak <= 8 + 8 + 16 + 256 * 8, "peak of {} bytes", file.peak);
+
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		let expected = enc.close().unwrap().unwrap().encode();
+
+		let actual = read(&path).unwrap();
+		remove_file(&path).unwrap();
+		assert_eq!(actual, expected);
 	}
-}
 
-impl<S: SinkStream> QoaSink for S { }
+	#[test]
+	fn checksums() {
+		const SAMPLES: usize = FRAME_LEN * 2 + 500;
+		const FRAME_SIZE: usize = 8 + 16 + 256 * 8;
+
+		let data: Vec<i16> = (0..SAMPLES as i32).map(|i| (i * 41 % 7000 - 3500) as i16).collect();
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 1, Buffer::default()).unwrap();
+		enc.set_checksums(true);
+		enc.encode_interleaved(&data, StreamDescriptor::default()).unwrap();
+		let mut data = enc.close().unwrap().unwrap().encode();
+
+		let decode = |data: &[u8], verify| {
+			let mut decoder = byte_decoder::Decoder::default();
+			decoder.verify_checksums(verify);
+			decoder.decode(data, &mut Vec::new())
+		};
+		assert_eq!(decode(&data, true).unwrap(), data.len());
+
+		// Flip a bit in the slice data of the second frame.
+		data[8 + FRAME_SIZE + 100] ^= 1;
+		assert!(decode(&data, false).is_ok());
+		assert!(matches!(decode(&data, true), Err(byte_decoder::Error::ChecksumMismatch(1))));
+	}
+
+	#[test]
+	fn pinned_descriptor() {
+		let mut enc = Encoder::new_streaming(Buffer::default());
+		enc.set_sample_rate(48000).unwrap();
+		enc.set_channels(1).unwrap();
+
+		let mut source = PcmFrame::from_interleaved(vec![0; 100], 44100, 1);
+		assert!(matches!(enc.encode(&mut source), Err(EncodeError::InvalidDescriptorChange)));
+
+		let mut source = PcmFrame::from_interleaved(vec![0; 100], 48000, 1);
+		enc.encode(&mut source).unwrap();
+		enc.flush().unwrap();
+		assert!(matches!(enc.set_sample_rate(44100), Err(EncodeError::InvalidDescriptorChange)));
+
+		let mut fixed = Encoder::new_fixed(100, 44100, 1, Buffer::default()).unwrap();
+		assert!(matches!(fixed.set_channels(2), Err(EncodeError::InvalidDescriptorChange)));
+	}
+}
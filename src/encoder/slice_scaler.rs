@@ -12,13 +12,55 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{DEQUANT_TABLE, div, QoaLmsState, QUANT_TABLE, SLICE_LEN};
+use std::array;
+use crate::{DEQUANT_TABLE, div, QoaLmsState, QUANT_TABLE, reconstruct, SLICE_LEN};
+use crate::tables::SF_TABLE;
 
 #[cfg(feature = "simd")]
 pub use simd::VectorScaler;
 
 pub trait SliceScaler {
 	fn scale(samples: &[i16], lms: &mut QoaLmsState, chn: usize, channel_count: usize) -> u64;
+
+	/// Scales a slice like [`Self::scale`], adding `noise` to each residual before
+	/// quantization. The noise is in 16.16 fixed-point fractions of the scale. By
+	/// default, this falls back to the [`LinearScaler`].
+	fn scale_dithered(
+		samples: &[i16],
+		lms: &mut QoaLmsState,
+		chn: usize,
+		channel_count: usize,
+		noise: &[i32; SLICE_LEN]
+	) -> u64 {
+		LinearScaler::scale_with(samples, lms, chn, channel_count, noise)
+	}
+}
+
+/// A deterministic source of triangular (TPDF) dither noise, spanning one
+/// quantization step either way.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Dither(u32);
+
+impl Dither {
+	/// Creates a noise source for channel `chn`.
+	pub fn new(chn: usize) -> Self {
+		Self((chn as u32).wrapping_mul(0x9E3779B9) | 1)
+	}
+
+	/// Returns the noise for one slice, in 16.16 fixed-point fractions of the
+	/// scale.
+	pub fn slice_noise(&mut self) -> [i32; SLICE_LEN] {
+		array::from_fn(|_| self.next() - self.next())
+	}
+
+	/// Returns a uniform value in `[0,65536)`, with a xorshift generator.
+	fn next(&mut self) -> i32 {
+		let Self(ref mut x) = self;
+		*x ^= *x << 13;
+		*x ^= *x >> 17;
+		*x ^= *x << 5;
+		(*x >> 16) as i32
+	}
 }
 
 /// A linear scaler, the method the reference encoder uses. Computes the error for
@@ -26,23 +68,29 @@ pub trait SliceScaler {
 pub struct LinearScaler;
 
 impl LinearScaler {
-	fn scale_sample(sample: i32, sf: usize, lms: &mut QoaLmsState) -> (u8, i32, i16) {
+	fn scale_sample(sample: i32, sf: usize, noise: i32, lms: &mut QoaLmsState) -> (u8, i32, i16) {
 		let predicted = lms.predict();
 		let residual = sample - predicted;
-		let scaled = div(residual, sf);
+		let dither = ((noise as i64 * SF_TABLE[sf] as i64) >> 16) as i32;
+		let scaled = div(residual + dither, sf);
 		let clamped = scaled.clamp(-8, 8);
 		let quantized = QUANT_TABLE[(clamped + 8) as usize];
 		let dequantized = DEQUANT_TABLE[sf][quantized as usize];
-		let reconst = (predicted + dequantized).clamp(i16::MIN as i32, 32767) as i16;
+		let reconst = reconstruct(predicted, dequantized);
 
 		lms.update(reconst, dequantized);
 		(quantized, dequantized, reconst)
 	}
-}
 
-impl SliceScaler for LinearScaler {
-	fn scale(samples: &[i16], lms: &mut QoaLmsState, chn: usize, channel_count: usize) -> u64 {
-		let len = SLICE_LEN.clamp(0, samples.len());
+	/// Scales a slice, adding `noise` to each residual before quantization.
+	fn scale_with(
+		samples: &[i16],
+		lms: &mut QoaLmsState,
+		chn: usize,
+		channel_count: usize,
+		noise: &[i32; SLICE_LEN]
+	) -> u64 {
+		let len = SLICE_LEN.clamp(0, samples.len() / channel_count);
 		let rng = chn..len * channel_count + chn;
 		let (_, best_slice, best_lms) = (0..16).map(|sf| {
 			let mut lms = *lms;
@@ -50,9 +98,10 @@ impl SliceScaler for LinearScaler {
 			let error = rng.clone()
 						   .step_by(channel_count)
 						   .map(|si| samples[si])
-						   .fold(0, |acc, sample| {
+						   .zip(noise)
+						   .fold(0, |acc, (sample, &noise)| {
 							   let (quantized, _, reconst) =
-								   Self::scale_sample(sample as i32, sf, &mut lms);
+								   Self::scale_sample(sample as i32, sf, noise, &mut lms);
 
 							   slice = slice << 3 | quantized as u64;
 							   let mut error = sample as i64 - reconst as i64;
@@ -67,6 +116,12 @@ impl SliceScaler for LinearScaler {
 	}
 }
 
+impl SliceScaler for LinearScaler {
+	fn scale(samples: &[i16], lms: &mut QoaLmsState, chn: usize, channel_count: usize) -> u64 {
+		Self::scale_with(samples, lms, chn, channel_count, &[0; SLICE_LEN])
+	}
+}
+
 #[cfg(test)]
 mod test {
 	extern crate test;
@@ -83,7 +138,7 @@ mod test {
 	fn scale_sample(lms: QoaLmsState, sample: i16) {
 		for sf in 0..16 {
 			let (lin_quant, lin_dequant, lin_reconst) =
-				LinearScaler::scale_sample(sample as i32, sf, &mut lms.clone());
+				LinearScaler::scale_sample(sample as i32, sf, 0, &mut lms.clone());
 			let (quant, dequant, reconst) =
 				qoa_ref_sys::scale_sample(sample as i32, sf as i32, &mut lms.clone().into());
 			assert_eq!(lin_quant,   quant,     "quantized residual for scale factor {sf}");
@@ -103,13 +158,37 @@ mod test {
 		}
 	}
 
+	/// A slice of interleaved samples for 1, 4, 6, or 8 channels.
+	#[derive(Clone, Debug)]
+	pub struct Channels(pub Vec<i16>, pub usize);
+
+	impl Arbitrary for Channels {
+		fn arbitrary(g: &mut Gen) -> Self {
+			let channels = *g.choose(&[1, 4, 6, 8]).unwrap();
+			Self((0..20 * channels).map(|_| i16::arbitrary(g)).collect(), channels)
+		}
+	}
+
+	/// Scales each channel of `samples` with `Sc` and the reference encoder,
+	/// asserting the slices and LMS states match.
+	pub fn assert_parity<Sc: SliceScaler>(samples: &[i16], channels: usize, lms: QoaLmsState) {
+		let mut act_lms = vec![lms; channels];
+		let mut ref_lms = [lms.into(); 8];
+		for chn in 0..channels {
+			let act_slice = Sc::scale(samples, &mut act_lms[chn], chn, channels);
+			let ref_slice = scale_slice(samples, channels, &mut ref_lms, chn);
+			assert_eq!(act_lms[chn], ref_lms[chn].into(), "LMS state on channel {chn} of {channels}");
+			assert_eq!(act_slice, ref_slice, "Slice data on channel {chn} of {channels}");
+		}
+	}
+
 	#[quickcheck]
 	fn scale(Slice(ref slice): Slice, lms: QoaLmsState) {
 		let mut lin_lms = [lms; 2];
 		let mut ref_lms = [lms.into(); 8];
 		let lin_slice1 = LinearScaler::scale(slice, &mut lin_lms[0], 0, 2);
 		let lin_slice2 = LinearScaler::scale(slice, &mut lin_lms[1], 1, 2);
-		let ref_slice1 = scale_slice(slice, 1, &mut ref_lms, 0);
+		let ref_slice1 = scale_slice(slice, 2, &mut ref_lms, 0);
 		let ref_slice2 = scale_slice(slice, 2, &mut ref_lms, 1);
 		assert_eq!(lin_lms[0], ref_lms[0].into(), "LMS state on channel 0");
 		assert_eq!(lin_slice1, ref_slice1, "Slice data on channel 0");
@@ -117,6 +196,11 @@ mod test {
 		assert_eq!(lin_slice2, ref_slice2, "Slice data on channel 1");
 	}
 
+	#[quickcheck]
+	fn scale_channels(Channels(ref samples, channels): Channels, lms: QoaLmsState) {
+		assert_parity::<LinearScaler>(samples, channels, lms)
+	}
+
 	#[bench]
 	fn scale_bench(b: &mut Bencher) {
 		#[inline(never)]
@@ -156,7 +240,7 @@ mod test {
 		use qoa_ref_sys::qoa::qoa_lms_t;
 		use qoa_ref_sys::scale_slice;
 		use crate::encoder::slice_scaler::{SliceScaler, VectorScaler};
-		use crate::encoder::slice_scaler::test::Slice;
+		use crate::encoder::slice_scaler::test::{assert_parity, Channels, Slice};
 		use crate::QoaLmsState;
 		use crate::simd::LmsStateVector;
 		use test::Bencher;
@@ -185,7 +269,7 @@ mod test {
 			let mut ref_lms = [lms.into(); 8];
 			let vec_slice1 = VectorScaler::scale(slice, &mut vec_lms[0], 0, 2);
 			let vec_slice2 = VectorScaler::scale(slice, &mut vec_lms[1], 1, 2);
-			let ref_slice1 = scale_slice(slice, 1, &mut ref_lms, 0);
+			let ref_slice1 = scale_slice(slice, 2, &mut ref_lms, 0);
 			let ref_slice2 = scale_slice(slice, 2, &mut ref_lms, 1);
 			assert_eq!(vec_lms[0], ref_lms[0].into(), "LMS state on channel 0");
 			assert_eq!(vec_slice1, ref_slice1, "Slice data on channel 0");
@@ -193,6 +277,11 @@ mod test {
 			assert_eq!(vec_slice2, ref_slice2, "Slice data on channel 1");
 		}
 
+		#[quickcheck]
+		fn scale_channels(Channels(ref samples, channels): Channels, lms: QoaLmsState) {
+			assert_parity::<VectorScaler>(samples, channels, lms)
+		}
+
 		#[bench]
 		fn scale_bench(b: &mut Bencher) {
 			#[inline(never)]
@@ -215,7 +304,7 @@ mod simd {
 	use std::simd::{i32x16, SimdInt, SimdOrd, SimdUint, u8x16};
 	use crate::encoder::slice_scaler::SliceScaler;
 	use crate::{DEQUANT_TABLE, QoaLmsState, QUANT_TABLE, SLICE_LEN};
-	use crate::simd::{const_splat, div, i64x16, LmsStateVector, SimdLanes, u64x16};
+	use crate::simd::{const_splat, div, i64x16, LmsStateVector, reconstruct, SimdLanes, u64x16};
 
 	/// A SIMD vector scaler. Computes the slice for each scale factor as a vector
 	/// element, then chooses the scaled slice with the smallest error. Should be
@@ -227,8 +316,6 @@ mod simd {
 		pub(super) fn scale_sample(sample: i32x16, lms: &mut LmsStateVector) -> (i32x16, i32x16, i32x16) {
 			const SCALED_MIN: i32x16 = const_splat(-8);
 			const SCALED_MAX: i32x16 = const_splat( 8);
-			const SAMPLE_MIN: i32x16 = const_splat(-32768);
-			const SAMPLE_MAX: i32x16 = const_splat( 32767);
 
 			let prediction = lms.predict();
 			let residual = sample - prediction;
@@ -245,7 +332,7 @@ mod simd {
 				}
 				q
 			};
-			let reconst = (prediction + dequantized).simd_clamp(SAMPLE_MIN, SAMPLE_MAX);
+			let reconst = reconstruct(prediction, dequantized);
 			(quantized, dequantized, reconst)
 		}
 	}
@@ -255,7 +342,7 @@ mod simd {
 			const SFS: u64x16 = u64x16::from_array(
 				[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
 			);
-			let len = SLICE_LEN.clamp(0, samples.len());
+			let len = SLICE_LEN.clamp(0, samples.len() / channel_count);
 			let rng = chn..len * channel_count + chn;
 
 			// Create an LMS State vector for all 16 scale factors.
@@ -282,7 +369,7 @@ mod simd {
 			// Return the slice with the minimum error and assign its LMS.
 			let best_lane = cur_err.min_lane();
 			*lms = lms_vec.collapse(best_lane);
-			slice[best_lane]
+			slice[best_lane] << (SLICE_LEN - len) * 3
 		}
 	}
 }
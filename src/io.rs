@@ -14,9 +14,12 @@
 
 use std::collections::VecDeque;
 use std::error::Error;
+use std::fs::File;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use amplify_derive::Display;
 use crate::io::ReadError::Eof;
 
@@ -26,6 +29,11 @@ pub enum ReadError {
 	Io(io::Error),
 	#[display("end of stream reached prematurely")]
 	Eof,
+	/// The stream ended partway through a long, after the number of bytes given.
+	/// Unlike [`ReadError::Eof`], this never falls on a long boundary, so the
+	/// stream must have been truncated.
+	#[display("end of stream reached {0} bytes into a long")]
+	Partial(usize),
 	#[display("{0}")]
 	Other(Box<dyn Error>)
 }
@@ -34,7 +42,8 @@ impl Error for ReadError {
 	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match self {
 			Self::Io(ref err) => Some(err),
-			Eof               => None,
+			Eof |
+			Self::Partial(_)  => None,
 			Self::Other(err)  => Some(err.as_ref())
 		}
 	}
@@ -77,6 +86,22 @@ pub type WriteResult = Result<(), WriteError>;
 /// An input stream of big endian, 64-bit integers.
 pub trait SourceStream {
 	fn read_long(&mut self) -> ReadResult;
+
+	/// Reads a little endian long. By default, this swaps the bytes of a big
+	/// endian long.
+	fn read_long_le(&mut self) -> ReadResult {
+		self.read_long().map(u64::swap_bytes)
+	}
+
+	/// Reads `N` big endian longs. By default, this reads each long in turn.
+	fn read_longs<const N: usize>(&mut self) -> Result<[u64; N], ReadError> where Self: Sized {
+		let mut longs = [0; N];
+		for long in &mut longs {
+			*long = self.read_long()?
+		}
+
+		Ok(longs)
+	}
 }
 
 pub trait IntoSourceStream {
@@ -91,11 +116,53 @@ impl<S: SourceStream> IntoSourceStream for S {
 	fn into_source(self) -> Self { self }
 }
 
+/// Fallible conversion into a [`SourceStream`], for types the blanket
+/// [`IntoSourceStream`] impl can't cover. Paths open a buffered file, and byte
+/// vectors are read through a [`Cursor`].
+pub trait TryIntoSourceStream {
+	type Source: SourceStream;
+
+	fn into_source(self) -> io::Result<Self::Source>;
+}
+
+impl TryIntoSourceStream for &Path {
+	type Source = BufReader<File>;
+
+	fn into_source(self) -> io::Result<Self::Source> {
+		Ok(BufReader::new(File::open(self)?))
+	}
+}
+
+impl TryIntoSourceStream for PathBuf {
+	type Source = BufReader<File>;
+
+	fn into_source(self) -> io::Result<Self::Source> { self.as_path().into_source() }
+}
+
+impl TryIntoSourceStream for Vec<u8> {
+	type Source = Cursor<Vec<u8>>;
+
+	fn into_source(self) -> io::Result<Self::Source> { Ok(Cursor::new(self)) }
+}
+
 /// An output stream of big endian, 64-bit integers.
 pub trait SinkStream {
 	fn write_long(&mut self, value: u64) -> WriteResult;
 
+	/// Writes a little endian long. By default, this swaps the bytes before
+	/// writing a big endian long.
+	fn write_long_le(&mut self, value: u64) -> WriteResult {
+		self.write_long(value.swap_bytes())
+	}
+
 	fn flush(&mut self) -> WriteResult { Ok(()) }
+
+	/// Overwrites the long written `back` longs before the current position, where
+	/// `1` is the last long written, then returns to the current position. Returns
+	/// `false` if the stream can't seek back. By default, this returns `false`.
+	fn rewrite_long(&mut self, _back: usize, _value: u64) -> Result<bool, WriteError> {
+		Ok(false)
+	}
 }
 
 pub trait IntoSinkStream {
@@ -110,69 +177,254 @@ impl<S: SinkStream> IntoSinkStream for S {
 	fn into_sink(self) -> Self { self }
 }
 
+/// Fallible conversion into a [`SinkStream`], for types the blanket
+/// [`IntoSinkStream`] impl can't cover. Paths create or truncate a buffered
+/// file.
+pub trait TryIntoSinkStream {
+	type Sink: SinkStream;
+
+	fn into_sink(self) -> io::Result<Self::Sink>;
+}
+
+impl TryIntoSinkStream for &Path {
+	type Sink = BufWriter<File>;
+
+	fn into_sink(self) -> io::Result<Self::Sink> {
+		Ok(BufWriter::new(File::create(self)?))
+	}
+}
+
+impl TryIntoSinkStream for PathBuf {
+	type Sink = BufWriter<File>;
+
+	fn into_sink(self) -> io::Result<Self::Sink> { self.as_path().into_sink() }
+}
+
 impl<R: Read> SourceStream for R {
 	fn read_long(&mut self) -> ReadResult {
-		let mut buf = [0; 8];
-		self.read_exact(&mut buf)?;
-		Ok(u64::from_be_bytes(buf))
+		read_bytes(self).map(u64::from_be_bytes)
+	}
+
+	fn read_long_le(&mut self) -> ReadResult {
+		read_bytes(self).map(u64::from_le_bytes)
 	}
 }
 
+/// Reads the 8 bytes of a long, like [`Read::read_exact`] but telling a clean end
+/// of stream apart from one partway through the long.
+///
+/// # Errors
+///
+/// [`ReadError::Eof`]: the stream ended before any bytes were read.
+///
+/// [`ReadError::Partial`]: the stream ended after some, but not all, bytes were
+/// read.
+fn read_bytes(reader: &mut impl Read) -> Result<[u8; 8], ReadError> {
+	let mut buf = [0; 8];
+	let mut len = 0;
+	while len < buf.len() {
+		match reader.read(&mut buf[len..]) {
+			Ok(0) if len == 0 => return Err(Eof),
+			Ok(0) => return Err(ReadError::Partial(len)),
+			Ok(n) => len += n,
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => { }
+			Err(err) => return Err(err.into())
+		}
+	}
+
+	Ok(buf)
+}
+
 impl<W: Write> SinkStream for W {
 	fn write_long(&mut self, value: u64) -> WriteResult {
 		Ok(self.write_all(&value.to_be_bytes())?)
 	}
+
+	fn write_long_le(&mut self, value: u64) -> WriteResult {
+		Ok(self.write_all(&value.to_le_bytes())?)
+	}
+
+	fn flush(&mut self) -> WriteResult {
+		Ok(Write::flush(self)?)
+	}
+
+	default fn rewrite_long(&mut self, _: usize, _: u64) -> Result<bool, WriteError> {
+		Ok(false)
+	}
+}
+
+impl<W: Write + Seek> SinkStream for W {
+	fn rewrite_long(&mut self, back: usize, value: u64) -> Result<bool, WriteError> {
+		let offset = back as i64 * 8;
+		self.seek(SeekFrom::Current(-offset))?;
+		self.write_all(&value.to_be_bytes())?;
+		self.seek(SeekFrom::Current(offset - 8))?;
+		Ok(true)
+	}
 }
 
+/// A buffer of longs, read from a tracked position without consuming them. Longs
+/// are stored as native endian words of their bytes, so bytes are encoded and
+/// decoded without swapping, and only reads and writes of the other endianness
+/// swap bytes. On little endian hardware, big endian reads swap and little endian
+/// reads don't. The buffer dereferences to these words.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct Buffer(VecDeque<u64>);
+pub struct Buffer(VecDeque<u64>, usize);
 
 impl Buffer {
-	pub fn unwrap(self) -> VecDeque<u64> { self.0 }
+	/// Returns the big endian longs in the buffer.
+	pub fn unwrap(self) -> VecDeque<u64> {
+		let mut longs = self.0;
+		for long in &mut longs {
+			*long = u64::from_be(*long);
+		}
+		longs
+	}
+
+	/// Returns the index of the next long to be read.
+	pub fn position(&self) -> usize { self.1 }
+
+	/// Moves the read position to the long at `index`.
+	///
+	/// # Errors
+	///
+	/// [`ReadError::Eof`]: `index` is past the end of the buffer.
+	pub fn seek_long(&mut self, index: usize) -> Result<(), ReadError> {
+		if index > self.0.len() {
+			return Err(Eof)
+		}
+
+		self.1 = index;
+		Ok(())
+	}
 
 	pub fn encode(self) -> Vec<u8> {
 		let mut buf = Vec::with_capacity(self.len() * 8);
 
-		for val in self.unwrap() {
-			buf.extend_from_slice(&val.to_be_bytes());
+		for val in self.0 {
+			buf.extend_from_slice(&val.to_ne_bytes());
 		}
 
 		buf
 	}
 
 	pub fn decode(buf: &mut Vec<u8>) -> Self {
+		let len = buf.len() - buf.len() % 8;
+		let words = buf.drain(..len)
+					   .array_chunks::<8>()
+					   .map(u64::from_ne_bytes)
+					   .collect();
+		Self(words, 0)
+	}
+
+	/// Decodes big endian bytes into a buffer like [`Self::decode`], but fails
+	/// rather than leaving a partial long behind.
+	///
+	/// # Errors
+	///
+	/// Returns the number of trailing bytes if `buf` isn't a whole number of
+	/// longs, leaving `buf` untouched.
+	pub fn decode_checked(buf: &mut Vec<u8>) -> Result<Self, usize> {
+		match buf.len() % 8 {
+			0 => Ok(Self::decode(buf)),
+			rem => Err(rem)
+		}
+	}
+
+	/// Encodes the buffer into little endian bytes.
+	pub fn encode_le(self) -> Vec<u8> {
+		let mut buf = Vec::with_capacity(self.len() * 8);
+
+		for val in self.unwrap() {
+			buf.extend_from_slice(&val.to_le_bytes());
+		}
+
+		buf
+	}
+
+	/// Decodes little endian bytes into a buffer.
+	pub fn decode_le(buf: &mut Vec<u8>) -> Self {
 		let len = buf.len() - buf.len() % 8;
 		buf.drain(..len)
 		   .array_chunks::<8>()
-		   .map(u64::from_be_bytes)
+		   .map(u64::from_le_bytes)
 		   .collect()
 	}
+
+	fn read_word(&mut self) -> ReadResult {
+		let word = self.0.get(self.1).copied().ok_or(Eof)?;
+		self.1 += 1;
+		Ok(word)
+	}
 }
 
 impl SourceStream for Buffer {
 	fn read_long(&mut self) -> ReadResult {
-		self.pop_front().ok_or(Eof)
+		self.read_word().map(u64::from_be)
+	}
+
+	fn read_long_le(&mut self) -> ReadResult {
+		self.read_word().map(u64::from_le)
+	}
+
+	/// Reads `N` longs, or none if fewer remain.
+	fn read_longs<const N: usize>(&mut self) -> Result<[u64; N], ReadError> {
+		let end = self.1 + N;
+		if end > self.0.len() {
+			return Err(Eof)
+		}
+
+		let mut longs = [0; N];
+		for (long, &word) in longs.iter_mut().zip(self.0.range(self.1..end)) {
+			*long = u64::from_be(word);
+		}
+
+		self.1 = end;
+		Ok(longs)
 	}
 }
 
 impl SinkStream for Buffer {
 	fn write_long(&mut self, value: u64) -> WriteResult {
-		self.push_back(value);
+		self.push_back(value.to_be());
+		Ok(())
+	}
+
+	fn write_long_le(&mut self, value: u64) -> WriteResult {
+		self.push_back(value.to_le());
 		Ok(())
 	}
+
+	fn rewrite_long(&mut self, back: usize, value: u64) -> Result<bool, WriteError> {
+		let len = self.0.len();
+		if back == 0 || back > len {
+			return Ok(false)
+		}
+
+		self.0[len - back] = value.to_be();
+		Ok(true)
+	}
 }
 
+/// Creates a buffer from big endian longs.
 impl From<VecDeque<u64>> for Buffer {
-	fn from(value: VecDeque<u64>) -> Self { Buffer(value) }
+	fn from(mut value: VecDeque<u64>) -> Self {
+		for long in &mut value {
+			*long = long.to_be();
+		}
+		Buffer(value, 0)
+	}
 }
 
+/// Creates a buffer from big endian longs.
 impl From<Vec<u64>> for Buffer {
-	fn from(value: Vec<u64>) -> Self { Buffer(value.into()) }
+	fn from(value: Vec<u64>) -> Self { VecDeque::from(value).into() }
 }
 
+/// Collects big endian longs into a buffer.
 impl FromIterator<u64> for Buffer {
 	fn from_iter<T: IntoIterator<Item = u64>>(iter: T) -> Self {
-		Self(iter.into_iter().collect())
+		Self(iter.into_iter().map(u64::to_be).collect(), 0)
 	}
 }
 
@@ -185,3 +437,402 @@ impl Deref for Buffer {
 impl DerefMut for Buffer {
 	fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
 }
+
+/// A [`SourceStream`] reading from shared bytes at a tracked position, rather than
+/// reslicing. Defaults to an [`Arc<[u8]>`], but any owned byte container can be
+/// used.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CursorSource<T: AsRef<[u8]> = Arc<[u8]>> {
+	data: T,
+	pos: usize,
+}
+
+impl<T: AsRef<[u8]>> CursorSource<T> {
+	/// Creates a new cursor at the start of `data`.
+	pub fn new(data: T) -> Self {
+		Self { data, pos: 0 }
+	}
+
+	/// Returns the byte position of the cursor.
+	pub fn position(&self) -> usize { self.pos }
+
+	/// Returns the number of bytes remaining after the cursor.
+	pub fn remaining(&self) -> usize { self.data.as_ref().len() - self.pos }
+
+	/// Moves the cursor to byte position `pos`.
+	///
+	/// # Errors
+	///
+	/// [`ReadError::Eof`]: `pos` is past the end of the data.
+	pub fn seek(&mut self, pos: usize) -> Result<(), ReadError> {
+		if pos > self.data.as_ref().len() {
+			return Err(Eof)
+		}
+
+		self.pos = pos;
+		Ok(())
+	}
+
+	/// Returns the underlying data.
+	pub fn unwrap(self) -> T { self.data }
+}
+
+impl<T: AsRef<[u8]>> CursorSource<T> {
+	/// Reads the 8 bytes of a long, leaving the cursor in place if fewer remain.
+	fn read_bytes(&mut self) -> Result<[u8; 8], ReadError> {
+		match self.remaining() {
+			0 => return Err(Eof),
+			rem @ 1..=7 => return Err(ReadError::Partial(rem)),
+			_ => { }
+		}
+
+		let end = self.pos + 8;
+		let bytes = self.data.as_ref()[self.pos..end].try_into().unwrap();
+		self.pos = end;
+		Ok(bytes)
+	}
+}
+
+impl<T: AsRef<[u8]>> SourceStream for CursorSource<T> {
+	fn read_long(&mut self) -> ReadResult {
+		self.read_bytes().map(u64::from_be_bytes)
+	}
+
+	fn read_long_le(&mut self) -> ReadResult {
+		self.read_bytes().map(u64::from_le_bytes)
+	}
+}
+
+impl<T: AsRef<[u8]>> From<T> for CursorSource<T> {
+	fn from(value: T) -> Self { Self::new(value) }
+}
+
+/// A QOA file mapped into memory, read as bytes by the
+/// [`byte_decoder`](crate::byte_decoder) or as a [`SourceStream`] through
+/// [`CursorSource`], without reading the file into memory first.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedFile(memmap2::Mmap);
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+	/// Maps the file at `path` into memory, read-only.
+	///
+	/// # Safety
+	///
+	/// The file must not be modified or truncated while mapped, by this or any
+	/// other process. The mapped bytes change with the file, so a write while
+	/// decoding is a data race, and truncation can fault on access.
+	pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		let file = File::open(path)?;
+		memmap2::Mmap::map(&file).map(Self)
+	}
+
+	/// Returns a [`SourceStream`] reading the mapped bytes.
+	pub fn source(&self) -> CursorSource<&[u8]> { CursorSource::new(&self.0[..]) }
+}
+
+#[cfg(feature = "mmap")]
+impl Deref for MappedFile {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] { &self.0 }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for MappedFile {
+	fn as_ref(&self) -> &[u8] { &self.0 }
+}
+
+/// A [`SinkStream`] writing every long to two sinks, in order. Writing stops at
+/// the first error, so the second sink may be one long behind the first. Longs
+/// can't be rewritten, since one sink could succeed where the other fails.
+#[derive(Clone, Debug, Default)]
+pub struct TeeSink<A: SinkStream, B: SinkStream> {
+	first: A,
+	second: B,
+}
+
+impl<A: SinkStream, B: SinkStream> TeeSink<A, B> {
+	/// Creates a new sink writing to `first`, then `second`.
+	pub fn new(first: A, second: B) -> Self {
+		Self { first, second }
+	}
+
+	/// Returns a reference to the first sink.
+	pub fn first(&self) -> &A { &self.first }
+
+	/// Returns a reference to the second sink.
+	pub fn second(&self) -> &B { &self.second }
+
+	/// Returns both sinks.
+	pub fn into_inner(self) -> (A, B) { (self.first, self.second) }
+}
+
+impl<A: SinkStream, B: SinkStream> SinkStream for TeeSink<A, B> {
+	fn write_long(&mut self, value: u64) -> WriteResult {
+		self.first.write_long(value)?;
+		self.second.write_long(value)
+	}
+
+	fn write_long_le(&mut self, value: u64) -> WriteResult {
+		self.first.write_long_le(value)?;
+		self.second.write_long_le(value)
+	}
+
+	fn flush(&mut self) -> WriteResult {
+		self.first.flush()?;
+		self.second.flush()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	extern crate test;
+
+	use std::assert_matches::assert_matches;
+	use std::sync::Arc;
+	use test::{Bencher, black_box};
+	use std::env::temp_dir;
+	use std::fs::{read, remove_file, write};
+	use crate::{Encoder, FRAME_LEN, PcmBuffer, PcmFrame, StreamDescriptor};
+	use crate::decoder::Decoder;
+	use crate::util::Crc32;
+	use super::{Buffer, CursorSource, ReadError, ReadResult, SinkStream, SourceStream, TeeSink, TryIntoSinkStream, TryIntoSourceStream, WriteResult};
+
+	#[test]
+	fn codec_long_le() {
+		let mut bytes = Vec::new();
+		bytes.write_long_le(0x0102030405060708).unwrap();
+		assert_eq!(bytes, [8, 7, 6, 5, 4, 3, 2, 1]);
+		assert_eq!((&*bytes).read_long_le().unwrap(), 0x0102030405060708);
+		assert_eq!(CursorSource::new(bytes.clone()).read_long_le().unwrap(), 0x0102030405060708);
+
+		let mut buf = Buffer::default();
+		buf.write_long_le(0x0102030405060708).unwrap();
+		assert_eq!(buf.clone().encode(), bytes);
+		assert_eq!(buf.read_long_le().unwrap(), 0x0102030405060708);
+
+		// Words are the native endian bytes, read either way.
+		let mut buf = Buffer::decode(&mut bytes.clone());
+		assert_eq!(*buf, [u64::from_ne_bytes(bytes.try_into().unwrap())]);
+		assert_eq!(buf.read_long_le().unwrap(), 0x0102030405060708);
+		buf.seek_long(0).unwrap();
+		assert_eq!(buf.read_long().unwrap(), 0x0807060504030201);
+		buf.rewrite_long(1, 0x0102030405060708).unwrap();
+		assert_eq!(buf.encode(), [1, 2, 3, 4, 5, 6, 7, 8]);
+	}
+
+	fn bench_data() -> Vec<u8> {
+		(0..8 * 4096).map(|i| i as u8).collect()
+	}
+
+	#[bench]
+	fn read_long_bench(b: &mut Bencher) {
+		let data = bench_data();
+		b.iter(|| {
+			let mut source = &*data;
+			while let Ok(value) = source.read_long() {
+				black_box(value);
+			}
+		})
+	}
+
+	#[bench]
+	fn read_long_le_bench(b: &mut Bencher) {
+		let data = bench_data();
+		b.iter(|| {
+			let mut source = &*data;
+			while let Ok(value) = source.read_long_le() {
+				black_box(value);
+			}
+		})
+	}
+
+	/// Encodes a stereo stream into a buffer, for decode benchmarks.
+	fn bench_stream() -> Buffer {
+		const LEN: usize = FRAME_LEN * 16;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 31 % 16000) as i16 - 8000).collect();
+		let mut enc = Encoder::new_fixed(LEN, 44100, 2, Buffer::default()).unwrap();
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		enc.close().unwrap().unwrap()
+	}
+
+	/// A source reading big endian longs from a buffer of little endian bytes, to
+	/// decode without swapping on little endian hardware.
+	struct LeSource(Buffer);
+
+	impl SourceStream for LeSource {
+		fn read_long(&mut self) -> ReadResult { self.0.read_long_le() }
+	}
+
+	#[bench]
+	fn decode_bench(b: &mut Bencher) {
+		let mut source = bench_stream();
+		b.iter(|| {
+			source.seek_long(0).unwrap();
+			black_box(Decoder::new(PcmBuffer::default()).decode(&mut source).unwrap());
+		})
+	}
+
+	#[bench]
+	fn decode_le_bench(b: &mut Bencher) {
+		let mut source = LeSource(Buffer::decode(&mut bench_stream().encode_le()));
+		b.iter(|| {
+			source.0.seek_long(0).unwrap();
+			black_box(Decoder::new(PcmBuffer::default()).decode(&mut source).unwrap());
+		})
+	}
+
+	#[test]
+	fn decode_le() {
+		fn decode(source: &mut impl SourceStream) -> Vec<i16> {
+			Decoder::new(PcmBuffer::default()).decode(source)
+											  .unwrap()
+											  .unwrap()
+											  .into_iter()
+											  .flat_map(PcmFrame::unwrap)
+											  .collect()
+		}
+
+		let stream = bench_stream();
+		let expected = decode(&mut stream.clone());
+		assert_eq!(decode(&mut LeSource(Buffer::decode(&mut stream.encode_le()))), expected);
+	}
+
+	#[test]
+	fn path_source() {
+		let path = temp_dir().join(format!("qoar-path-source-{}.bin", std::process::id()));
+		write(&path, [0, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+		let mut source = path.clone().into_source().unwrap();
+		assert_eq!(source.read_long().unwrap(), 0x0001020304050607);
+		assert_matches!(source.read_long(), Err(ReadError::Partial(1)));
+		drop(source);
+		remove_file(&path).unwrap();
+
+		assert!(path.into_source().is_err());
+	}
+
+	#[test]
+	fn path_sink() {
+		let path = temp_dir().join(format!("qoar-path-sink-{}.bin", std::process::id()));
+		let mut sink = path.as_path().into_sink().unwrap();
+		sink.write_long(0x0102030405060708).unwrap();
+		SinkStream::flush(&mut sink).unwrap();
+		drop(sink);
+
+		let bytes = read(&path).unwrap();
+		remove_file(&path).unwrap();
+		assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 7, 8]);
+	}
+
+	#[test]
+	fn vec_source() {
+		let mut source = vec![8, 7, 6, 5, 4, 3, 2, 1].into_source().unwrap();
+		assert_eq!(source.read_long().unwrap(), 0x0807060504030201);
+		assert_matches!(source.read_long(), Err(ReadError::Eof));
+	}
+
+	#[test]
+	fn cursor_position() {
+		let data: Arc<[u8]> = (0..20).collect();
+		let mut cursor = CursorSource::new(data);
+
+		assert_eq!(cursor.read_long().unwrap(), 0x0001020304050607);
+		assert_eq!(cursor.position(), 8);
+		assert_eq!(cursor.remaining(), 12);
+
+		cursor.seek(12).unwrap();
+		assert_eq!(cursor.read_long().unwrap(), 0x0C0D0E0F10111213);
+		assert_eq!(cursor.remaining(), 0);
+		assert_matches!(cursor.read_long(), Err(ReadError::Eof));
+		assert_eq!(cursor.position(), 20);
+	}
+
+	#[test]
+	fn cursor_seek() {
+		let mut cursor = CursorSource::new(vec![0u8; 16]);
+		assert_matches!(cursor.seek(17), Err(ReadError::Eof));
+		assert_eq!(cursor.position(), 0);
+
+		cursor.seek(9).unwrap();
+		assert_matches!(cursor.read_long(), Err(ReadError::Partial(7)));
+		assert_eq!(cursor.position(), 9);
+	}
+
+	#[test]
+	fn buffer_seek() {
+		let mut buf = Buffer::from(vec![0x716F616600000100, 1, 2, 3]);
+		assert_eq!(buf.read_long().unwrap(), 0x716F616600000100);
+		assert_eq!(buf.read_long().unwrap(), 1);
+		assert_eq!(buf.position(), 2);
+
+		buf.seek_long(0).unwrap();
+		assert_eq!(buf.read_long().unwrap(), 0x716F616600000100);
+
+		assert_matches!(buf.seek_long(5), Err(ReadError::Eof));
+		buf.seek_long(4).unwrap();
+		assert_matches!(buf.read_long(), Err(ReadError::Eof));
+		assert_eq!(buf.position(), 4);
+	}
+
+	#[derive(Default)]
+	struct CrcSink(Crc32);
+
+	impl SinkStream for CrcSink {
+		fn write_long(&mut self, value: u64) -> WriteResult {
+			self.0.update(&value.to_be_bytes());
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn tee() {
+		let longs: Vec<u64> = (0..64).map(|i| i * 0x0101010101010101).collect();
+		let mut tee = TeeSink::new(Buffer::default(), CrcSink::default());
+		for &long in &longs {
+			tee.write_long(long).unwrap();
+		}
+		tee.flush().unwrap();
+		assert_eq!(tee.rewrite_long(1, 0).unwrap(), false);
+
+		let (buf, crc) = tee.into_inner();
+		assert_eq!(buf.clone().unwrap(), longs);
+		assert_eq!(crc.0.finish(), Crc32::checksum(&buf.encode()));
+	}
+
+	#[test]
+	fn buffer_read_longs() {
+		let mut buf: Buffer = (1..=5).collect();
+		assert_eq!(buf.read_long().unwrap(), 1);
+		assert_eq!(buf.read_longs::<3>().unwrap(), [2, 3, 4]);
+
+		// A batch past the end reads nothing.
+		assert_matches!(buf.read_longs::<2>(), Err(ReadError::Eof));
+		assert_eq!(buf.read_longs::<1>().unwrap(), [5]);
+		assert_eq!(buf.read_longs::<0>().unwrap(), [0u64; 0]);
+		assert_matches!(buf.read_long(), Err(ReadError::Eof));
+
+		// The default reads each long in turn.
+		let mut bytes: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 7];
+		assert_eq!(bytes.read_longs::<2>().unwrap(), [6, 7]);
+	}
+
+	#[test]
+	fn buffer_decode_partial() {
+		let mut bytes = vec![0u8; 21];
+		bytes[..8].copy_from_slice(&1u64.to_be_bytes());
+		assert_eq!(Buffer::decode_checked(&mut bytes), Err(5));
+		assert_eq!(bytes.len(), 21);
+
+		let buf = Buffer::decode(&mut bytes);
+		assert_eq!(buf.unwrap(), [1, 0]);
+		assert_eq!(bytes.len(), 5);
+
+		let mut bytes = 2u64.to_be_bytes().to_vec();
+		assert_eq!(Buffer::decode_checked(&mut bytes).unwrap().unwrap(), [2]);
+		assert!(bytes.is_empty());
+	}
+}
@@ -14,6 +14,7 @@
 
 //! See the draft spec: https://qoaformat.org/qoa-specification-draft-01.pdf
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(incomplete_features)]
 #![feature(
 	assert_matches,
@@ -27,31 +28,72 @@
 	slice_flatten,
 	specialization,
 )]
-#![cfg(feature = "simd")]
-#![feature(portable_simd)]
-#![cfg(test)]
-#![feature(test)]
-
-use std::cmp::min;
-use amplify_derive::{Display, Error};
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+#![cfg_attr(test, feature(test))]
 
+extern crate alloc;
+#[cfg(test)]
+extern crate self as qoar;
+
+use core::cmp::min;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use amplify_derive::Display;
+use tables::DEQUANT_TABLE;
+#[cfg(feature = "std")]
+use tables::{QUANT_TABLE, RECIP_TABLE};
+#[cfg(feature = "std")]
+use amplify_derive::Error;
+
+#[cfg(feature = "std")]
 pub use encoder::*;
+#[cfg(feature = "std")]
+pub use decoder::{DecodeError, DecodeWriteKind, Decoder};
+#[cfg(feature = "std")]
 pub use decoder::bytes as byte_decoder;
+pub use byte_decoder::QoaHeader;
+#[cfg(feature = "std")]
 pub use pcm_io::*;
+#[cfg(feature = "std")]
+pub use resample::*;
 
 #[cfg(feature = "conv")]
 pub mod conv;
+#[cfg(feature = "std")]
 mod pcm_io;
+#[cfg(feature = "std")]
+mod resample;
+#[cfg(feature = "std")]
 mod encoder;
+#[cfg(feature = "std")]
 mod decoder;
+#[cfg(not(feature = "std"))]
+#[path = "decoder/bytes.rs"]
+pub mod byte_decoder;
+#[cfg(feature = "std")]
 pub mod io;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod wav;
+#[cfg(feature = "ring")]
+pub mod ring;
+pub mod tables;
+#[cfg(feature = "std")]
+pub mod testing;
 mod util;
+#[cfg(feature = "std")]
 mod simd;
+#[cfg(all(test, feature = "std"))]
+mod reference;
 
-#[derive(Copy, Clone, Debug, Display, Error)]
+#[derive(Copy, Clone, Debug, Display)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum DescriptorError {
 	#[display("sample rate {0} is outside the range the accepted range, [1,2^24)")]
 	UnsupportedRate(u32),
+	#[display("QOA streams must have a non-zero sample rate")]
+	ZeroRate,
 	#[display("QOA streams must have at least one channel")]
 	NoChannels,
 	#[display("QOA streams must have at least one sample")]
@@ -60,9 +102,13 @@ pub enum DescriptorError {
 	TooManyChannels(usize),
 	#[display("QOA streams are limited to 2^32-1 samples, but was {0}")]
 	TooManySamples(usize),
+	#[display("channel {0} has {1} samples, but channel 0 has {2}")]
+	RaggedChannels(usize, usize, usize),
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "SerdeDescriptor"))]
 pub struct StreamDescriptor {
 	/// The number of samples per channel.
 	sample_count: Option<usize>,
@@ -77,42 +123,80 @@ impl StreamDescriptor {
 	///
 	/// # Errors
 	///
-	/// [`DescriptorError::UnsupportedRate`]: `sample_rate` is outside the range
-	/// `[1,2^24)`.
-	///
-	/// [`DescriptorError::NoChannels`]: `channel_count` is `0`.
-	///
-	/// [`DescriptorError::NoSamples`]: `sample_count` is `0`.
+	/// Any error returned by [`StreamDescriptor::validate`] for the fields.
 	fn new(
 		sample_count: Option<usize>,
 		sample_rate: Option<u32>,
 		channel_count: Option<usize>,
 	) -> Result<Self, DescriptorError> {
-		if let Some(rate) = sample_rate {
-			if !(1..16777216).contains(&rate) {
-				return Err(DescriptorError::UnsupportedRate(rate))
-			}
-		}
+		let desc = Self {
+			sample_count,
+			sample_rate,
+			channel_count,
+		};
+		desc.validate()?;
+		Ok(desc)
+	}
 
-		if let Some(_s @ 0) = sample_count {
-			return Err(DescriptorError::NoSamples)
+	/// Checks the fields present in this descriptor, such as one assembled with
+	/// the `suggest_*` methods. Omitted fields are not checked.
+	///
+	/// # Errors
+	///
+	/// [`DescriptorError::ZeroRate`]: `sample_rate` is `0`. An unknown rate must
+	/// be omitted instead.
+	///
+	/// [`DescriptorError::UnsupportedRate`]: `sample_rate` is `2^24` or greater.
+	///
+	/// [`DescriptorError::NoSamples`]: `sample_count` is `0`.
+	///
+	/// [`DescriptorError::NoChannels`]: `channel_count` is `0`.
+	///
+	/// [`DescriptorError::TooManyChannels`]: `channel_count` is greater than `255`.
+	///
+	/// [`DescriptorError::TooManySamples`]: `sample_count` is greater than `2^32-1`.
+	pub fn validate(&self) -> Result<(), DescriptorError> {
+		match self.sample_rate {
+			Some(0) => return Err(DescriptorError::ZeroRate),
+			Some(rate @ 16777216..) => return Err(DescriptorError::UnsupportedRate(rate)),
+			_ => { }
 		}
 
-		if let Some(_c @ 0) = channel_count {
-			return Err(DescriptorError::NoChannels)
+		match self.sample_count {
+			Some(0) => return Err(DescriptorError::NoSamples),
+			Some(samples) if samples > u32::MAX as usize =>
+				return Err(DescriptorError::TooManySamples(samples)),
+			_ => { }
 		}
 
-		Ok(Self {
-			sample_count,
-			sample_rate,
-			channel_count,
-		})
+		match self.channel_count {
+			Some(0) => Err(DescriptorError::NoChannels),
+			Some(chn @ 256..) => Err(DescriptorError::TooManyChannels(chn)),
+			_ => Ok(())
+		}
 	}
 
+	/// Returns `true` if [`StreamDescriptor::validate`] succeeds.
+	pub fn is_valid(&self) -> bool { self.validate().is_ok() }
+
 	pub fn samples(&self) -> Option<usize> { self.sample_count }
 	pub fn rate(&self) -> Option<u32> { self.sample_rate }
 	pub fn channels(&self) -> Option<usize> { self.channel_count }
 
+	/// Merges this descriptor with `other`, keeping the fields of this descriptor
+	/// and filling omitted fields from `other`.
+	///
+	/// # Errors
+	///
+	/// Any error returned by [`StreamDescriptor::validate`] for the merged fields.
+	pub fn merge(self, other: Self) -> Result<Self, DescriptorError> {
+		Self::new(
+			self.sample_count .or(other.sample_count ),
+			self.sample_rate  .or(other.sample_rate  ),
+			self.channel_count.or(other.channel_count),
+		)
+	}
+
 	pub fn suggest_sample_count(&mut self, sample_count: usize) {
 		let samples = self.sample_count.get_or_insert(sample_count);
 		*samples = min(*samples, sample_count);
@@ -126,10 +210,12 @@ impl StreamDescriptor {
 		let _ = self.channel_count.get_or_insert(channel_count);
 	}
 	
+	#[cfg(feature = "std")]
 	fn is_streaming(&self) -> bool {
 		self.sample_count.is_none()
 	}
 
+	#[cfg(feature = "std")]
 	fn unwrap_all(self) -> (usize, u32, usize) {
 		let Self { sample_count, sample_rate, channel_count } = self;
 
@@ -140,6 +226,7 @@ impl StreamDescriptor {
 		)
 	}
 
+	#[cfg(feature = "std")]
 	fn set<T: Copy>(option: &mut Option<T>, fallback: &Option<T>)  {
 		if option.is_none() {
 			if let Some(value) = fallback {
@@ -148,7 +235,8 @@ impl StreamDescriptor {
 		}
 	}
 
-	pub(crate) fn infer_from_vec(&mut self, vec: &Vec<i16>, fallback: &Self) {
+	#[cfg(feature = "std")]
+	pub(crate) fn infer_from_vec(&mut self, vec: &[i16], fallback: &Self) {
 		self.infer(fallback);
 
 		if let Some(samples) = self.sample_count.as_mut() {
@@ -163,18 +251,52 @@ impl StreamDescriptor {
 		}
 	}
 
+	/// Sets the channel count from per-channel `channels` and infers the sample
+	/// count from their length, after filling omitted fields from `fallback`.
+	///
+	/// # Errors
+	///
+	/// [`DescriptorError::RaggedChannels`]: the channels differ in length.
+	#[cfg(feature = "std")]
+	pub(crate) fn infer_from_planar(&mut self, channels: &[Vec<i16>], fallback: &Self) -> Result<(), DescriptorError> {
+		self.infer(fallback);
+
+		let Some(len) = channels.first().map(Vec::len) else { return Ok(()) };
+		if let Some((chn, ragged)) = channels.iter().enumerate().find(|(_, chn)| chn.len() != len) {
+			return Err(DescriptorError::RaggedChannels(chn, ragged.len(), len))
+		}
+
+		self.channel_count = Some(channels.len());
+		let samples = self.sample_count.get_or_insert(len);
+		*samples = min(*samples, len);
+		Ok(())
+	}
+
+	#[cfg(feature = "std")]
 	pub(crate) fn infer(&mut self, fallback: &Self) {
 		Self::set(&mut self.sample_count,  &fallback.sample_count );
 		Self::set(&mut self.sample_rate,   &fallback.sample_rate  );
 		Self::set(&mut self.channel_count, &fallback.channel_count);
+	}
+}
 
-		if let Some(_s @ 0) = self.sample_rate {
-			self.sample_rate = None;
-		}
+/// The serialized form of [`StreamDescriptor`], checked by
+/// [`StreamDescriptor::validate`] when deserializing.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SerdeDescriptor {
+	sample_count: Option<usize>,
+	sample_rate: Option<u32>,
+	channel_count: Option<usize>,
+}
 
-		if let Some(_c @ 0) = self.sample_rate {
-			self.channel_count = None;
-		}
+#[cfg(feature = "serde")]
+impl TryFrom<SerdeDescriptor> for StreamDescriptor {
+	type Error = DescriptorError;
+
+	fn try_from(value: SerdeDescriptor) -> Result<Self, DescriptorError> {
+		let SerdeDescriptor { sample_count, sample_rate, channel_count } = value;
+		Self::new(sample_count, sample_rate, channel_count)
 	}
 }
 
@@ -188,14 +310,18 @@ impl Default for StreamDescriptor {
 	}
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct QoaLmsState {
 	history: [i32; 4],
 	weights: [i32; 4],
 }
 
+/// An unpacked slice: a scale factor and 20 quantized residuals, from one
+/// channel.
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
-struct QoaSlice {
+pub struct QoaSlice {
 	quant: u8,
 	resid: [u8; 20],
 }
@@ -203,57 +329,68 @@ struct QoaSlice {
 // Codec
 
 const MAGIC: u32 = u32::from_be_bytes(*b"qoaf");
+/// The magic of the loop point trailer, an extension written after the final
+/// frame: the magic and payload size, then the loop start and end sample offsets
+/// as 32-bit values.
+const LOOP_MAGIC: u32 = u32::from_be_bytes(*b"qoal");
+/// The magic of the checksum trailer, an extension written after the final frame:
+/// the magic and payload size, then the CRC-32 of each frame, packed two per long.
+const CHECKSUM_MAGIC: u32 = u32::from_be_bytes(*b"qoac");
+/// The magic of the metadata trailer, an extension written after the final frame:
+/// the magic and payload size, then UTF-8 key-value tags, each string prefixed by
+/// its 32-bit length.
+const META_MAGIC: u32 = u32::from_be_bytes(*b"qoam");
 
 const SLICE_LEN: usize = 20;
 const FRAME_LEN: usize = SLICE_LEN * 256;
 
-static QUANT_TABLE: [u8; 17] = [
-	7, 7, 7, 5, 5, 3, 3, 1,
-	0,
-	0, 2, 2, 4, 4, 6, 6, 6
-];
-
-static SF_TABLE: [i32; 16] = [
-	1, 7, 21, 45, 84, 138, 211, 304, 421, 562, 731, 928, 1157, 1419, 1715, 2048
-];
-
-const RECIP_TABLE: [i64; 16] = [
-	65536, 9363, 3121, 1457, 781, 475, 311, 216, 156, 117, 90, 71, 57, 47, 39, 32
-];
-
-static DEQUANT_TABLE: [[i32; 8]; 16] = [
-	[   1,    -1,    3,    -3,    5,    -5,     7,     -7],
-	[   5,    -5,   18,   -18,   32,   -32,    49,    -49],
-	[  16,   -16,   53,   -53,   95,   -95,   147,   -147],
-	[  34,   -34,  113,  -113,  203,  -203,   315,   -315],
-	[  63,   -63,  210,  -210,  378,  -378,   588,   -588],
-	[ 104,  -104,  345,  -345,  621,  -621,   966,   -966],
-	[ 158,  -158,  528,  -528,  950,  -950,  1477,  -1477],
-	[ 228,  -228,  760,  -760, 1368, -1368,  2128,  -2128],
-	[ 316,  -316, 1053, -1053, 1895, -1895,  2947,  -2947],
-	[ 422,  -422, 1405, -1405, 2529, -2529,  3934,  -3934],
-	[ 548,  -548, 1828, -1828, 3290, -3290,  5117,  -5117],
-	[ 696,  -696, 2320, -2320, 4176, -4176,  6496,  -6496],
-	[ 868,  -868, 2893, -2893, 5207, -5207,  8099,  -8099],
-	[1064, -1064, 3548, -3548, 6386, -6386,  9933,  -9933],
-	[1286, -1286, 4288, -4288, 7718, -7718, 12005, -12005],
-	[1536, -1536, 5120, -5120, 9216, -9216, 14336, -14336],
-];
+/// Reconstructs a sample from its LMS prediction and dequantized residual,
+/// clamped to the 16-bit range, as the reference does.
+#[inline]
+pub(crate) fn reconstruct(predicted: i32, dequantized: i32) -> i16 {
+	(predicted + dequantized).clamp(-32768, 32767) as i16
+}
+
+/// Returns the size in bytes of a frame of `channels` with `slices` slices per
+/// channel: an 8-byte frame header, a 16-byte LMS state per channel, and 8 bytes
+/// per slice.
+pub const fn frame_byte_size(channels: usize, slices: usize) -> usize {
+	8 + 16 * channels + 8 * slices * channels
+}
 
 impl QoaLmsState {
+	/// Creates a new LMS state from its `history` and `weights`.
+	pub fn new(history: [i32; 4], weights: [i32; 4]) -> Self {
+		Self { history, weights }
+	}
+
+	/// Returns the sample history.
+	pub fn history(&self) -> &[i32; 4] { &self.history }
+
+	/// Returns the predictor weights.
+	pub fn weights(&self) -> &[i32; 4] { &self.weights }
+
+	/// Predicts the next sample. Like the reference, the weighted sum wraps on
+	/// overflow, which only adversarial input can cause.
+	#[cfg(feature = "std")]
 	fn predict(&self) -> i32 {
 		let history = self.history.iter().cloned();
 		let weights = self.weights.iter().cloned();
-		let p: i32 = history.zip(weights).map(|(h, w)| h * w).sum();
+		let p = history.zip(weights)
+					   .map(|(h, w)| h.wrapping_mul(w))
+					   .fold(0i32, i32::wrapping_add);
 		p >> 13
 	}
 
+	/// Updates the weights from the dequantized `residual`, then pushes `sample`
+	/// into the history. Weights wrap on overflow, as in the reference.
+	#[cfg(feature = "std")]
 	fn update(&mut self, sample: i16, residual: i32) {
 		let delta = residual >> 4;
 		for (history, weight) in self.history
 									 .into_iter()
 									 .zip(self.weights.iter_mut()) {
-			*weight += if history < 0 { -delta } else { delta };
+			*weight = weight.wrapping_add(if history < 0 { -delta } else { delta });
 		}
 
 		self.history.copy_within(1..4, 0);
@@ -270,6 +407,63 @@ impl Default for QoaLmsState {
 	}
 }
 
+#[cfg(feature = "std")]
+impl QoaSlice {
+	/// Returns the scale factor, in `[0,16)`.
+	pub fn scale_factor(&self) -> u8 { self.quant }
+
+	/// Returns the quantized residuals, each in `[0,8)`.
+	pub fn residuals(&self) -> &[u8; SLICE_LEN] { &self.resid }
+
+	/// Unpacks a slice from a long, overwriting this one.
+	pub fn unpack(&mut self, mut v: u64) {
+		let ref mut resid = self.resid;
+		for i in (0..20).rev() {
+			resid[i] = (v & 0b111) as u8;
+			v >>= 3;
+		}
+		self.quant = v as u8;
+	}
+
+	/// Packs the slice into a long: the scale factor in the top 4 bits, followed
+	/// by each 3-bit residual.
+	pub fn pack(&self) -> u64 {
+		self.resid
+			.iter()
+			.fold(self.quant as u64 & 0xF, |acc, &r| acc << 3 | (r & 0b111) as u64)
+	}
+}
+
+#[cfg(feature = "std")]
+impl From<u64> for QoaSlice {
+	fn from(value: u64) -> Self {
+		let mut slice = Self::default();
+		slice.unpack(value);
+		slice
+	}
+}
+
+/// Encodes 20 samples of one channel into a packed slice with the scaler `Sc`,
+/// updating `lms`. Useful for custom framing; streams should use an [`Encoder`].
+#[cfg(feature = "std")]
+pub fn encode_slice<Sc: SliceScaler>(samples: &[i16; SLICE_LEN], lms: &mut QoaLmsState) -> u64 {
+	Sc::scale(samples, lms, 0, 1)
+}
+
+/// Decodes a packed slice into 20 samples of one channel, updating `lms`.
+#[cfg(feature = "std")]
+pub fn decode_slice(packed: u64, lms: &mut QoaLmsState) -> [i16; SLICE_LEN] {
+	let QoaSlice { quant, resid } = packed.into();
+	resid.map(|qr| {
+		let predicted = lms.predict();
+		let dequantized = DEQUANT_TABLE[quant as usize][qr as usize];
+		let reconst = reconstruct(predicted, dequantized);
+		lms.update(reconst, dequantized);
+		reconst
+	})
+}
+
+#[cfg(feature = "std")]
 fn div(v: i32, sf: usize) -> i32 {
 	let recip = RECIP_TABLE[sf];
 	let mut n = ((v as i64 * recip + (1 << 15)) >> 16) as i32;
@@ -278,16 +472,19 @@ fn div(v: i32, sf: usize) -> i32 {
 	n
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
+	use std::assert_matches::assert_matches;
 	use std::fmt::{Arguments, Debug};
 	use quickcheck_macros::quickcheck;
 	use quickcheck::{Arbitrary, Gen, TestResult};
 	use qoa_ref_sys::qoa::qoa_lms_t;
+	use qoa_ref_sys::QoaDesc;
 	use crate::decoder::QoaSource;
 	use crate::encoder::QoaSink;
 	use crate::io::Buffer;
-	use crate::{DEQUANT_TABLE, QoaLmsState};
+	use crate::reference::{from_ref_desc, to_ref_desc};
+	use crate::{byte_decoder, decode_slice, DEQUANT_TABLE, DescriptorError, encode_slice, Encoder, FRAME_LEN, LinearScaler, QoaLmsState, QoaSlice, SLICE_LEN, StreamDescriptor};
 
 	#[macro_export]
 	macro_rules! qc_assert_eq {
@@ -353,6 +550,199 @@ mod test {
 		}
 	}
 
+	#[test]
+	fn ref_descriptor_round_trip() {
+		let desc = StreamDescriptor::new(Some(1000), Some(44100), Some(2)).unwrap();
+		let qoa_desc = to_ref_desc(desc);
+		assert_eq!((qoa_desc.samples, qoa_desc.samplerate, qoa_desc.channels), (1000, 44100, 2));
+
+		let desc = from_ref_desc(&qoa_desc).unwrap();
+		assert_eq!(desc.samples(), Some(1000));
+		assert_eq!(desc.rate(), Some(44100));
+		assert_eq!(desc.channels(), Some(2));
+
+		let streaming = from_ref_desc(&QoaDesc::default()).unwrap();
+		assert!(streaming.is_streaming());
+		assert_eq!(streaming.rate(), None);
+
+		let invalid = QoaDesc { samplerate: 1 << 24, ..QoaDesc::default() };
+		assert!(matches!(from_ref_desc(&invalid), Err(DescriptorError::UnsupportedRate(_))));
+	}
+
+	#[test]
+	fn merge_complementary() {
+		let meta  = StreamDescriptor::new(Some(1000), None, None).unwrap();
+		let probe = StreamDescriptor::new(None, Some(44100), Some(2)).unwrap();
+		let merged = meta.merge(probe).unwrap();
+		assert_eq!(merged.samples(), Some(1000));
+		assert_eq!(merged.rate(), Some(44100));
+		assert_eq!(merged.channels(), Some(2));
+	}
+
+	#[test]
+	fn merge_conflicting() {
+		let meta  = StreamDescriptor::new(Some(1000), Some(48000), None).unwrap();
+		let probe = StreamDescriptor::new(Some(2000), Some(44100), Some(1)).unwrap();
+		let merged = meta.merge(probe).unwrap();
+		assert_eq!(merged.samples(), Some(1000));
+		assert_eq!(merged.rate(), Some(48000));
+		assert_eq!(merged.channels(), Some(1));
+
+		let merged = probe.merge(meta).unwrap();
+		assert_eq!(merged.samples(), Some(2000));
+		assert_eq!(merged.rate(), Some(44100));
+		assert_eq!(merged.channels(), Some(1));
+	}
+
+	#[test]
+	fn merge_invalid() {
+		let desc = StreamDescriptor::default();
+		let zero_rate = StreamDescriptor { sample_rate: Some(0), ..desc };
+		let channels  = StreamDescriptor { channel_count: Some(256), ..desc };
+		assert!(matches!(desc.merge(zero_rate), Err(DescriptorError::ZeroRate)));
+		assert!(matches!(desc.merge(channels), Err(DescriptorError::TooManyChannels(256))));
+
+		// A valid field in self shadows an invalid one in other.
+		let rate = StreamDescriptor::new(None, Some(44100), None).unwrap();
+		assert!(rate.merge(zero_rate).is_ok());
+	}
+
+	#[test]
+	fn validate_suggested() {
+		fn suggested(samples: usize, rate: u32, channels: usize) -> StreamDescriptor {
+			let mut desc = StreamDescriptor::default();
+			desc.suggest_sample_count(samples);
+			desc.suggest_sample_rate(rate);
+			desc.suggest_channel_count(channels);
+			desc
+		}
+
+		assert!(suggested(5120, 44100, 2).is_valid());
+		assert!(StreamDescriptor::default().is_valid());
+		assert_matches!(suggested(5120, 0, 2).validate(), Err(DescriptorError::ZeroRate));
+		assert_matches!(suggested(5120, 1 << 24, 2).validate(), Err(DescriptorError::UnsupportedRate(16777216)));
+		assert_matches!(suggested(0, 44100, 2).validate(), Err(DescriptorError::NoSamples));
+		assert_matches!(suggested(5120, 44100, 0).validate(), Err(DescriptorError::NoChannels));
+		assert_matches!(suggested(5120, 44100, 256).validate(), Err(DescriptorError::TooManyChannels(256)));
+		#[cfg(target_pointer_width = "64")]
+		assert_matches!(
+			suggested(u32::MAX as usize + 1, 44100, 2).validate(),
+			Err(DescriptorError::TooManySamples(4294967296))
+		);
+
+		// A valid suggestion after an invalid one doesn't replace it.
+		let mut desc = suggested(5120, 0, 2);
+		desc.suggest_sample_rate(44100);
+		assert!(!desc.is_valid());
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn descriptor_json() {
+		let desc = StreamDescriptor::new(Some(5120), Some(44100), Some(2)).unwrap();
+		let json = serde_json::to_string(&desc).unwrap();
+		assert_eq!(json, r#"{"sample_count":5120,"sample_rate":44100,"channel_count":2}"#);
+		assert_eq!(serde_json::from_str::<StreamDescriptor>(&json).unwrap(), desc);
+
+		let unknown = serde_json::from_str::<StreamDescriptor>(r#"{"sample_count":null,"sample_rate":null,"channel_count":null}"#);
+		assert_eq!(unknown.unwrap(), StreamDescriptor::default());
+		assert!(serde_json::from_str::<StreamDescriptor>(r#"{"sample_count":null,"sample_rate":0,"channel_count":1}"#).is_err());
+
+		let lms = QoaLmsState::default();
+		let json = serde_json::to_string(&lms).unwrap();
+		assert_eq!(serde_json::from_str::<QoaLmsState>(&json).unwrap(), lms);
+	}
+
+	#[test]
+	fn unknown_zero_rate() {
+		let unknown = StreamDescriptor::new(None, None, None).unwrap();
+		assert_eq!(unknown.rate(), None);
+		assert!(matches!(StreamDescriptor::new(None, Some(0), None), Err(DescriptorError::ZeroRate)));
+		let known = StreamDescriptor::new(None, Some(48000), None).unwrap();
+		assert_eq!(known.rate(), Some(48000));
+
+		// Only an unknown rate is filled from the fallback; a zero rate is kept to be
+		// rejected, rather than silently treated as unknown.
+		let mut desc = unknown;
+		desc.infer(&known);
+		assert_eq!(desc.rate(), Some(48000));
+
+		let mut zero = StreamDescriptor { sample_rate: Some(0), ..unknown };
+		zero.infer(&known);
+		assert_eq!(zero.rate(), Some(0));
+		assert!(matches!(zero.merge(known), Err(DescriptorError::ZeroRate)));
+	}
+
+	#[test]
+	fn infer_from_planar() {
+		let channels = [vec![0; 300], vec![0; 300], vec![0; 300]];
+		let mut desc = StreamDescriptor::default();
+		desc.infer_from_planar(&channels, &StreamDescriptor::new(None, Some(44100), None).unwrap()).unwrap();
+		assert_eq!(desc.samples(), Some(300));
+		assert_eq!(desc.rate(), Some(44100));
+		assert_eq!(desc.channels(), Some(3));
+
+		// A known sample count is clamped to the channel length.
+		let mut desc = StreamDescriptor::new(Some(1000), None, None).unwrap();
+		desc.infer_from_planar(&channels, &StreamDescriptor::default()).unwrap();
+		assert_eq!(desc.samples(), Some(300));
+	}
+
+	#[test]
+	fn infer_from_ragged_planar() {
+		let channels = [vec![0; 300], vec![0; 300], vec![0; 200]];
+		let mut desc = StreamDescriptor::default();
+		assert!(matches!(
+			desc.infer_from_planar(&channels, &StreamDescriptor::default()),
+			Err(DescriptorError::RaggedChannels(2, 200, 300))
+		));
+	}
+
+	#[quickcheck]
+	fn slice_pack(packed: u64) -> TestResult {
+		qc_assert_eq!(QoaSlice::from(packed).pack(), packed)
+	}
+
+	#[quickcheck]
+	fn slice_round_trip(samples: Vec<i16>, lms: QoaLmsState) -> TestResult {
+		let Some(samples) = samples.get(..SLICE_LEN) else {
+			return TestResult::discard()
+		};
+		let samples: &[i16; SLICE_LEN] = samples.try_into().unwrap();
+
+		let mut enc_lms = lms;
+		let mut dec_lms = lms;
+		let decoded = decode_slice(encode_slice::<LinearScaler>(samples, &mut enc_lms), &mut dec_lms);
+		if enc_lms != dec_lms {
+			return qc_assert_eq!(dec_lms, enc_lms, "decoder LMS state")
+		}
+
+		let history: Vec<i32> = decoded[SLICE_LEN - 4..].iter().map(|&s| s as i32).collect();
+		qc_assert_eq!(dec_lms.history().to_vec(), history, "LMS history")
+	}
+
+	#[test]
+	fn slice_round_trip_tone() {
+		let tone: Vec<i16> = (0..SLICE_LEN * 64).map(|i| {
+			let t = i as f64 / 44100.0;
+			(8000.0 * (2.0 * std::f64::consts::PI * 440.0 * t).sin()) as i16
+		}).collect();
+
+		let mut enc_lms = QoaLmsState::default();
+		let mut dec_lms = QoaLmsState::default();
+		for (i, slice) in tone.chunks(SLICE_LEN).enumerate() {
+			let slice: &[i16; SLICE_LEN] = slice.try_into().unwrap();
+			let decoded = decode_slice(encode_slice::<LinearScaler>(slice, &mut enc_lms), &mut dec_lms);
+			assert_eq!(dec_lms, enc_lms, "LMS state after slice {i}");
+
+			// Skip the first slice, while the predictor adapts.
+			if i == 0 { continue }
+			for (&exp, act) in slice.iter().zip(decoded) {
+				assert!((exp as i32 - act as i32).abs() <= 256, "sample {exp} decoded as {act} in slice {i}");
+			}
+		}
+	}
+
 	#[quickcheck]
 	fn lms_predict(lms: QoaLmsState) -> TestResult {
 		let exp = {
@@ -366,17 +756,148 @@ mod test {
 
 	#[quickcheck]
 	fn lms_update(mut lms: QoaLmsState, sample: i16, residual: i32) -> TestResult {
-		if !DEQUANT_TABLE.flatten().contains(&residual) {
-			return TestResult::discard()
-		}
-
 		let mut other: qoa_lms_t = lms.into();
 		other.update(sample, residual);
-		let other = lms.into();
+		let other: QoaLmsState = other.into();
 		lms.update(sample, residual);
 		qc_assert_eq!(lms, other)
 	}
 
+	#[test]
+	fn lms_update_overflow() {
+		let mut lms = QoaLmsState::new([1, -1, 1, -1], [i32::MAX, i32::MIN, i32::MAX - 1, i32::MIN]);
+		let mut other: qoa_lms_t = lms.into();
+		let residual = DEQUANT_TABLE[15][6];
+
+		lms.update(0, residual);
+		other.update(0, residual);
+		assert_eq!(lms, other.into());
+		assert_eq!(lms.weights()[0], i32::MAX.wrapping_add(residual >> 4));
+		assert_eq!(lms.predict(), Into::<qoa_lms_t>::into(lms).predict());
+	}
+
+	/// An LMS state with weights spanning the full `i32` range, as can be reached
+	/// by warm-started or long-running predictors.
+	#[derive(Copy, Clone, Debug)]
+	struct WideLms(QoaLmsState);
+
+	impl Arbitrary for WideLms {
+		fn arbitrary(g: &mut Gen) -> Self {
+			let mut lms = QoaLmsState::arbitrary(g);
+			lms.weights.fill_with(|| i32::arbitrary(g));
+			Self(lms)
+		}
+	}
+
+	/// The prediction sum before the shift, without overflow.
+	fn wide_sum(lms: &QoaLmsState) -> i64 {
+		lms.history.iter().zip(lms.weights).map(|(&h, w)| h as i64 * w as i64).sum()
+	}
+
+	/// The reference sums in a C `int`, so the prediction must wrap the same way
+	/// rather than widen.
+	#[quickcheck]
+	fn lms_predict_wide(WideLms(lms): WideLms) -> TestResult {
+		let exp = Into::<qoa_lms_t>::into(lms).predict();
+		qc_assert_eq!(lms.predict(), exp, "sum {}", wide_sum(&lms))
+	}
+
+	#[test]
+	fn lms_predict_saturated() {
+		let lms = QoaLmsState::new([i16::MIN as i32; 4], [i32::MIN; 4]);
+		assert!(wide_sum(&lms) > i32::MAX as i64);
+		assert_eq!(lms.predict(), Into::<qoa_lms_t>::into(lms).predict());
+
+		let lms = QoaLmsState::new([i16::MAX as i32, i16::MIN as i32, i16::MAX as i32, i16::MIN as i32], [i32::MAX, i32::MIN, i32::MAX, i32::MIN]);
+		assert_eq!(lms.predict(), Into::<qoa_lms_t>::into(lms).predict());
+	}
+
+	/// Runs full-scale noise through the encoder's predictor, checking every state
+	/// reached against the reference, overflowing or not.
+	#[test]
+	fn lms_predict_encoded_noise() {
+		let mut seed = 0x2545F491u32;
+		let mut lms = QoaLmsState::default();
+		for _ in 0..FRAME_LEN * 8 / SLICE_LEN {
+			let samples = [(); SLICE_LEN].map(|_| {
+				seed ^= seed << 13;
+				seed ^= seed >> 17;
+				seed ^= seed << 5;
+				seed as i16
+			});
+			encode_slice::<LinearScaler>(&samples, &mut lms);
+			assert_eq!(lms.predict(), Into::<qoa_lms_t>::into(lms).predict(), "sum {}", wide_sum(&lms));
+		}
+	}
+
+	/// A stereo frame of one slice, with LMS states predicting past the 16-bit
+	/// range in both directions.
+	fn clamped_frame() -> Vec<u64> {
+		fn pack(values: [i16; 4]) -> u64 {
+			values.iter().fold(0, |acc, &v| acc << 16 | v as u16 as u64)
+		}
+
+		let residuals = |qr: u64| (0..20).fold(0, |acc, _| acc << 3 | qr);
+		vec![
+			(crate::MAGIC as u64) << 32 | 20,
+			2 << 56 | 44100 << 32 | 20 << 16 | super::frame_byte_size(2, 1) as u64,
+			pack([0, 0, 0, i16::MAX]), pack([0, 0, 0, 16384]),
+			pack([0, 0, 0, i16::MIN]), pack([0, 0, 0, 16384]),
+			15 << 60 | residuals(0),
+			15 << 60 | residuals(1),
+		]
+	}
+
+	#[test]
+	fn reconstruct_clamped() {
+		assert_eq!(super::reconstruct(i32::MAX >> 13, DEQUANT_TABLE[15][6]), i16::MAX);
+		assert_eq!(super::reconstruct(i32::MIN >> 13, DEQUANT_TABLE[15][7]), i16::MIN);
+		assert_eq!(super::reconstruct(100, -200), -100);
+
+		let frame = clamped_frame();
+		let mut expected = [[0; SLICE_LEN]; 2];
+		for (chn, out) in expected.iter_mut().enumerate() {
+			let mut lms = QoaLmsState::default();
+			let [history, weights] = [frame[2 + chn * 2], frame[3 + chn * 2]];
+			for i in 0..4 {
+				lms.history[i] = (history >> (48 - 16 * i)) as i16 as i32;
+				lms.weights[i] = (weights >> (48 - 16 * i)) as i16 as i32;
+			}
+			*out = decode_slice(frame[6 + chn], &mut lms);
+		}
+		assert_eq!((expected[0][0], expected[1][0]), (i16::MAX, i16::MIN));
+		let expected: Vec<i16> = (0..SLICE_LEN).flat_map(|i| [expected[0][i], expected[1][i]]).collect();
+
+		let bytes: Vec<u8> = frame.iter().flat_map(|long| long.to_be_bytes()).collect();
+		let mut actual = Vec::new();
+		byte_decoder::Decoder::default().decode(&bytes, &mut actual).unwrap();
+		assert_eq!(actual, expected, "byte decoder");
+
+		let mut source = Buffer::from(frame);
+		let actual = crate::decoder::Decoder::new(crate::PcmFrame::new(SLICE_LEN, 44100, 2))
+			.decode(&mut source)
+			.unwrap();
+		assert_eq!(actual.data(), expected, "stream decoder");
+	}
+
+	#[test]
+	fn frame_byte_size() {
+		for (channels, len) in [(1, FRAME_LEN), (2, FRAME_LEN), (3, 77), (8, 20), (1, 1)] {
+			let slices = (len + SLICE_LEN - 1) / SLICE_LEN;
+			let expected = super::frame_byte_size(channels, slices);
+
+			let mut enc = Encoder::new_fixed(len, 44100, channels, Buffer::default()).unwrap();
+			enc.encode_interleaved(&vec![100; len * channels], StreamDescriptor::default()).unwrap();
+			let data = enc.close().unwrap().unwrap().encode();
+			let header = u64::from_be_bytes(data[8..16].try_into().unwrap());
+			assert_eq!((header & 0xFFFF) as usize, expected, "encoded size of {channels}x{len}");
+			assert_eq!(data.len(), 8 + expected);
+
+			let bytes = byte_decoder::Decoder::default().decode(&data, &mut Vec::new()).unwrap();
+			assert_eq!(bytes, 8 + expected, "decoded size of {channels}x{len}");
+		}
+	}
+
 	#[quickcheck]
 	fn codec_file_header(sample_count: u32) -> TestResult {
 		let mut buf = Buffer::default();
@@ -407,6 +928,34 @@ mod test {
 		}
 	}
 
+	#[test]
+	fn max_rate() {
+		const MAX_RATE: u32 = (1 << 24) - 1;
+
+		let mut buf = Buffer::default();
+		buf.enc_frame_header(2, MAX_RATE, 5120, 1024).unwrap();
+		assert_eq!(buf.dec_frame_header().unwrap(), (2, MAX_RATE, 5120, 1024));
+
+		let desc = StreamDescriptor::new(None, Some(MAX_RATE), None).unwrap();
+		assert_eq!(desc.rate(), Some(MAX_RATE));
+		assert!(matches!(
+			StreamDescriptor::new(None, Some(MAX_RATE + 1), None),
+			Err(DescriptorError::UnsupportedRate(16777216))
+		));
+
+		let samples: Vec<i16> = (0..100).map(|i| i * 50).collect();
+		let mut enc = Encoder::new_fixed(samples.len(), MAX_RATE, 1, Buffer::default()).unwrap();
+		enc.encode_interleaved(&samples, StreamDescriptor::default()).unwrap();
+		let data = enc.close().unwrap().unwrap().encode();
+
+		let mut decoded = Vec::new();
+		let (_, desc) = byte_decoder::Decoder::default()
+			.decode_with_descriptor(&data, &mut decoded)
+			.unwrap();
+		assert_eq!(desc.rate(), Some(MAX_RATE));
+		assert_eq!(decoded.len(), samples.len());
+	}
+
 	#[quickcheck]
 	fn codec_lms(lms: QoaLmsState) -> TestResult {
 		let mut buf = Buffer::default();
@@ -421,4 +970,29 @@ mod test {
 
 		qc_assert_eq!(lms, decoded[0])
 	}
-}
\ No newline at end of file
+
+	#[quickcheck]
+	fn codec_lms_accessors(
+		(h0, h1, h2, h3): (i16, i16, i16, i16),
+		(w0, w1, w2, w3): (i16, i16, i16, i16)
+	) -> TestResult {
+		let history = [h0, h1, h2, h3].map(i32::from);
+		let weights = [w0, w1, w2, w3].map(i32::from);
+		let lms = QoaLmsState::new(history, weights);
+		let mut buf = Buffer::default();
+		if let Err(error) = buf.enc_lms_state(&lms) {
+			return TestResult::error(format!("{error}"))
+		}
+
+		let mut decoded = [QoaLmsState::default(); 1];
+		if let Err(error) = buf.dec_lms(&mut decoded) {
+			return TestResult::error(format!("{error}"))
+		}
+
+		let [decoded] = decoded;
+		qc_assert_eq!(
+			(decoded.history(), decoded.weights()),
+			(lms.history(), lms.weights())
+		)
+	}
+}
@@ -17,19 +17,13 @@
 use std::assert_matches::assert_matches;
 use std::env::args;
 use std::error::Error as StdError;
-use std::fs::{File, read};
-use std::io::{BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::fs::{create_dir_all, File, metadata, read, read_dir};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use amplify_derive::{Display, Error as AmpError};
-use symphonia::core::audio::Channels;
-use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::{FormatOptions};
-use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::{Hint, ProbeResult};
-use symphonia::default::{get_codecs, get_probe};
-use qoar::conv::FormatSource;
-use qoar::{byte_decoder::Decoder, Encoder, PcmBuffer};
+use qoar::conv;
+use qoar::{byte_decoder::Decoder, EncodeError, Encoder, metrics, PcmSource, PcmStream, SliceScaler, wav};
+use qoar::io::{Buffer, SinkStream, TryIntoSinkStream};
 
 #[derive(Clone, Debug, Display, AmpError)]
 enum Error {
@@ -37,8 +31,12 @@ enum Error {
 	MissingArguments(MissingArgument),
 	#[display("unknown command {0}")]
 	UnknownCommand(String),
-	#[display("no tracks found")]
-	NoTracks,
+	#[display("decoded {1} samples, but the source has {0}")]
+	SampleCountMismatch(usize, usize),
+	#[display("unknown flag {0}")]
+	UnknownFlag(String),
+	#[display("SIMD encoding requires the simd feature")]
+	SimdUnavailable,
 }
 
 #[derive(Copy, Clone, Debug, Display)]
@@ -51,15 +49,44 @@ enum MissingArgument {
 	DestinationFile,
 }
 
+/// The slice scaler used to encode.
+#[derive(Copy, Clone, Debug, Default)]
+enum Backend {
+	/// The linear scaler, like the reference encoder.
+	#[default]
+	Scalar,
+	/// The SIMD vector scaler.
+	Simd,
+}
+
+impl Backend {
+	/// Parses an optional `--simd` or `--scalar` flag.
+	fn parse(flag: Option<String>) -> Result<Self, Error> {
+		match flag.as_deref() {
+			None | Some("--scalar") => Ok(Self::Scalar),
+			Some("--simd") if cfg!(feature = "simd") => Ok(Self::Simd),
+			Some("--simd") => Err(Error::SimdUnavailable),
+			Some(flag) => Err(Error::UnknownFlag(flag.to_owned()))
+		}
+	}
+}
+
 fn main() { run(args().skip(1)).unwrap() }
 
 fn run(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn StdError>> {
 	let cmd = args.next().ok_or(Error::MissingArguments(MissingArgument::Command))?;
 	let src = args.next().ok_or(Error::MissingArguments(MissingArgument::SourceFile))?.into();
+
+	if cmd == "verify" {
+		return verify(src)
+	}
+
 	let dst = args.next().ok_or(Error::MissingArguments(MissingArgument::DestinationFile))?.into();
 
 	if cmd == "encode" {
-		enc(src, dst)
+		enc(src, dst, Backend::parse(args.next())?)
+	} else if cmd == "encode-dir" {
+		enc_dir(src, dst, Backend::parse(args.next())?)
 	} else if cmd == "decode" {
 		dec(src, dst)
 	} else {
@@ -67,7 +94,7 @@ fn run(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn StdError>>
 	}
 }
 
-fn enc(src: PathBuf, dst: PathBuf) -> Result<(), Box<dyn StdError>> {
+fn enc(src: PathBuf, dst: PathBuf, backend: Backend) -> Result<(), Box<dyn StdError>> {
 	assert_matches!(
 		dst.extension()
 		   .map(|ext| ext.to_string_lossy())
@@ -75,35 +102,134 @@ fn enc(src: PathBuf, dst: PathBuf) -> Result<(), Box<dyn StdError>> {
 		Some("qoa")
 	);
 
-	let src = File::open(src)?;
-	let dst = File::options().truncate(true)
-							 .create(true)
-							 .write(true)
-							 .open(dst)?;
-	let registry = get_codecs();
-	let probe = get_probe();
-	let source = MediaSourceStream::new(
-		Box::new(src),
-		MediaSourceStreamOptions::default()
-	);
-	let ProbeResult { format: demuxer, .. } = probe.format(
-		&Hint::new(),
-		source,
-		&FormatOptions::default(),
-		&MetadataOptions::default()
-	)?;
-	let track = demuxer.default_track().ok_or(Error::NoTracks)?.clone();
-	let decoder = registry.make(&track.codec_params, &DecoderOptions::default())?;
+	encode_file(src, dst.into_sink()?, backend)?;
+	Ok(())
+}
+
+/// Encodes the audio file at `src` into `sink` with the `backend` scaler, then
+/// returns the sink.
+fn encode_file<S: SinkStream>(src: PathBuf, sink: S, backend: Backend) -> Result<S, Box<dyn StdError>> {
+	match backend {
+		Backend::Scalar => encode_file_with(src, sink, Encoder::new_fixed),
+		#[cfg(feature = "simd")]
+		Backend::Simd => encode_file_with(src, sink, Encoder::new_fixed_simd),
+		#[cfg(not(feature = "simd"))]
+		Backend::Simd => Err(Error::SimdUnavailable.into()),
+	}
+}
+
+/// Encodes the audio file at `src` into `sink` with an encoder created by `new`,
+/// then returns the sink.
+fn encode_file_with<S: SinkStream, Sc: SliceScaler>(
+	src: PathBuf,
+	sink: S,
+	new: fn(usize, u32, usize, S) -> Result<Encoder<S, Sc>, EncodeError>,
+) -> Result<S, Box<dyn StdError>> {
+	// Read PCM16 WAV directly, falling back to Symphonia for anything else.
+	if src.extension().is_some_and(|ext| ext == "wav") {
+		match wav::read_pcm16(&src) {
+			Ok((samples, desc)) => {
+				let mut enc = new(
+					desc.samples().unwrap_or_default(),
+					desc.rate().unwrap_or_default(),
+					desc.channels().unwrap_or_default(),
+					sink,
+				)?;
+				enc.encode_interleaved(&samples, desc)?;
+				return Ok(enc.close().unwrap()?)
+			}
+			Err(wav::WavError::Unsupported(..)) => { }
+			Err(err) => return Err(err.into())
+		}
+	}
 
-	let mut source = FormatSource::new(track.clone(), demuxer, decoder);
+	let (mut source, tags) = conv::open_file(src)?;
 
-	let mut enc = Encoder::new_fixed(
-		track.codec_params.n_frames.unwrap_or_default() as usize,
-		track.codec_params.sample_rate.unwrap_or_default(),
-		track.codec_params.channels.map(Channels::count).unwrap_or_default(),
-		BufWriter::new(dst),
+	let mut enc = new(
+		source.sample_count(),
+		source.sample_rate(),
+		source.channel_count(),
+		sink,
 	)?;
-	enc.encode(&mut source)?;
+	enc.set_metadata(tags);
+	enc.encode_format(&mut source)?;
+	Ok(enc.close().unwrap()?)
+}
+
+/// Encodes a WAV file in memory, decodes it, then prints error metrics and the
+/// compression ratio.
+fn verify(src: PathBuf) -> Result<(), Box<dyn StdError>> {
+	let (samples, desc) = wav::read_pcm16(&src)?;
+	let data = encode_file(src.clone(), Buffer::default(), Backend::Scalar)?.encode();
+
+	let mut decoded = Vec::new();
+	Decoder::default().decode(&data, &mut decoded)?;
+
+	if decoded.len() != samples.len() {
+		return Err(Error::SampleCountMismatch(samples.len(), decoded.len()).into())
+	}
+
+	let src_len = metadata(&src)?.len();
+	let ratio = src_len as f64 / data.len() as f64;
+	println!(
+		"{} samples, {} channels at {}Hz",
+		desc.samples().unwrap_or_default(),
+		desc.channels().unwrap_or_default(),
+		desc.rate().unwrap_or_default()
+	);
+	println!("rmse: {:.3}", metrics::rmse(&samples, &decoded)?);
+	println!("psnr: {:.2}dB", metrics::psnr(&samples, &decoded)?);
+	println!("compression: {ratio:.2}:1 ({src_len} -> {} bytes)", data.len());
+	Ok(())
+}
+
+/// The extensions of files encoded by `encode-dir`.
+const BATCH_EXTENSIONS: [&str; 3] = ["wav", "flac", "ogg"];
+
+/// Encodes audio files found in `src` and its subdirectories into QOA files at the
+/// same relative path in `dst`, continuing past files that fail.
+fn enc_dir(src: PathBuf, dst: PathBuf, backend: Backend) -> Result<(), Box<dyn StdError>> {
+	let mut files = Vec::new();
+	find_files(&src, &mut files)?;
+
+	let mut failed = 0;
+	for file in &files {
+		let rel = file.strip_prefix(&src)?;
+		let out = dst.join(rel).with_extension("qoa");
+
+		let result = out.parent()
+						.map_or(Ok(()), create_dir_all)
+						.map_err(Into::into)
+						.and_then(|_| enc(file.clone(), out.clone(), backend))
+						.and_then(|_| Ok((metadata(file)?.len(), metadata(&out)?.len())));
+		match result {
+			Ok((src_len, dst_len)) => {
+				let ratio = 100.0 * (1.0 - dst_len as f64 / src_len.max(1) as f64);
+				println!("{}: {src_len} -> {dst_len} bytes ({ratio:.1}% smaller)", rel.display());
+			}
+			Err(err) => {
+				eprintln!("{}: {err}", rel.display());
+				failed += 1;
+			}
+		}
+	}
+
+	println!("encoded {} of {} files", files.len() - failed, files.len());
+	Ok(())
+}
+
+/// Collects files in `dir` and its subdirectories with a batch extension.
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn StdError>> {
+	for entry in read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			find_files(&path, files)?;
+		} else if path.extension()
+					  .and_then(|ext| ext.to_str())
+					  .is_some_and(|ext| BATCH_EXTENSIONS.contains(&&*ext.to_ascii_lowercase())) {
+			files.push(path);
+		}
+	}
 	Ok(())
 }
 
@@ -115,11 +241,19 @@ fn dec(src: PathBuf, dst: PathBuf) -> Result<(), Box<dyn StdError>> {
 		Some("qoa")
 	);
 
-	let mut src = read(src)?;
-	let mut dst = File::create(dst)?;
+	let src = read(src)?;
 	let mut buf = Vec::new();
-	Decoder::default()
-		.decode(&mut src, &mut buf)?;
+	let (_, desc) = Decoder::default()
+		.decode_with_descriptor(&src, &mut buf)?;
+
+	// Write WAV if requested, otherwise raw PCM16-LE samples.
+	if dst.extension().is_some_and(|ext| ext == "wav") {
+		let rate = desc.rate().unwrap_or_default();
+		let channels = desc.channels().unwrap_or_default() as u16;
+		return Ok(wav::write_pcm16(dst, &buf, rate, channels)?)
+	}
+
+	let mut dst = File::create(dst)?;
 	let buf: Vec<_> = buf.into_iter()
 						 .map(i16::to_le_bytes)
 						 .flatten()
@@ -0,0 +1,162 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Quality metrics comparing original PCM samples to their decoded counterparts.
+
+use std::result;
+use amplify_derive::{Display, Error};
+use MetricError::*;
+
+type Result<T = f64> = result::Result<T, MetricError>;
+
+#[derive(Copy, Clone, Debug, Display, Error, Eq, PartialEq)]
+pub enum MetricError {
+	#[display("sample lengths differ, original has {0} samples but decoded has {1}")]
+	LengthMismatch(usize, usize),
+	#[display("sample length {0} is not a multiple of the channel count {1}")]
+	PartialSlice(usize, usize),
+	#[display("cannot compare empty sample buffers")]
+	Empty,
+	#[display("channel count must be non-zero")]
+	NoChannels,
+}
+
+/// Computes the root-mean-square error between `orig` and `decoded` samples. The
+/// samples may be channel-interleaved; the error is computed across all channels.
+///
+/// # Errors
+///
+/// [`MetricError::LengthMismatch`]: `orig` and `decoded` have different lengths.
+///
+/// [`MetricError::Empty`]: `orig` and `decoded` are empty.
+pub fn rmse(orig: &[i16], decoded: &[i16]) -> Result {
+	check_len(orig, decoded)?;
+	Ok(sum_rmse(orig.iter().zip(decoded), orig.len()))
+}
+
+/// Computes the peak signal-to-noise ratio, in decibels, between `orig` and
+/// `decoded` samples. Returns [`f64::INFINITY`] if the samples are identical.
+///
+/// # Errors
+///
+/// See [`rmse`].
+pub fn psnr(orig: &[i16], decoded: &[i16]) -> Result {
+	rmse(orig, decoded).map(rmse_to_psnr)
+}
+
+/// Computes the root-mean-square error between channel-interleaved `orig` and
+/// `decoded` samples for each of `channels`.
+///
+/// # Errors
+///
+/// [`MetricError::NoChannels`]: `channels` is `0`.
+///
+/// [`MetricError::PartialSlice`]: the sample length isn't a multiple of
+/// `channels`.
+///
+/// See [`rmse`] for other errors.
+pub fn channel_rmse(orig: &[i16], decoded: &[i16], channels: usize) -> Result<Vec<f64>> {
+	if channels == 0 {
+		return Err(NoChannels)
+	}
+
+	check_len(orig, decoded)?;
+
+	if orig.len() % channels != 0 {
+		return Err(PartialSlice(orig.len(), channels))
+	}
+
+	let len = orig.len() / channels;
+	Ok((0..channels).map(|chn| {
+		let orig    = orig   [chn..].iter().step_by(channels);
+		let decoded = decoded[chn..].iter().step_by(channels);
+		sum_rmse(orig.zip(decoded), len)
+	}).collect())
+}
+
+/// Computes the peak signal-to-noise ratio, in decibels, for each of `channels`.
+///
+/// # Errors
+///
+/// See [`channel_rmse`].
+pub fn channel_psnr(orig: &[i16], decoded: &[i16], channels: usize) -> Result<Vec<f64>> {
+	let mut values = channel_rmse(orig, decoded, channels)?;
+	for value in &mut values {
+		*value = rmse_to_psnr(*value);
+	}
+	Ok(values)
+}
+
+fn check_len(orig: &[i16], decoded: &[i16]) -> Result<()> {
+	if orig.len() != decoded.len() {
+		Err(LengthMismatch(orig.len(), decoded.len()))
+	} else if orig.is_empty() {
+		Err(Empty)
+	} else {
+		Ok(())
+	}
+}
+
+fn sum_rmse<'a>(pairs: impl Iterator<Item = (&'a i16, &'a i16)>, len: usize) -> f64 {
+	let sum: u64 = pairs.map(|(&a, &b)| {
+		let error = a as i64 - b as i64;
+		(error * error) as u64
+	}).sum();
+	(sum as f64 / len as f64).sqrt()
+}
+
+fn rmse_to_psnr(rmse: f64) -> f64 {
+	if rmse == 0.0 {
+		f64::INFINITY
+	} else {
+		20.0 * (i16::MAX as f64 / rmse).log10()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{channel_rmse, MetricError, psnr, rmse};
+
+	#[test]
+	fn identical() {
+		let samples = [-32768, -1, 0, 1, 32767];
+		assert_eq!(rmse(&samples, &samples), Ok(0.0));
+		assert_eq!(psnr(&samples, &samples), Ok(f64::INFINITY));
+	}
+
+	#[test]
+	fn known_error() {
+		let orig    = [0, 0, 0, 0];
+		let decoded = [3, -3, 3, -3];
+		assert_eq!(rmse(&orig, &decoded), Ok(3.0));
+
+		let psnr = psnr(&orig, &decoded).unwrap();
+		assert!((psnr - 20.0 * (32767.0f64 / 3.0).log10()).abs() < 1e-9);
+	}
+
+	#[test]
+	fn interleaved() {
+		let orig    = [0, 0, 0, 0, 0, 0];
+		let decoded = [2, 0, -2, 0, 2, 0];
+		assert_eq!(channel_rmse(&orig, &decoded, 2), Ok(vec![2.0, 0.0]));
+		assert_eq!(channel_rmse(&orig, &decoded, 4), Err(MetricError::PartialSlice(6, 4)));
+		assert_eq!(channel_rmse(&orig, &decoded, 0), Err(MetricError::NoChannels));
+	}
+
+	#[test]
+	fn mismatched() {
+		assert_eq!(rmse(&[0; 4], &[0; 3]), Err(MetricError::LengthMismatch(4, 3)));
+		assert_eq!(psnr(&[], &[]), Err(MetricError::Empty));
+	}
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{error, mem};
+use std::{error, io, mem};
 use std::cmp::min;
 use amplify_derive::Display;
 use crate::{FRAME_LEN, StreamDescriptor};
@@ -34,20 +34,41 @@ pub enum Error {
 	DescriptorSet,
 	#[display("attempted to write without setting descriptor")]
 	UninitializedDescriptor,
+	#[display("invalid descriptor with sample rate {0} and {1} channels")]
+	InvalidDescriptor(u32, usize),
 	#[display("cannot read samples")]
 	Read(Box<dyn error::Error>),
 	#[display("cannot write samples")]
 	Write(Box<dyn error::Error>),
+	/// An IO error, keeping its [`io::ErrorKind`] to match on, for example to
+	/// retry on [`io::ErrorKind::Interrupted`].
+	#[display("{0}")]
+	Io(io::Error),
+	/// A source kept reporting samples read without writing them.
+	#[display("source stopped making progress after {0} samples")]
+	Stalled(usize),
+	/// An interleaved buffer ended partway through a sample, its length not being
+	/// a multiple of the channel count.
+	#[display("interleaved buffer of length {0} ends within a sample of {1} channels")]
+	PartialSample(usize, usize),
+	/// A channel was written to a [`Vec`] before the preceding channels.
+	#[display("channel {0} written before the preceding channels")]
+	ChannelOrder(usize),
 	#[display("{0}")]
 	Other(Box<dyn error::Error>),
 }
 
+impl From<io::Error> for Error {
+	fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+
 impl error::Error for Error {
 	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
 		match self {
 			Self::Read (err) |
 			Self::Write(err) |
 			Self::Other(err) => Some(err.as_ref()),
+			Self::Io(err) => Some(err),
 			_ => None
 		}
 	}
@@ -59,15 +80,31 @@ pub trait PcmSource: PcmStream {
 	/// number of samples read.
 	fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, Error>;
 
+	/// Reads all samples into a [`PcmBuffer`]. If the sample rate or channel count
+	/// isn't known yet, the source must set it on the buffer as it reads.
+	///
+	/// # Errors
+	///
+	/// [`Error::Stalled`]: a read reported samples without writing any. A source
+	/// may read past the sample count it first reported, which can be an estimate.
 	fn read_all(mut self) -> Result<PcmBuffer, Error> where Self: Sized {
 		let mut buf = PcmBuffer::default();
 		let rate = self.sample_rate();
 		let chan = self.channel_count();
 
-		buf.set_descriptor(rate, chan)?;
+		if rate > 0 && chan > 0 {
+			buf.set_descriptor(rate, chan)?;
+		}
 
 		let mut cnt = self.sample_count();
-		while self.read(&mut buf, cnt)? > 0 {
+		loop {
+			let len = buf.len();
+			if self.read(&mut buf, cnt)? == 0 { break }
+
+			if buf.len() == len {
+				return Err(Error::Stalled(len))
+			}
+
 			cnt = self.sample_count();
 		}
 
@@ -80,11 +117,13 @@ pub trait PcmSource: PcmStream {
 	/// Gets a [`StreamDescriptor`] instance describing the source.
 	fn descriptor(&self) -> StreamDescriptor {
 		let samples  = self.sample_count();
-		let rate     = self.sample_rate().clamp(0, 16777216);
+		let rate     = self.sample_rate();
 		let channels = self.channel_count();
+		// A rate the frame header can't hold is treated as unknown, rather than
+		// discarding the whole descriptor.
 		StreamDescriptor::new(
 			(samples  > 0).then(|| samples ),
-			(rate     > 0).then(|| rate    ),
+			(1..16777216).contains(&rate).then(|| rate),
 			(channels > 0).then(|| channels),
 		).unwrap_or_default()
 	}
@@ -140,6 +179,8 @@ pub struct PcmFrame {
 	size: usize,
 	rate: u32,
 	chan: usize,
+	/// The number of samples written to each channel.
+	fill: Vec<usize>,
 }
 
 impl PcmFrame {
@@ -158,9 +199,58 @@ impl PcmFrame {
 			size: sample_count,
 			rate: sample_rate,
 			chan: channel_count,
+			fill: vec![0; channel_count],
+		}
+	}
+
+	/// Creates a full frame from channel-interleaved `data`.
+	///
+	/// # Panics
+	///
+	/// Panics if `sample_rate` or `channel_count` are `0`, or if the length of
+	/// `data` isn't a multiple of `channel_count`.
+	pub fn from_interleaved(data: Vec<i16>, sample_rate: u32, channel_count: usize) -> Self {
+		assert_ne!(sample_rate, 0, "sample rate must be known");
+		assert_ne!(channel_count, 0, "channel count must be known");
+		assert_eq!(
+			data.len() % channel_count, 0,
+			"data length must be a multiple of the channel count"
+		);
+
+		let len = data.len() / channel_count;
+		Self {
+			data,
+			len,
+			size: len,
+			rate: sample_rate,
+			chan: channel_count,
+			fill: vec![len; channel_count],
 		}
 	}
 
+	/// Creates a full frame by interleaving per-channel sample data.
+	///
+	/// # Panics
+	///
+	/// Panics if `sample_rate` is `0`, `channels` is empty, or the channels differ
+	/// in length.
+	pub fn from_planar(channels: &[Vec<i16>], sample_rate: u32) -> Self {
+		assert!(!channels.is_empty(), "channel count must be known");
+
+		let len = channels[0].len();
+		assert!(
+			channels.iter().all(|chn| chn.len() == len),
+			"channels must have the same length"
+		);
+
+		let mut data = Vec::with_capacity(len * channels.len());
+		for i in 0..len {
+			data.extend(channels.iter().map(|chn| chn[i]));
+		}
+
+		Self::from_interleaved(data, sample_rate, channels.len())
+	}
+
 	/// Trims and returns the internal sample buffer.
 	pub fn unwrap(mut self) -> Vec<i16> {
 		self.trim();
@@ -187,12 +277,36 @@ impl PcmFrame {
 	pub fn grow(&mut self, sample_count: usize) {
 		self.size += sample_count;
 		self.data.reserve(sample_count * self.chan);
+		debug_assert!(self.validate());
 	}
 
-	/// Trims the frame such that its max size equals its length.
+	/// Trims the frame such that its max size equals its length. Samples written
+	/// past the length to only some channels are discarded.
 	pub fn trim(&mut self) {
 		self.size = self.len();
 		self.data.truncate(self.size * self.chan);
+		self.set_len(self.size);
+		debug_assert!(self.validate());
+	}
+
+	/// Returns `true` if the length, size, channel fills, and sample data agree:
+	/// the length is the least fill, no fill exceeds the size, and the data holds
+	/// exactly every sample up to the greatest fill.
+	pub fn validate(&self) -> bool {
+		let min_fill = self.fill.iter().copied().min().unwrap_or_default();
+		let max_fill = self.fill.iter().copied().max().unwrap_or_default();
+		self.fill.len() == self.chan &&
+		self.len  == min_fill        &&
+		max_fill  <= self.size       &&
+		self.data.len() == max_fill * self.chan
+	}
+
+	/// Sets the length, truncating the fill of each channel to it.
+	fn set_len(&mut self, len: usize) {
+		self.len = len;
+		for fill in &mut self.fill {
+			*fill = min(*fill, len);
+		}
 	}
 }
 
@@ -212,10 +326,14 @@ impl PcmStream for PcmFrame {
 
 impl PcmSource for PcmFrame {
 	fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, Error> {
-		let samples = min(sample_count, self.len * self.chan);
-		let read = buf.write_interleaved(&self.data()[..samples])?;
-		self.len = self.len.saturating_sub(read);
-		self.data.truncate(self.len * self.chan);
+		let samples = min(sample_count, self.len) * self.chan;
+		let read = buf.write_interleaved(&self.data[..samples])? / self.chan;
+		self.data.drain(..read * self.chan);
+		self.len -= read;
+		for fill in &mut self.fill {
+			*fill -= read;
+		}
+		debug_assert!(self.validate());
 		Ok(read)
 	}
 
@@ -223,35 +341,54 @@ impl PcmSource for PcmFrame {
 }
 
 impl PcmSink for PcmFrame {
+	/// Writes samples from `data` into `chn`, then returns the number of samples
+	/// written. Channels may be written in any order and with unequal lengths; the
+	/// frame length is the number of samples written to every channel, with the
+	/// rest padded with silence until written.
 	fn write(&mut self, data: &[i16], chn: usize) -> Result<usize, Error> {
 		assert!(chn < self.chan, "channel index out of bounds");
 
-		let len = self.len();
-		let samples = min(data.len(), self.size - len);
+		let fill = self.fill[chn];
+		let samples = min(data.len(), self.size.saturating_sub(fill));
 
 		if samples == 0 { return Ok(0) }
 
-		if chn == 0 {
-			self.data.resize(len + samples * self.chan, 0);
-		} else if chn == self.chan - 1 {
-			self.len += samples;
+		let end = fill + samples;
+		if self.data.len() < end * self.chan {
+			self.data.resize(end * self.chan, 0);
 		}
 
-		let off = len * self.chan + chn;
-		for i in 0..samples {
-			self.data[off + i] = data[i]
+		for (i, &sample) in data[..samples].iter().enumerate() {
+			self.data[(fill + i) * self.chan + chn] = sample;
 		}
 
+		self.fill[chn] = end;
+		self.len = self.fill.iter().copied().min().unwrap_or_default();
+		debug_assert!(self.validate());
 		Ok(samples)
 	}
 
+	/// Writes interleaved samples from `data`, up to the frame size, then returns
+	/// the number of values written. This is always a multiple of the channel
+	/// count.
+	///
+	/// # Errors
+	///
+	/// [`Error::PartialSample`]: the length of `data` isn't a multiple of the
+	/// channel count. Nothing is written.
 	fn write_interleaved(&mut self, data: &[i16]) -> Result<usize, Error> {
-		self.len = self.data.len();
-		let samples = min(data.len() / self.chan, self.sample_capacity());
-		self.len += samples;
+		if data.len() % self.chan != 0 {
+			return Err(Error::PartialSample(data.len(), self.chan))
+		}
 
+		let len = self.data.len() / self.chan;
+		let samples = min(data.len() / self.chan, self.size.saturating_sub(len));
 		let total = samples * self.chan;
 		self.data.extend_from_slice(&data[..total]);
+
+		self.len = len + samples;
+		self.fill.fill(self.len);
+		debug_assert!(self.validate());
 		Ok(total)
 	}
 
@@ -261,7 +398,7 @@ impl PcmSink for PcmFrame {
 		Ok(frame.is_empty().then(|| frame))
 	}
 	
-	fn sample_capacity(&self) -> usize { self.size - self.len }
+	fn sample_capacity(&self) -> usize { self.size.saturating_sub(self.len) }
 
 	fn set_descriptor(&mut self, sample_rate: u32, channel_count: usize) -> Result<(), Error> {
 		(sample_rate != self.rate || channel_count != self.chan)
@@ -286,6 +423,47 @@ impl PcmBuffer {
 		}
 	}
 
+	/// Creates a buffer with an initial empty frame of `rate` and `channels`, so
+	/// the descriptor is known before any samples are written.
+	///
+	/// # Panics
+	///
+	/// Panics if `frame_size`, `rate`, or `channels` is `0`.
+	pub fn with_descriptor(frame_size: usize, rate: u32, channels: usize) -> Self {
+		let mut buf = Self::new(frame_size);
+		buf.new_frame(rate, channels);
+		buf
+	}
+
+	/// Creates a buffer of `FRAME_LEN`-sample frames from channel-interleaved
+	/// `data`.
+	///
+	/// # Panics
+	///
+	/// Panics if `rate` or `channels` is `0`, or if the length of `data` isn't a
+	/// multiple of `channels`.
+	pub fn from_interleaved(data: &[i16], rate: u32, channels: usize) -> Self {
+		let mut buf = Self::with_descriptor(FRAME_LEN, rate, channels);
+		buf.write_interleaved(data)
+		   .expect("data length must be a multiple of the channel count");
+		buf
+	}
+
+	/// Returns the number of samples per channel in new frames.
+	pub fn frame_size(&self) -> usize { self.frame_size }
+
+	/// Sets the number of samples per channel in frames created after this call.
+	/// Existing frames keep their size, so the last frame is filled before a frame
+	/// of the new size is started.
+	///
+	/// # Panics
+	///
+	/// Panics if `frame_size` is `0`.
+	pub fn set_frame_size(&mut self, frame_size: usize) {
+		assert!(frame_size > 0, "frame size must be non-zero");
+		self.frame_size = frame_size;
+	}
+
 	pub fn len(&self) -> usize { self.buf.iter().map(PcmFrame::len).sum() }
 
 	pub fn is_empty(&self) -> bool { self.len() == 0 }
@@ -339,9 +517,13 @@ impl PcmBuffer {
 		let mut count = 0;
 		while !buf.is_empty() {
 			let mut frame = self.pop_frame(rate, chan);
-			let n = write(&mut frame, buf)?;
+			let n = write(&mut frame, buf);
 			self.buf.push(frame);
-			buf = &buf[n * chan..];
+
+			let n = n?;
+			if n == 0 { break }
+
+			buf = &buf[n..];
 			count += n;
 		}
 		Ok(count)
@@ -371,12 +553,15 @@ impl PcmSource for PcmBuffer {
 		if sample_count == 0 { return Ok(0) }
 
 		let mut count = 0;
-		while let Some(mut frame) = (count < sample_count)
-			.and_then(|| self.buf.pop()) {
-			count += frame.read(buf, count)?;
-
-			if !frame.is_empty() {
-				self.buf.push(frame)
+		while count < sample_count {
+			let Some(frame) = self.buf.first_mut() else { break };
+			let read = frame.read(buf, sample_count - count)?;
+			count += read;
+
+			if frame.is_empty() {
+				self.buf.remove(0);
+			} else if read == 0 {
+				break
 			}
 		}
 		Ok(count)
@@ -405,7 +590,17 @@ impl PcmSink for PcmBuffer {
 
 	fn sample_capacity(&self) -> usize { usize::MAX }
 
+	/// Sets the descriptor, starting a new frame if it differs from the last frame
+	/// or the last frame is full.
+	///
+	/// # Errors
+	///
+	/// [`Error::InvalidDescriptor`]: `sample_rate` or `channel_count` is `0`.
 	fn set_descriptor(&mut self, sample_rate: u32, channel_count: usize) -> Result<(), Error> {
+		if sample_rate == 0 || channel_count == 0 {
+			return Err(Error::InvalidDescriptor(sample_rate, channel_count))
+		}
+
 		if let Some(frame) = self.buf.last_mut() {
 			if frame.is_full()             ||
 			   sample_rate   != frame.rate ||
@@ -418,3 +613,510 @@ impl PcmSink for PcmBuffer {
 		Ok(())
 	}
 }
+
+/// A sink of per-channel PCM samples, the planar analog of [`PcmBuffer`]. Each
+/// channel is written to its own vector.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PlanarSink {
+	channels: Vec<Vec<i16>>,
+	rate: u32,
+}
+
+impl PlanarSink {
+	/// Creates an empty sink, with the descriptor set by the first writer.
+	pub fn new() -> Self { Self::default() }
+
+	/// Returns the samples of each channel.
+	pub fn channels(&self) -> &[Vec<i16>] { &self.channels }
+
+	/// Returns the number of samples in the shortest channel.
+	pub fn len(&self) -> usize {
+		self.channels
+			.iter()
+			.map(Vec::len)
+			.min()
+			.unwrap_or_default()
+	}
+
+	pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+	/// Returns the per-channel sample vectors.
+	pub fn unwrap(self) -> Vec<Vec<i16>> { self.channels }
+}
+
+impl PcmStream for PlanarSink {
+	fn channel_count(&self) -> usize { self.channels.len() }
+
+	fn sample_rate(&self) -> u32 { self.rate }
+}
+
+impl PcmSink for PlanarSink {
+	/// Appends samples from `buf` to channel `chn`, returning `buf.len()`.
+	///
+	/// # Errors
+	///
+	/// [`Error::UninitializedDescriptor`]: the descriptor hasn't been set.
+	///
+	/// # Panics
+	///
+	/// Panics if `chn` is out of bounds.
+	fn write(&mut self, buf: &[i16], chn: usize) -> Result<usize, Error> {
+		if self.channels.is_empty() {
+			return Err(Error::UninitializedDescriptor)
+		}
+
+		assert!(chn < self.channels.len(), "channel index out of bounds");
+		self.channels[chn].extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn write_interleaved(&mut self, buf: &[i16]) -> Result<usize, Error> {
+		let chan = self.channels.len();
+		if chan == 0 {
+			return Err(Error::UninitializedDescriptor)
+		}
+
+		for slice in buf.chunks_exact(chan) {
+			for (channel, &sample) in self.channels.iter_mut().zip(slice) {
+				channel.push(sample);
+			}
+		}
+
+		Ok(buf.len() - buf.len() % chan)
+	}
+
+	fn sample_capacity(&self) -> usize { usize::MAX }
+
+	/// Sets the descriptor, which can't change once samples are written.
+	///
+	/// # Errors
+	///
+	/// [`Error::InvalidDescriptor`]: `sample_rate` or `channel_count` is `0`.
+	///
+	/// [`Error::DescriptorSet`]: the descriptor differs from that of the samples
+	/// already written.
+	fn set_descriptor(&mut self, sample_rate: u32, channel_count: usize) -> Result<(), Error> {
+		if sample_rate == 0 || channel_count == 0 {
+			return Err(Error::InvalidDescriptor(sample_rate, channel_count))
+		}
+
+		if sample_rate == self.rate && channel_count == self.channels.len() {
+			return Ok(())
+		}
+
+		if self.channels.iter().any(|chn| !chn.is_empty()) {
+			return Err(Error::DescriptorSet)
+		}
+
+		self.rate = sample_rate;
+		self.channels.resize_with(channel_count, Vec::new);
+		Ok(())
+	}
+}
+
+/// A flat vector is the simplest interleaved sink. It has no descriptor, so the
+/// channel count and sample rate are reported as unknown.
+impl PcmStream for Vec<i16> {
+	fn channel_count(&self) -> usize { 0 }
+
+	fn sample_rate(&self) -> u32 { 0 }
+}
+
+impl PcmSink for Vec<i16> {
+	/// Writes samples from `buf` into `chn`, returning `buf.len()`. A write to
+	/// channel `0` appends a new run of samples; a write to channel `chn` then
+	/// interleaves into that run, which must have been written to channels `0` to
+	/// `chn - 1` in order, with the same length.
+	///
+	/// # Errors
+	///
+	/// [`Error::ChannelOrder`]: there are fewer than `chn * buf.len()` samples to
+	/// interleave into. Nothing is written.
+	fn write(&mut self, buf: &[i16], chn: usize) -> Result<usize, Error> {
+		if chn == 0 {
+			self.extend_from_slice(buf);
+			return Ok(buf.len())
+		}
+
+		let Some(start) = self.len().checked_sub(chn * buf.len()) else {
+			return Err(Error::ChannelOrder(chn))
+		};
+
+		// Spread the run out back to front, so each sample is moved before its
+		// place is overwritten.
+		self.resize(start + (chn + 1) * buf.len(), 0);
+		for (i, &sample) in buf.iter().enumerate().rev() {
+			let src = start + i * chn;
+			let dst = start + i * (chn + 1);
+			self.copy_within(src..src + chn, dst);
+			self[dst + chn] = sample;
+		}
+		Ok(buf.len())
+	}
+
+	fn write_interleaved(&mut self, buf: &[i16]) -> Result<usize, Error> {
+		self.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn sample_capacity(&self) -> usize { usize::MAX }
+
+	fn set_descriptor(&mut self, _: u32, _: usize) -> Result<(), Error> { Ok(()) }
+}
+
+#[cfg(test)]
+mod test {
+	use std::assert_matches::assert_matches;
+	use std::io;
+	use crate::FRAME_LEN;
+	use crate::byte_decoder;
+	use crate::decoder::Decoder;
+	use crate::io::Buffer;
+	use crate::reference::encode_ref;
+	use super::{Error, PcmBuffer, PcmFrame, PcmSink, PcmSource, PcmStream, PlanarSink};
+
+	/// A sink failing every write with [`io::ErrorKind::Interrupted`].
+	struct InterruptedSink;
+
+	impl PcmStream for InterruptedSink {
+		fn channel_count(&self) -> usize { 1 }
+		fn sample_rate(&self) -> u32 { 44100 }
+	}
+
+	impl PcmSink for InterruptedSink {
+		fn write(&mut self, _: &[i16], _: usize) -> Result<usize, Error> {
+			Err(io::Error::from(io::ErrorKind::Interrupted).into())
+		}
+
+		fn write_interleaved(&mut self, _: &[i16]) -> Result<usize, Error> {
+			Err(io::Error::from(io::ErrorKind::Interrupted).into())
+		}
+
+		fn sample_capacity(&self) -> usize { usize::MAX }
+
+		fn set_descriptor(&mut self, _: u32, _: usize) -> Result<(), Error> { Ok(()) }
+	}
+
+	/// A source whose descriptor is only known once reading starts.
+	struct UnknownRateSource(Vec<i16>);
+
+	impl PcmStream for UnknownRateSource {
+		fn channel_count(&self) -> usize { 1 }
+		fn sample_rate(&self) -> u32 { 0 }
+	}
+
+	impl PcmSource for UnknownRateSource {
+		fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, Error> {
+			buf.set_descriptor(44100, 1)?;
+			let len = sample_count.min(self.0.len());
+			let read = buf.write_interleaved(&self.0[..len])?;
+			self.0.drain(..read);
+			Ok(read)
+		}
+
+		fn sample_count(&self) -> usize { self.0.len() }
+	}
+
+	/// A source reporting a sample count of `10` throughout, reading one sample
+	/// per call until `remaining` runs out, or forever without writing it.
+	struct StaleSource {
+		remaining: Option<usize>,
+	}
+
+	impl PcmStream for StaleSource {
+		fn channel_count(&self) -> usize { 1 }
+		fn sample_rate(&self) -> u32 { 44100 }
+	}
+
+	impl PcmSource for StaleSource {
+		fn read(&mut self, buf: &mut impl PcmSink, _: usize) -> Result<usize, Error> {
+			match self.remaining.as_mut() {
+				Some(0) => Ok(0),
+				Some(remaining) => {
+					*remaining -= 1;
+					buf.write_interleaved(&[0])?;
+					Ok(1)
+				}
+				None => Ok(1)
+			}
+		}
+
+		fn sample_count(&self) -> usize { 10 }
+	}
+
+	#[test]
+	fn read_all_stalled() {
+		assert_matches!(StaleSource { remaining: None }.read_all(), Err(Error::Stalled(0)));
+	}
+
+	#[test]
+	fn read_all_past_estimate() {
+		assert_eq!(StaleSource { remaining: Some(15) }.read_all().unwrap().len(), 15);
+	}
+
+	#[test]
+	fn with_descriptor() {
+		let buf = PcmBuffer::with_descriptor(FRAME_LEN, 44100, 2);
+		assert!(buf.is_empty());
+		assert_eq!(buf.sample_rate(), 44100);
+		assert_eq!(buf.channel_count(), 2);
+	}
+
+	#[test]
+	fn read_all_unknown_rate() {
+		let buf = UnknownRateSource(vec![1, 2, 3, 4]).read_all().unwrap();
+		assert_eq!(buf.sample_rate(), 44100);
+		assert_eq!(buf.unwrap().into_iter().flat_map(PcmFrame::unwrap).collect::<Vec<_>>(), [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn set_zero_descriptor() {
+		let mut buf = PcmBuffer::default();
+		assert_matches!(buf.set_descriptor(0, 2), Err(Error::InvalidDescriptor(0, 2)));
+		assert_matches!(buf.set_descriptor(44100, 0), Err(Error::InvalidDescriptor(44100, 0)));
+		assert!(buf.is_empty());
+		assert!(buf.set_descriptor(44100, 2).is_ok());
+	}
+
+	#[test]
+	fn interrupted_write() {
+		let mut frame = PcmFrame::from_interleaved(vec![1, 2, 3], 44100, 1);
+		assert_matches!(
+			frame.read(&mut InterruptedSink, 3),
+			Err(Error::Io(err)) if err.kind() == io::ErrorKind::Interrupted
+		);
+		assert_eq!(frame.len(), 3);
+	}
+
+	#[test]
+	fn from_interleaved() {
+		let frame = PcmFrame::from_interleaved(vec![1, 2, 3, 4, 5, 6], 44100, 2);
+		assert_eq!(frame.len(), 3);
+		assert!(frame.is_full());
+		assert_eq!(frame.data(), [1, 2, 3, 4, 5, 6]);
+
+		let mono = PcmFrame::from_interleaved(vec![1, 2, 3], 44100, 1);
+		assert_eq!(mono.len(), 3);
+		assert!(mono.is_full());
+	}
+
+	#[test]
+	fn from_planar() {
+		let frame = PcmFrame::from_planar(&[vec![1, 3, 5], vec![2, 4, 6]], 44100);
+		assert_eq!(frame, PcmFrame::from_interleaved(vec![1, 2, 3, 4, 5, 6], 44100, 2));
+		assert_eq!(frame.len(), 3);
+		assert!(frame.is_full());
+
+		let mono = PcmFrame::from_planar(&[vec![1, 2, 3]], 8000);
+		assert_eq!(mono, PcmFrame::from_interleaved(vec![1, 2, 3], 8000, 1));
+	}
+
+	#[test]
+	#[should_panic]
+	fn from_interleaved_partial() {
+		PcmFrame::from_interleaved(vec![1, 2, 3], 44100, 2);
+	}
+
+	#[test]
+	fn write_out_of_order() {
+		let mut frame = PcmFrame::new(4, 44100, 2);
+		assert_eq!(frame.write(&[2, 4, 6], 1).unwrap(), 3);
+		assert_eq!(frame.len(), 0);
+		assert_eq!(frame.write(&[1, 3], 0).unwrap(), 2);
+		assert_eq!(frame.len(), 2);
+		assert_eq!(frame.write(&[5, 7], 0).unwrap(), 2);
+		assert_eq!(frame.len(), 3);
+		assert_eq!(frame.write(&[8, 10], 1).unwrap(), 1);
+		assert_eq!(frame.len(), 4);
+		assert_eq!(frame.data(), [1, 2, 3, 4, 5, 6, 7, 8]);
+	}
+
+	#[test]
+	fn grow_write_trim() {
+		let mut frame = PcmFrame::new(2, 44100, 2);
+		assert_eq!(frame.write_interleaved(&[1, 2, 3, 4]).unwrap(), 4);
+		assert!(frame.is_full());
+
+		frame.grow(3);
+		assert!(frame.validate());
+		assert_eq!(frame.write(&[5, 7, 9], 0).unwrap(), 3);
+		assert_eq!(frame.write(&[6], 1).unwrap(), 1);
+		assert!(frame.validate());
+		assert_eq!(frame.len(), 3);
+
+		frame.trim();
+		assert!(frame.validate());
+		assert!(frame.is_full());
+		assert_eq!(frame.data(), [1, 2, 3, 4, 5, 6]);
+
+		frame.grow(1);
+		assert_eq!(frame.write_interleaved(&[7, 8]).unwrap(), 2);
+		assert!(frame.validate());
+		assert_eq!(frame.unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+	}
+
+	#[test]
+	fn partial_sample() {
+		let mut frame = PcmFrame::new(4, 44100, 2);
+		assert_matches!(frame.write_interleaved(&[1, 2, 3]), Err(Error::PartialSample(3, 2)));
+		assert!(frame.is_empty());
+		assert!(frame.validate());
+
+		let mut buf = PcmBuffer::with_descriptor(4, 44100, 2);
+		assert_matches!(buf.write_interleaved(&[1; 11]), Err(Error::PartialSample(11, 2)));
+		assert!(buf.is_empty());
+	}
+
+	#[test]
+	fn buffer_write_stereo() {
+		let data: Vec<i16> = (0..20).collect();
+		let mut buf = PcmBuffer::with_descriptor(4, 44100, 2);
+		assert_eq!(buf.write_interleaved(&data).unwrap(), 20);
+		assert_eq!(buf.len(), 10);
+
+		let frames = buf.unwrap();
+		let lens: Vec<_> = frames.iter().map(PcmFrame::len).collect();
+		assert_eq!(lens, [4, 4, 2]);
+		assert_eq!(frames.iter().flat_map(PcmFrame::data).copied().collect::<Vec<_>>(), data);
+	}
+
+	#[test]
+	fn vec_sink() {
+		let mut vec = vec![0i16];
+		assert_eq!(vec.write(&[1, 2], 0).unwrap(), 2);
+		assert_eq!(vec.write(&[3, 4], 1).unwrap(), 2);
+		assert_eq!(vec.write(&[5, 6], 2).unwrap(), 2);
+		assert_eq!(vec.write(&[7], 0).unwrap(), 1);
+		assert_eq!(vec.write(&[8], 1).unwrap(), 1);
+		assert_eq!(vec.write(&[9], 2).unwrap(), 1);
+		assert_eq!(vec, [0, 1, 3, 5, 2, 4, 6, 7, 8, 9]);
+		assert_matches!(vec.write(&[1, 2, 3, 4, 5, 6], 2), Err(Error::ChannelOrder(2)));
+		assert_eq!(vec.len(), 10);
+
+		let mut frame = PcmFrame::from_interleaved(vec![1, 2, 3, 4, 5, 6], 44100, 2);
+		let mut vec = Vec::<i16>::new();
+		assert_eq!(frame.read(&mut vec, 2).unwrap(), 2);
+		assert_eq!(vec, [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn decode_vec() {
+		const LEN: usize = FRAME_LEN + 333;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 13 % 5000) as i16 - 2500).collect();
+		let data = encode_ref(&samples, 2);
+
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut expected).unwrap();
+
+		let mut source = Buffer::decode(&mut data.clone());
+		let actual = Decoder::new(Vec::<i16>::new()).decode(&mut source).unwrap();
+		assert_eq!(actual, expected);
+	}
+
+	#[test]
+	fn partial_read() {
+		let mut frame = PcmFrame::from_interleaved(vec![1, 2, 3, 4, 5, 6], 44100, 2);
+		let mut sink = PcmFrame::new(2, 44100, 2);
+		assert_eq!(frame.read(&mut sink, 2).unwrap(), 2);
+		assert!(frame.validate());
+		assert_eq!(frame.len(), 1);
+		assert_eq!(frame.data(), [5, 6]);
+		assert_eq!(sink.data(), [1, 2, 3, 4]);
+
+		// The sink is full, so nothing more is read.
+		assert_eq!(frame.read(&mut sink, 1).unwrap(), 0);
+		assert_eq!(frame.data(), [5, 6]);
+	}
+
+	#[test]
+	fn buffer_read_in_order() {
+		let mut buf = PcmBuffer::new(2);
+		buf.set_descriptor(44100, 1).unwrap();
+		assert_eq!(buf.write_interleaved(&[1, 2, 3, 4, 5]).unwrap(), 5);
+
+		let mut sink = PcmFrame::new(4, 44100, 1);
+		assert_eq!(buf.read(&mut sink, 4).unwrap(), 4);
+		assert_eq!(sink.data(), [1, 2, 3, 4]);
+		assert_eq!(buf.len(), 1);
+	}
+
+	#[test]
+	fn buffer_from_interleaved() {
+		let data: Vec<i16> = (0..(FRAME_LEN * 2 + 10) as i16 * 2).collect();
+		let buf = PcmBuffer::from_interleaved(&data, 44100, 2);
+		assert_eq!(buf.len(), FRAME_LEN * 2 + 10);
+		assert_eq!((buf.sample_rate(), buf.channel_count()), (44100, 2));
+
+		let bytes: Vec<u8> = data.iter().flat_map(|s| s.to_le_bytes()).collect();
+		assert_eq!(buf.encode(), bytes);
+
+		let lens: Vec<_> = buf.unwrap().iter().map(PcmFrame::len).collect();
+		assert_eq!(lens, [FRAME_LEN, FRAME_LEN, 10]);
+
+		let empty = PcmBuffer::from_interleaved(&[], 44100, 2);
+		assert!(empty.is_empty());
+		assert_eq!(empty.channel_count(), 2);
+	}
+
+	#[test]
+	fn set_frame_size() {
+		let mut buf = PcmBuffer::with_descriptor(100, 44100, 1);
+		assert_eq!(buf.write_interleaved(&[1; 150]).unwrap(), 150);
+
+		buf.set_frame_size(30);
+		assert_eq!(buf.frame_size(), 30);
+		assert_eq!(buf.write_interleaved(&[2; 100]).unwrap(), 100);
+
+		let frames = buf.unwrap();
+		let sizes: Vec<_> = frames.iter().map(|frame| frame.size).collect();
+		let lens:  Vec<_> = frames.iter().map(PcmFrame::len).collect();
+		assert_eq!(sizes, [100, 100, 30, 30]);
+		assert_eq!(lens,  [100, 100, 30, 20]);
+		assert!(frames.iter().all(PcmFrame::validate));
+	}
+
+	#[test]
+	#[should_panic(expected = "frame size must be non-zero")]
+	fn set_zero_frame_size() {
+		PcmBuffer::default().set_frame_size(0);
+	}
+
+	#[test]
+	fn planar_sink() {
+		let mut sink = PlanarSink::new();
+		assert_matches!(sink.write(&[1], 0), Err(Error::UninitializedDescriptor));
+
+		sink.set_descriptor(44100, 2).unwrap();
+		assert_eq!(sink.write_interleaved(&[1, 2, 3, 4, 5]).unwrap(), 4);
+		assert_eq!(sink.write(&[7], 1).unwrap(), 1);
+		assert_eq!(sink.channels(), [vec![1, 3], vec![2, 4, 7]]);
+		assert_eq!(sink.len(), 2);
+
+		assert!(sink.set_descriptor(44100, 2).is_ok());
+		assert_matches!(sink.set_descriptor(48000, 2), Err(Error::DescriptorSet));
+	}
+
+	#[test]
+	fn decode_planar() {
+		const LEN: usize = FRAME_LEN + 300;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| (i * 41 % 9000) as i16 - 4500).collect();
+		let data = encode_ref(&samples, 2);
+
+		let mut expected = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut expected).unwrap();
+
+		let sink = Decoder::new(PlanarSink::new())
+			.decode(&mut Buffer::decode(&mut data.clone()))
+			.unwrap();
+		assert_eq!(sink.sample_rate(), 44100);
+
+		let channels = sink.unwrap();
+		assert_eq!(channels.len(), 2);
+		for (chn, actual) in channels.iter().enumerate() {
+			let expected: Vec<i16> = expected.iter().copied().skip(chn).step_by(2).collect();
+			assert_eq!(*actual, expected, "channel {chn}");
+		}
+	}
+}
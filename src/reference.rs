@@ -0,0 +1,61 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for testing against the reference codec, shared by the unit tests
+//! and the integration tests (through `#[path]`). Not every test crate uses
+//! every helper.
+
+#![allow(dead_code)]
+
+use qoa_ref_sys::{encode, QoaDesc};
+use qoar::{DescriptorError, StreamDescriptor};
+
+/// Encodes interleaved `samples` with `channels` channels at 44.1kHz with the
+/// reference encoder.
+pub fn encode_ref(samples: &[i16], channels: usize) -> Vec<u8> {
+	encode_ref_at(samples, channels, 44100)
+}
+
+/// Encodes interleaved `samples` with `channels` channels at `sample_rate` with
+/// the reference encoder.
+pub fn encode_ref_at(samples: &[i16], channels: usize, sample_rate: u32) -> Vec<u8> {
+	let ref mut desc = QoaDesc {
+		channels: channels as u32,
+		samplerate: sample_rate,
+		samples: (samples.len() / channels) as u32,
+		..QoaDesc::default()
+	};
+	encode(samples, desc).unwrap().into_vec()
+}
+
+/// Converts a reference descriptor, where fields of `0` are unknown.
+pub fn from_ref_desc(&QoaDesc { channels, samplerate, samples, .. }: &QoaDesc) -> Result<StreamDescriptor, DescriptorError> {
+	let mut desc = StreamDescriptor::default();
+	if samples    > 0 { desc.suggest_sample_count(samples as usize) }
+	if samplerate > 0 { desc.suggest_sample_rate(samplerate) }
+	if channels   > 0 { desc.suggest_channel_count(channels as usize) }
+	desc.validate()?;
+	Ok(desc)
+}
+
+/// Converts a descriptor to a reference descriptor, with unknown fields set to
+/// `0`.
+pub fn to_ref_desc(desc: StreamDescriptor) -> QoaDesc {
+	QoaDesc {
+		channels: desc.channels().unwrap_or_default() as u32,
+		samplerate: desc.rate().unwrap_or_default(),
+		samples: desc.samples().unwrap_or_default() as u32,
+		..QoaDesc::default()
+	}
+}
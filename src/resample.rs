@@ -0,0 +1,228 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::min;
+use crate::{DescriptorError, FRAME_LEN, PcmFrame, PcmSink, PcmSource, PcmStream};
+use crate::pcm_io::Error;
+
+/// A [`PcmSource`] wrapper retargeting the sample rate of its inner source with
+/// linear interpolation. Each channel is interpolated separately.
+pub struct ResampleSource<S: PcmSource> {
+	source: S,
+	rate: u32,
+	/// Interleaved input samples, starting at `input_pos`.
+	input: Vec<i16>,
+	/// The index of the first input sample per channel held in `input`.
+	input_pos: usize,
+	/// The index of the next output sample per channel.
+	output_pos: usize,
+	/// The total number of output samples per channel, or `0` if not known.
+	output_len: usize,
+	exhausted: bool,
+}
+
+impl<S: PcmSource> ResampleSource<S> {
+	/// Creates a new resampling source, converting samples from `source` to
+	/// `sample_rate`.
+	///
+	/// # Errors
+	///
+	/// [`DescriptorError::ZeroRate`]: `sample_rate` or the sample rate of `source`
+	/// is `0`.
+	///
+	/// [`DescriptorError::UnsupportedRate`]: `sample_rate` is `2^24` or greater.
+	///
+	/// [`DescriptorError::NoChannels`]: the channel count of `source` is `0`.
+	pub fn new(source: S, sample_rate: u32) -> Result<Self, DescriptorError> {
+		match sample_rate {
+			0 => return Err(DescriptorError::ZeroRate),
+			16777216.. => return Err(DescriptorError::UnsupportedRate(sample_rate)),
+			_ => { }
+		}
+		if source.sample_rate() == 0 { return Err(DescriptorError::ZeroRate) }
+		if source.channel_count() == 0 { return Err(DescriptorError::NoChannels) }
+
+		let output_len = Self::scale_len(
+			source.sample_count(),
+			source.sample_rate(),
+			sample_rate
+		);
+
+		Ok(Self {
+			source,
+			rate: sample_rate,
+			input: Vec::new(),
+			input_pos: 0,
+			output_pos: 0,
+			output_len,
+			exhausted: false,
+		})
+	}
+
+	/// Returns the inner source.
+	pub fn unwrap(self) -> S { self.source }
+
+	/// Returns the number of output samples covering `len` input samples.
+	fn scale_len(len: usize, src_rate: u32, dst_rate: u32) -> usize {
+		let len = len as u64 * dst_rate as u64;
+		let src = src_rate as u64;
+		((len + src - 1) / src) as usize
+	}
+
+	/// Maps an output sample index to an input sample index and the fractional
+	/// distance to the next input sample, in units of `1 / rate`.
+	fn position(&self, index: usize) -> (usize, i64) {
+		let pos = index as u64 * self.source.sample_rate() as u64;
+		let dst = self.rate as u64;
+		((pos / dst) as usize, (pos % dst) as i64)
+	}
+
+	/// Returns the number of input samples per channel currently buffered.
+	fn buffered(&self) -> usize {
+		self.input.len() / self.source.channel_count()
+	}
+
+	/// Reads from the inner source until the input sample at `index` and the one
+	/// after it are buffered, or the source is exhausted.
+	fn fill(&mut self, index: usize) -> Result<(), Error> {
+		let rate = self.source.sample_rate();
+		let chan = self.source.channel_count();
+
+		while !self.exhausted && self.input_pos + self.buffered() <= index + 1 {
+			let mut frame = PcmFrame::new(FRAME_LEN, rate, chan);
+			self.source.read(&mut frame, FRAME_LEN)?;
+
+			if frame.is_empty() {
+				self.exhausted = true;
+			} else {
+				self.input.extend_from_slice(frame.data());
+			}
+		}
+		Ok(())
+	}
+
+	/// Drops buffered input samples before `index`.
+	fn discard(&mut self, index: usize) {
+		let count = min(index.saturating_sub(self.input_pos), self.buffered());
+		self.input.drain(..count * self.source.channel_count());
+		self.input_pos += count;
+	}
+}
+
+impl<S: PcmSource> PcmStream for ResampleSource<S> {
+	fn channel_count(&self) -> usize { self.source.channel_count() }
+
+	fn sample_rate(&self) -> u32 { self.rate }
+}
+
+impl<S: PcmSource> PcmSource for ResampleSource<S> {
+	fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, Error> {
+		let chan = self.channel_count();
+		let count = min(sample_count, buf.sample_capacity());
+		buf.set_descriptor(self.rate, chan)?;
+
+		let mut data = Vec::with_capacity(count * chan);
+		for pos in self.output_pos..self.output_pos + count {
+			let (index, frac) = self.position(pos);
+			self.fill(index)?;
+
+			let off = index.saturating_sub(self.input_pos);
+			let len = self.buffered();
+			if off >= len { break }
+
+			// Clamp the next sample to the last at the end of the stream.
+			let next = min(off + 1, len - 1);
+			for chn in 0..chan {
+				let a = self.input[off  * chan + chn] as i64;
+				let b = self.input[next * chan + chn] as i64;
+				data.push((a + (b - a) * frac / self.rate as i64) as i16);
+			}
+		}
+
+		// Only advance past the samples the sink accepted, keeping the input the
+		// next output sample interpolates from.
+		let written = buf.write_interleaved(&data)? / chan;
+		self.output_pos += written;
+		self.discard(self.position(self.output_pos).0);
+		Ok(written)
+	}
+
+	fn sample_count(&self) -> usize {
+		self.output_len.saturating_sub(self.output_pos)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{DescriptorError, PcmFrame, PcmSink, PcmSource, PcmStream};
+	use super::ResampleSource;
+
+	#[test]
+	fn resample_ramp() {
+		const LEN: usize = 400;
+
+		let ramp: Vec<i16> = (0..LEN as i16).map(|i| i * 8).collect();
+		let mut source = PcmFrame::new(LEN, 8000, 1);
+		source.write_interleaved(&ramp).unwrap();
+
+		let mut resampler = ResampleSource::new(source, 16000).unwrap();
+		assert_eq!(resampler.sample_rate(), 16000);
+		assert_eq!(resampler.sample_count(), LEN * 2);
+
+		let mut sink = PcmFrame::new(LEN * 2, 16000, 1);
+		let read = resampler.read(&mut sink, LEN * 2).unwrap();
+		assert_eq!(read, LEN * 2);
+		assert_eq!(resampler.sample_count(), 0);
+
+		let data = sink.data();
+		assert_eq!(data.len(), LEN * 2);
+		assert_eq!(data[0], ramp[0]);
+		assert_eq!(data[1], (ramp[0] + ramp[1]) / 2);
+		assert_eq!(data[LEN * 2 - 1], ramp[LEN - 1]);
+	}
+
+	#[test]
+	fn resample_short_write() {
+		const LEN: usize = 400;
+
+		let ramp: Vec<i16> = (0..LEN as i16).map(|i| i * 8).collect();
+		let mut source = PcmFrame::new(LEN, 8000, 1);
+		source.write_interleaved(&ramp).unwrap();
+		let mut expected = PcmFrame::new(LEN * 2, 16000, 1);
+		ResampleSource::new(source.clone(), 16000).unwrap()
+			.read(&mut expected, LEN * 2).unwrap();
+
+		// Samples the sink can't hold are left for the next read.
+		let mut resampler = ResampleSource::new(source, 16000).unwrap();
+		let mut data = Vec::new();
+		while resampler.sample_count() > 0 {
+			let mut sink = PcmFrame::new(150, 16000, 1);
+			let read = resampler.read(&mut sink, LEN * 2).unwrap();
+			assert_eq!(read, sink.data().len());
+			data.extend_from_slice(sink.data());
+		}
+
+		assert_eq!(data, expected.data());
+	}
+
+	#[test]
+	fn resample_invalid() {
+		let source = PcmFrame::new(16, 8000, 1);
+		assert!(matches!(ResampleSource::new(source.clone(), 0), Err(DescriptorError::ZeroRate)));
+		assert!(matches!(
+			ResampleSource::new(source, 1 << 24),
+			Err(DescriptorError::UnsupportedRate(16777216))
+		));
+	}
+}
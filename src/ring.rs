@@ -0,0 +1,242 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`PcmSink`] writing interleaved samples into a lock-free SPSC ring buffer,
+//! for feeding an audio thread during live playback.
+
+use std::cmp::min;
+use rtrb::Producer;
+use crate::{PcmSink, PcmStream};
+use crate::pcm_io::Error;
+use crate::util::Then;
+
+/// A [`PcmSink`] writing interleaved samples into the producer half of an
+/// [`rtrb`] ring buffer. Writes are short when the ring is full. Samples written
+/// to some but not all channels are staged until the remaining channels are
+/// written.
+pub struct RingSink {
+	producer: Producer<i16>,
+	rate: u32,
+	chan: usize,
+	/// Interleaved samples not yet written to every channel.
+	staged: Vec<i16>,
+	/// The number of staged samples written to each channel.
+	fill: Vec<usize>,
+}
+
+impl RingSink {
+	/// Creates a sink writing samples of `rate` and `channels` into `producer`.
+	///
+	/// # Panics
+	///
+	/// Panics if `rate` or `channels` is `0`.
+	pub fn new(producer: Producer<i16>, rate: u32, channels: usize) -> Self {
+		assert_ne!(rate, 0, "sample rate must be non-zero");
+		assert_ne!(channels, 0, "channel count must be non-zero");
+
+		Self {
+			producer,
+			rate,
+			chan: channels,
+			staged: Vec::new(),
+			fill: vec![0; channels],
+		}
+	}
+
+	/// Returns the inner producer. Staged samples are discarded.
+	pub fn unwrap(self) -> Producer<i16> { self.producer }
+
+	/// Returns the number of samples per channel the ring has room for.
+	fn free(&self) -> usize { self.producer.slots() / self.chan }
+
+	/// Moves staged samples written to every channel into the ring.
+	fn commit(&mut self) -> Result<(), Error> {
+		let Self { producer, chan, staged, fill, .. } = self;
+		let rows = fill.iter().copied().min().unwrap_or_default();
+
+		if rows > 0 {
+			producer.write_chunk_uninit(rows * *chan)
+					.map_err(|err| Error::Write(err.into()))?
+					.fill_from_iter(staged.drain(..rows * *chan));
+
+			for fill in fill {
+				*fill -= rows;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl PcmStream for RingSink {
+	fn channel_count(&self) -> usize { self.chan }
+
+	fn sample_rate(&self) -> u32 { self.rate }
+}
+
+impl PcmSink for RingSink {
+	fn write(&mut self, buf: &[i16], chn: usize) -> Result<usize, Error> {
+		assert!(chn < self.chan, "channel index out of bounds");
+
+		let fill = self.fill[chn];
+		let samples = min(buf.len(), self.free().saturating_sub(fill));
+		let end = fill + samples;
+
+		if self.staged.len() < end * self.chan {
+			self.staged.resize(end * self.chan, 0);
+		}
+
+		for (i, &sample) in buf[..samples].iter().enumerate() {
+			self.staged[(fill + i) * self.chan + chn] = sample;
+		}
+
+		self.fill[chn] = end;
+		self.commit()?;
+		Ok(samples)
+	}
+
+	fn write_interleaved(&mut self, buf: &[i16]) -> Result<usize, Error> {
+		if self.fill.iter().any(|&fill| fill > 0) {
+			// Complete the staged channels first, one channel at a time, writing the
+			// same number of samples to each.
+			let rows = min(buf.len() / self.chan, self.sample_capacity());
+			for chn in 0..self.chan {
+				let plane: Vec<i16> = buf[chn..].iter().step_by(self.chan).take(rows).copied().collect();
+				self.write(&plane, chn)?;
+			}
+			return Ok(rows * self.chan)
+		}
+
+		let samples = min(buf.len() / self.chan, self.free()) * self.chan;
+		if samples > 0 {
+			self.producer
+				.write_chunk_uninit(samples)
+				.map_err(|err| Error::Write(err.into()))?
+				.fill_from_iter(buf[..samples].iter().copied());
+		}
+		Ok(samples)
+	}
+
+	/// Returns the number of samples every channel has room for.
+	fn sample_capacity(&self) -> usize {
+		let fill = self.fill.iter().copied().max().unwrap_or_default();
+		self.free().saturating_sub(fill)
+	}
+
+	fn set_descriptor(&mut self, sample_rate: u32, channel_count: usize) -> Result<(), Error> {
+		(sample_rate != self.rate || channel_count != self.chan)
+			.then_err(Error::DescriptorSet)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::cmp::min;
+	use std::thread;
+	use rtrb::{Consumer, RingBuffer};
+	use crate::PcmSink;
+	use super::RingSink;
+
+	const SAMPLES: usize = 2000;
+
+	fn drain(mut consumer: Consumer<i16>, len: usize) -> thread::JoinHandle<Vec<i16>> {
+		thread::spawn(move || {
+			let mut samples = Vec::with_capacity(len);
+			while samples.len() < len {
+				match consumer.pop() {
+					Ok(sample) => samples.push(sample),
+					Err(_) => thread::yield_now()
+				}
+			}
+			samples
+		})
+	}
+
+	#[test]
+	fn write_interleaved() {
+		let data: Vec<i16> = (0..SAMPLES as i16 * 2).collect();
+		let (producer, consumer) = RingBuffer::new(64);
+		let consumer = drain(consumer, data.len());
+
+		let mut sink = RingSink::new(producer, 44100, 2);
+		let mut buf = &data[..];
+		while !buf.is_empty() {
+			let written = sink.write_interleaved(buf).unwrap();
+			assert!(written <= 64);
+			buf = &buf[written..];
+			thread::yield_now();
+		}
+
+		assert_eq!(consumer.join().unwrap(), data);
+	}
+
+	#[test]
+	fn write_channels() {
+		let data: Vec<i16> = (0..SAMPLES as i16 * 2).collect();
+		let (producer, consumer) = RingBuffer::new(64);
+		let consumer = drain(consumer, data.len());
+
+		let mut sink = RingSink::new(producer, 44100, 2);
+		for slice in data.chunks(40) {
+			for chn in 0..2 {
+				let plane: Vec<i16> = slice[chn..].iter().step_by(2).copied().collect();
+				let mut buf = &plane[..];
+				while !buf.is_empty() {
+					buf = &buf[sink.write(buf, chn).unwrap()..];
+					thread::yield_now();
+				}
+			}
+		}
+
+		assert_eq!(consumer.join().unwrap(), data);
+	}
+
+	#[test]
+	fn write_mixed() {
+		const ROWS: usize = 40;
+
+		fn pop_all(consumer: &mut Consumer<i16>, out: &mut Vec<i16>) {
+			while let Ok(sample) = consumer.pop() {
+				out.push(sample);
+			}
+		}
+
+		let left: Vec<i16> = (0..ROWS as i16).map(|i| i * 2).collect();
+		let right: Vec<i16> = (0..ROWS as i16).map(|i| i * 2 + 1).collect();
+		let (producer, mut consumer) = RingBuffer::new(16);
+		let mut sink = RingSink::new(producer, 44100, 2);
+		let mut out = Vec::new();
+
+		// Stage most of the ring's room on the left channel only.
+		assert_eq!(sink.write(&left[..5], 0).unwrap(), 5);
+
+		let (mut l, mut r) = (5, 0);
+		while l < ROWS {
+			let buf: Vec<i16> = left[l..].iter().zip(&right[r..]).flat_map(|(&a, &b)| [a, b]).collect();
+			let rows = sink.write_interleaved(&buf).unwrap() / 2;
+			assert_eq!(rows, min(3, ROWS - l));
+			l += rows;
+			r += rows;
+			pop_all(&mut consumer, &mut out);
+		}
+
+		while r < ROWS {
+			r += sink.write(&right[r..], 1).unwrap();
+			pop_all(&mut consumer, &mut out);
+		}
+
+		let expected: Vec<i16> = left.iter().zip(&right).flat_map(|(&a, &b)| [a, b]).collect();
+		assert_eq!(out, expected);
+	}
+}
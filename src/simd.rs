@@ -12,13 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! SIMD primitives for vector-based encoding.
+//! SIMD primitives for vector-based encoding and decoding.
 
 #![cfg(feature = "simd")]
 #![allow(non_camel_case_types)]
 
-use std::simd::{i32x16, i32x4, i64x4, LaneCount, Simd, SimdElement, SimdInt, SimdOrd, SimdUint, SupportedLaneCount};
-use crate::{QoaLmsState, RECIP_TABLE};
+use std::simd::{i32x16, i32x4, LaneCount, Simd, SimdElement, SimdInt, SimdOrd, SimdUint, SupportedLaneCount};
+use crate::{byte_decoder, DEQUANT_TABLE, QoaLmsState, RECIP_TABLE, SLICE_LEN};
 
 pub type i64x16 = Simd<i64, 16>;
 pub type u64x16 = Simd<u64, 16>;
@@ -57,6 +57,17 @@ pub fn div(v: i32x16) -> i32x16 {
 	n
 }
 
+// reconstruct
+
+const SAMPLE_MIN: i32x16 = const_splat(-32768);
+const SAMPLE_MAX: i32x16 = const_splat( 32767);
+
+/// Reconstructs samples from their LMS predictions and dequantized residuals,
+/// clamped to the 16-bit range. The lane-wise [`crate::reconstruct`].
+pub fn reconstruct(predicted: i32x16, dequantized: i32x16) -> i32x16 {
+	(predicted + dequantized).simd_clamp(SAMPLE_MIN, SAMPLE_MAX)
+}
+
 // min
 
 pub trait SimdLanes<T> {
@@ -82,8 +93,9 @@ pub struct LmsState {
 }
 
 impl LmsState {
+	/// Predicts the next sample. Wraps on overflow, as in the reference.
 	pub fn predict(&self) -> i32 {
-		((self.history() * self.weights()).reduce_sum() >> 13) as i32
+		(self.history * self.weights).reduce_sum() >> 13
 	}
 
 	pub fn update(&mut self, sample: i32, residual: i32) {
@@ -95,9 +107,6 @@ impl LmsState {
 		*history = history.rotate_lanes_left::<1>();
 		history[3] = sample;
 	}
-
-	fn history(&self) -> i64x4 { self.history.cast() }
-	fn weights(&self) -> i64x4 { self.weights.cast() }
 }
 
 impl From<LmsState> for QoaLmsState {
@@ -150,12 +159,108 @@ impl From<QoaLmsState> for LmsStateVector {
 	}
 }
 
+// decode
+
+/// The LMS states of up to 16 channels, one channel per lane.
+struct LmsLanes {
+	history: [i32x16; 4],
+	weights: [i32x16; 4],
+}
+
+impl LmsLanes {
+	fn load(lms: &[byte_decoder::LmsState]) -> Self {
+		let mut history = [ZERO; 4];
+		let mut weights = [ZERO; 4];
+		for (chn, lms) in lms.iter().enumerate() {
+			for i in 0..4 {
+				history[i][chn] = lms.history[i];
+				weights[i][chn] = lms.weights[i];
+			}
+		}
+		Self { history, weights }
+	}
+
+	fn store(&self, lms: &mut [byte_decoder::LmsState]) {
+		for (chn, lms) in lms.iter_mut().enumerate() {
+			for i in 0..4 {
+				lms.history[i] = self.history[i][chn];
+				lms.weights[i] = self.weights[i][chn];
+			}
+		}
+	}
+
+	fn predict(&self) -> i32x16 {
+		const SHR: i32x16 = const_splat(13);
+		let Self { history, weights } = self;
+		(history[0] * weights[0] +
+		 history[1] * weights[1] +
+		 history[2] * weights[2] +
+		 history[3] * weights[3]) >> SHR
+	}
+
+	fn update(&mut self, sample: i32x16, residual: i32x16) {
+		const SHR: i32x16 = const_splat(4);
+		let Self { history, weights } = self;
+		let delta = residual >> SHR;
+		for i in 0..4 {
+			weights[i] += history[i].is_negative().select(-delta, delta);
+		}
+		history.copy_within(1..4, 0);
+		history[3] = sample;
+	}
+}
+
+/// Decodes `len` samples from the packed slice of each channel into `out`, with
+/// up to 16 channels decoded at once in parallel lanes.
+pub fn decode_slice_simd(
+	slices: &[u64],
+	lms: &mut [byte_decoder::LmsState],
+	len: usize,
+	out: &mut [[i16; SLICE_LEN]],
+) {
+	const SF_SHR: u64x16 = const_splat(60);
+	const QR_SHR: u64x16 = const_splat(57);
+	const QR_SHL: u64x16 = const_splat(3);
+	const QR_MASK: u64x16 = const_splat(7);
+	const SF_SHL: u64x16 = const_splat(3);
+
+	for ((slices, lms), out) in slices.chunks(16)
+									 .zip(lms.chunks_mut(16))
+									 .zip(out.chunks_mut(16)) {
+		let mut lanes = LmsLanes::load(lms);
+		let mut packed = u64x16::splat(0);
+		packed.as_mut_array()[..slices.len()].copy_from_slice(slices);
+
+		let sf = (packed >> SF_SHR) << SF_SHL;
+		for si in 0..len {
+			let qr = (packed >> QR_SHR) & QR_MASK;
+			packed <<= QR_SHL;
+
+			// The flattened table is indexed by sf * 8 + qr.
+			let dq = i32x16::gather_or_default(DEQUANT_TABLE.flatten(), (sf | qr).cast());
+			let re = reconstruct(lanes.predict(), dq);
+
+			for (chn, out) in out.iter_mut().enumerate() {
+				out[si] = re[chn] as i16;
+			}
+
+			lanes.update(re, dq);
+		}
+
+		lanes.store(lms);
+	}
+}
+
 #[cfg(test)]
 mod test {
+	extern crate test;
+
 	use std::simd::i32x16;
 	use quickcheck::{Arbitrary, Gen, TestResult};
 	use quickcheck_macros::quickcheck;
-	use crate::{DEQUANT_TABLE, qc_assert_eq, QoaLmsState};
+	use test::{Bencher, black_box};
+	use crate::{byte_decoder, DEQUANT_TABLE, qc_assert_eq, QoaLmsState, SLICE_LEN};
+	use crate::reference::encode_ref;
 	use crate::simd::LmsStateVector;
 
 	#[derive(Copy, Clone, Debug)]
@@ -210,4 +315,129 @@ mod test {
 
 		qc_assert_eq!(vec_lms.0.map(QoaLmsState::from).to_vec(), lin_lms)
 	}
+
+	/// The scalar equivalent of [`super::decode_slice_simd`].
+	fn decode_slice_scalar(
+		slices: &[u64],
+		lms: &mut [byte_decoder::LmsState],
+		len: usize,
+		out: &mut [[i16; SLICE_LEN]],
+	) {
+		for ((&slice, lms), out) in slices.iter().zip(lms).zip(out) {
+			let sf = (slice >> 60) as usize;
+			for si in 0..len {
+				let qr = ((slice << 3 * si) >> 57 & 0x7) as usize;
+				let dq = DEQUANT_TABLE[sf][qr];
+				let re = crate::reconstruct(lms.predict(), dq);
+				out[si] = re;
+				lms.update(re, dq);
+			}
+		}
+	}
+
+	/// Splits a QOA stream into the LMS states, packed slices, and slice count of
+	/// each frame.
+	fn frames(data: &[u8]) -> Vec<(Vec<byte_decoder::LmsState>, Vec<u64>)> {
+		let longs: Vec<u64> = data.chunks_exact(8)
+								  .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+								  .collect();
+		let mut longs = &longs[1..];
+		let mut frames = Vec::new();
+		while let Some((&header, rest)) = longs.split_first() {
+			let chan = (header >> 56) as usize;
+			let size = (header & 0xFFFF) as usize / 8 - 1;
+			let (frame, rest) = rest.split_at(size);
+			let lms = frame[..2 * chan].chunks(2).map(|pair| {
+				let unpack = |mut v: u64| [0; 4].map(|_: i32| {
+					let x = (v >> 48) as i16 as i32;
+					v <<= 16;
+					x
+				});
+				byte_decoder::LmsState {
+					history: unpack(pair[0]),
+					weights: unpack(pair[1]),
+				}
+			}).collect();
+			frames.push((lms, frame[2 * chan..].to_vec()));
+			longs = rest;
+		}
+		frames
+	}
+
+	fn stereo_file() -> Vec<u8> {
+		const LEN: usize = 5120 * 4;
+
+		let samples: Vec<i16> = (0..LEN * 2).map(|i| ((i * 7919) % 16384) as i16 - 8192).collect();
+		encode_ref(&samples, 2)
+	}
+
+	/// Decodes every frame with `decode`, returning the samples of each channel
+	/// in slice order.
+	fn decode_frames(
+		frames: &[(Vec<byte_decoder::LmsState>, Vec<u64>)],
+		decode: fn(&[u64], &mut [byte_decoder::LmsState], usize, &mut [[i16; SLICE_LEN]]),
+	) -> Vec<i16> {
+		let mut samples = Vec::new();
+		let mut out = Vec::new();
+		for (lms, slices) in frames {
+			let mut lms = lms.clone();
+			let chan = lms.len();
+			out.resize(chan, [0; SLICE_LEN]);
+			for slices in slices.chunks(chan) {
+				decode(slices, &mut lms, SLICE_LEN, &mut out);
+				samples.extend(out.iter().flatten());
+			}
+		}
+		samples
+	}
+
+	#[quickcheck]
+	fn decode_slice(slices: Vec<u64>, len: usize) -> TestResult {
+		if slices.is_empty() { return TestResult::discard() }
+
+		// Keep scale factors small enough that the scalar prediction can't overflow.
+		let slices: Vec<u64> = slices.into_iter().map(|s| s & !(0xC << 60)).collect();
+
+		let len = len % SLICE_LEN + 1;
+		let chan = slices.len();
+		let mut vec_lms = vec![byte_decoder::LmsState::default(); chan];
+		for lms in &mut vec_lms {
+			lms.weights = [0, 0, -(1 << 13), 1 << 14];
+		}
+		let mut lin_lms = vec_lms.clone();
+		let mut vec_out = vec![[0; SLICE_LEN]; chan];
+		let mut lin_out = vec![[0; SLICE_LEN]; chan];
+
+		super::decode_slice_simd(&slices, &mut vec_lms, len, &mut vec_out);
+		decode_slice_scalar(&slices, &mut lin_lms, len, &mut lin_out);
+
+		for chn in 0..chan {
+			if vec_lms[chn].history != lin_lms[chn].history ||
+			   vec_lms[chn].weights != lin_lms[chn].weights {
+				return TestResult::error(format!("LMS state mismatch on channel {chn}"))
+			}
+		}
+		qc_assert_eq!(vec_out, lin_out)
+	}
+
+	#[test]
+	fn decode_stereo() {
+		let frames = frames(&stereo_file());
+		assert_eq!(
+			decode_frames(&frames, super::decode_slice_simd),
+			decode_frames(&frames, decode_slice_scalar)
+		);
+	}
+
+	#[bench]
+	fn decode_stereo_simd_bench(b: &mut Bencher) {
+		let frames = frames(&stereo_file());
+		b.iter(|| black_box(decode_frames(&frames, super::decode_slice_simd)))
+	}
+
+	#[bench]
+	fn decode_stereo_scalar_bench(b: &mut Bencher) {
+		let frames = frames(&stereo_file());
+		b.iter(|| black_box(decode_frames(&frames, decode_slice_scalar)))
+	}
 }
@@ -0,0 +1,98 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The quantization tables of the QOA format.
+
+/// Maps a scaled residual, clamped to `[-8,8]` and offset by `8`, to its 3-bit
+/// quantized value.
+pub const QUANT_TABLE: [u8; 17] = [
+	7, 7, 7, 5, 5, 3, 3, 1,
+	0,
+	0, 2, 2, 4, 4, 6, 6, 6
+];
+
+/// The scale of each 4-bit scale factor, `round((sf + 1)^2.75)`.
+pub const SF_TABLE: [i32; 16] = [
+	1, 7, 21, 45, 84, 138, 211, 304, 421, 562, 731, 928, 1157, 1419, 1715, 2048
+];
+
+/// The 16.16 fixed-point reciprocal of each scale in [`SF_TABLE`], used to
+/// divide residuals by the scale without division.
+pub const RECIP_TABLE: [i64; 16] = [
+	65536, 9363, 3121, 1457, 781, 475, 311, 216, 156, 117, 90, 71, 57, 47, 39, 32
+];
+
+/// The dequantized residual, indexed by scale factor then quantized value; each
+/// is the scale from [`SF_TABLE`] multiplied by `±0.75`, `±2.5`, `±4.5`, or `±7`.
+pub const DEQUANT_TABLE: [[i32; 8]; 16] = [
+	[   1,    -1,    3,    -3,    5,    -5,     7,     -7],
+	[   5,    -5,   18,   -18,   32,   -32,    49,    -49],
+	[  16,   -16,   53,   -53,   95,   -95,   147,   -147],
+	[  34,   -34,  113,  -113,  203,  -203,   315,   -315],
+	[  63,   -63,  210,  -210,  378,  -378,   588,   -588],
+	[ 104,  -104,  345,  -345,  621,  -621,   966,   -966],
+	[ 158,  -158,  528,  -528,  950,  -950,  1477,  -1477],
+	[ 228,  -228,  760,  -760, 1368, -1368,  2128,  -2128],
+	[ 316,  -316, 1053, -1053, 1895, -1895,  2947,  -2947],
+	[ 422,  -422, 1405, -1405, 2529, -2529,  3934,  -3934],
+	[ 548,  -548, 1828, -1828, 3290, -3290,  5117,  -5117],
+	[ 696,  -696, 2320, -2320, 4176, -4176,  6496,  -6496],
+	[ 868,  -868, 2893, -2893, 5207, -5207,  8099,  -8099],
+	[1064, -1064, 3548, -3548, 6386, -6386,  9933,  -9933],
+	[1286, -1286, 4288, -4288, 7718, -7718, 12005, -12005],
+	[1536, -1536, 5120, -5120, 9216, -9216, 14336, -14336],
+];
+
+#[cfg(test)]
+mod test {
+	use super::{DEQUANT_TABLE, QUANT_TABLE, RECIP_TABLE, SF_TABLE};
+
+	/// The dequantization multipliers of each quantized value, from the spec.
+	const DQT: [f64; 8] = [0.75, -0.75, 2.5, -2.5, 4.5, -4.5, 7.0, -7.0];
+
+	#[test]
+	fn scale_factors() {
+		for (sf, &scale) in SF_TABLE.iter().enumerate() {
+			assert_eq!(scale, ((sf + 1) as f64).powf(2.75).round() as i32, "scale factor {sf}");
+		}
+	}
+
+	#[test]
+	fn reciprocals() {
+		for (sf, &scale) in SF_TABLE.iter().enumerate() {
+			assert_eq!(RECIP_TABLE[sf], ((1 << 16) + scale as i64 - 1) / scale as i64, "scale factor {sf}");
+		}
+	}
+
+	#[test]
+	fn dequantization() {
+		for (sf, &scale) in SF_TABLE.iter().enumerate() {
+			let row = DQT.map(|dqt| (scale as f64 * dqt).round() as i32);
+			assert_eq!(DEQUANT_TABLE[sf], row, "scale factor {sf}");
+		}
+	}
+
+	#[test]
+	fn quantization() {
+		// Each residual quantizes to the value whose multiplier is nearest.
+		for (r, &q) in QUANT_TABLE.iter().enumerate() {
+			let r = r as f64 - 8.0;
+			let nearest = (0..8).min_by(|&a: &usize, &b: &usize|
+				(DQT[a] - r).abs().total_cmp(&(DQT[b] - r).abs())
+			).unwrap();
+			assert_eq!(DQT[q as usize].signum(), if r < 0.0 { -1.0 } else { 1.0 }, "residual {r}");
+			assert!((DQT[q as usize] - r).abs() <= (DQT[nearest] - r).abs(), "residual {r}");
+		}
+	}
+}
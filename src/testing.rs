@@ -0,0 +1,197 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic generated signals, for examples and benchmarks without sample
+//! files.
+
+use std::cmp::min;
+use std::f64::consts::TAU;
+use std::time::Duration;
+use crate::{Error, FRAME_LEN, PcmSink, PcmSource, PcmStream};
+
+/// The shape of a generated signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Waveform {
+	/// A sine wave of a frequency in Hz.
+	Sine(f64),
+	/// A square wave of a frequency in Hz.
+	Square(f64),
+	/// Uniform white noise, seeded for repeatable output.
+	Noise(u64),
+	/// Silence.
+	Silence,
+}
+
+/// A [`PcmSource`] generating a [`Waveform`] on every channel. Samples depend only
+/// on their position, so the output is identical however it's read.
+#[derive(Clone, Debug)]
+pub struct SignalSource {
+	waveform: Waveform,
+	amplitude: i16,
+	rate: u32,
+	chan: usize,
+	/// The number of samples per channel generated so far.
+	pos: usize,
+	/// The number of samples per channel left to generate.
+	len: usize,
+}
+
+impl SignalSource {
+	/// Creates a source of `sample_count` samples per channel, at half of full
+	/// scale.
+	///
+	/// # Panics
+	///
+	/// Panics if `sample_rate` or `channel_count` is `0`.
+	pub fn new(waveform: Waveform, sample_rate: u32, channel_count: usize, sample_count: usize) -> Self {
+		assert!(sample_rate > 0, "sample rate must be non-zero");
+		assert!(channel_count > 0, "channel count must be non-zero");
+
+		Self {
+			waveform,
+			amplitude: i16::MAX / 2,
+			rate: sample_rate,
+			chan: channel_count,
+			pos: 0,
+			len: sample_count,
+		}
+	}
+
+	/// Creates a source lasting `duration`, rounded down to a whole sample.
+	///
+	/// # Panics
+	///
+	/// Panics if `sample_rate` or `channel_count` is `0`.
+	pub fn with_duration(waveform: Waveform, sample_rate: u32, channel_count: usize, duration: Duration) -> Self {
+		let samples = (duration.as_secs_f64() * sample_rate as f64) as usize;
+		Self::new(waveform, sample_rate, channel_count, samples)
+	}
+
+	/// Sets the peak amplitude of the signal.
+	pub fn with_amplitude(mut self, amplitude: i16) -> Self {
+		self.amplitude = amplitude;
+		self
+	}
+
+	/// Returns the sample at position `pos` of channel `chn`.
+	fn sample(&self, pos: usize, chn: usize) -> i16 {
+		let amp = self.amplitude as f64;
+		let time = pos as f64 / self.rate as f64;
+		match self.waveform {
+			Waveform::Sine(freq) => ((TAU * freq * time).sin() * amp) as i16,
+			Waveform::Square(freq) =>
+				if (freq * time).fract() < 0.5 { self.amplitude } else { -self.amplitude },
+			Waveform::Noise(seed) => {
+				// SplitMix64 over the position, seed, and channel.
+				let mut z = seed ^ (pos as u64 * self.chan as u64 + chn as u64)
+					.wrapping_mul(0x9E3779B97F4A7C15);
+				z = (z ^ z >> 30).wrapping_mul(0xBF58476D1CE4E5B9);
+				z = (z ^ z >> 27).wrapping_mul(0x94D049BB133111EB);
+				z ^= z >> 31;
+				((z >> 11) as f64 / (1u64 << 53) as f64 * 2.0 * amp - amp) as i16
+			}
+			Waveform::Silence => 0,
+		}
+	}
+}
+
+impl PcmStream for SignalSource {
+	fn channel_count(&self) -> usize { self.chan }
+
+	fn sample_rate(&self) -> u32 { self.rate }
+}
+
+impl PcmSource for SignalSource {
+	fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, Error> {
+		let mut count = 0;
+		let mut data = Vec::with_capacity(min(sample_count, FRAME_LEN) * self.chan);
+		while count < sample_count && self.len > 0 {
+			let samples = min(min(sample_count - count, self.len), FRAME_LEN);
+			data.clear();
+			data.extend(
+				(self.pos..self.pos + samples).flat_map(|pos|
+					(0..self.chan).map(move |chn| (pos, chn))
+				).map(|(pos, chn)| self.sample(pos, chn))
+			);
+
+			let read = buf.write_interleaved(&data)? / self.chan;
+			self.pos += read;
+			self.len -= read;
+			count += read;
+
+			if read < samples { break }
+		}
+		Ok(count)
+	}
+
+	fn sample_count(&self) -> usize { self.len }
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+	use crate::{byte_decoder, Encoder, PcmBuffer, PcmFrame, PcmSink, PcmSource};
+	use crate::io::Buffer;
+	use super::{SignalSource, Waveform};
+
+	fn encode(mut source: SignalSource) -> Vec<i16> {
+		let mut enc = Encoder::new_fixed(source.sample_count(), source.rate, source.chan, Buffer::default()).unwrap();
+		enc.encode(&mut source).unwrap();
+		let data = enc.close().unwrap().unwrap().encode();
+		let mut samples = Vec::new();
+		byte_decoder::Decoder::default().decode(&data, &mut samples).unwrap();
+		samples
+	}
+
+	fn interleaved(buf: PcmBuffer) -> Vec<i16> {
+		buf.unwrap().into_iter().flat_map(PcmFrame::unwrap).collect()
+	}
+
+	#[test]
+	fn sine_frequency() {
+		let source = SignalSource::with_duration(Waveform::Sine(440.0), 44100, 1, Duration::from_secs(1));
+		let samples = encode(source);
+		assert_eq!(samples.len(), 44100);
+
+		let rising = samples.windows(2)
+							.filter(|pair| pair[0] < 0 && pair[1] >= 0)
+							.count();
+		assert!((438..=442).contains(&rising), "expected 440 rising zero crossings, found {rising}");
+	}
+
+	#[test]
+	fn deterministic() {
+		let source = SignalSource::new(Waveform::Noise(7), 48000, 2, 10000);
+		let whole = source.clone().read_all().unwrap();
+
+		let mut source = source;
+		let mut parts = PcmBuffer::default();
+		parts.set_descriptor(48000, 2).unwrap();
+		while source.read(&mut parts, 333).unwrap() > 0 { }
+		assert_eq!(parts.len(), 10000);
+		assert_eq!(interleaved(parts), interleaved(whole));
+	}
+
+	#[test]
+	fn waveforms() {
+		let square = SignalSource::new(Waveform::Square(100.0), 1000, 1, 10).with_amplitude(1000);
+		let square = interleaved(square.read_all().unwrap());
+		assert_eq!(square[1..5], [1000; 4]);
+		assert_eq!(square[6..], [-1000; 4]);
+		let silence = encode(SignalSource::new(Waveform::Silence, 8000, 2, 500));
+		// The smallest dequantized residual is 1, so silence decodes to +/-1.
+		assert_eq!(silence.len(), 1000);
+		assert!(silence.iter().all(|&s| s.abs() <= 1));
+	}
+}
@@ -12,34 +12,65 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::ops::Mul;
-
+#[cfg(feature = "std")]
 pub trait Then: Sized {
-	fn then_ok<T, E: Default>(self, val: T) -> Result<T, E>;
-
 	fn then_err<T: Default, E>(self, err: E) -> Result<T, E>;
-
-	fn and_then<T, F: FnMut() -> Option<T>>(self, then: F) -> Option<T>;
 }
 
+#[cfg(feature = "std")]
 impl Then for bool {
-	fn then_ok<T, E: Default>(self, val: T) -> Result<T, E> {
-		if self { Ok(val) } else { Err(E::default()) }
-	}
-
 	fn then_err<T: Default, E>(self, err: E) -> Result<T, E> {
 		if self { Err(err) } else { Ok(T::default()) }
 	}
+}
+
+/// A CRC-32 (ISO-HDLC) checksum, computed incrementally.
+#[derive(Copy, Clone, Debug)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+	const TABLE: [u32; 256] = {
+		let mut table = [0; 256];
+		let mut i = 0;
+		while i < 256 {
+			let mut crc = i as u32;
+			let mut bit = 0;
+			while bit < 8 {
+				crc = if crc & 1 == 1 { crc >> 1 ^ 0xEDB88320 } else { crc >> 1 };
+				bit += 1;
+			}
+			table[i] = crc;
+			i += 1;
+		}
+		table
+	};
 
-	fn and_then<T, F: FnMut() -> Option<T>>(self, mut then: F) -> Option<T> {
-		if self { then() } else { None }
+	pub fn update(&mut self, data: &[u8]) {
+		for &byte in data {
+			self.0 = Self::TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ self.0 >> 8;
+		}
 	}
-}
 
-pub trait Zip<T>: Iterator<Item = (T, T)> + Sized {
-	fn mul(self) -> impl Iterator<Item = T::Output> where T: Mul {
-		self.map(|(a, b)| a * b)
+	pub fn finish(self) -> u32 { !self.0 }
+
+	/// Computes the checksum of `data`.
+	pub fn checksum(data: &[u8]) -> u32 {
+		let mut crc = Self::default();
+		crc.update(data);
+		crc.finish()
 	}
 }
 
-impl<T, I: Iterator<Item = (T, T)> + Sized> Zip<T> for I { }
+impl Default for Crc32 {
+	fn default() -> Self { Self(!0) }
+}
+
+#[cfg(test)]
+mod test {
+	use super::Crc32;
+
+	#[test]
+	fn crc32_check() {
+		assert_eq!(Crc32::checksum(b"123456789"), 0xCBF43926);
+	}
+}
@@ -0,0 +1,256 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal reading and writing of PCM16 WAV files.
+
+use std::{error, io, result};
+use std::fs::File;
+use std::io::{BufWriter, Read, Result, Write};
+use std::path::Path;
+use amplify_derive::Display;
+use crate::{DescriptorError, StreamDescriptor};
+
+#[derive(Debug, Display)]
+pub enum WavError {
+	#[display("{0}")]
+	IO(io::Error),
+	#[display("not a RIFF/WAVE file")]
+	NotWave,
+	#[display("missing {0} chunk")]
+	MissingChunk(&'static str),
+	#[display("unsupported format {0} with {1} bits per sample, only PCM16 is supported")]
+	Unsupported(u16, u16),
+	#[display("{0}")]
+	Descriptor(DescriptorError),
+}
+
+impl error::Error for WavError {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Self::IO(err) => Some(err),
+			Self::Descriptor(err) => Some(err),
+			_ => None
+		}
+	}
+}
+
+impl From<io::Error> for WavError {
+	fn from(value: io::Error) -> Self { Self::IO(value) }
+}
+
+/// The size of the canonical RIFF/WAVE header.
+const HEADER_LEN: usize = 44;
+
+/// Writes channel-interleaved `samples` to a PCM16 WAV file at `path`, creating or
+/// truncating it.
+pub fn write_pcm16(
+	path: impl AsRef<Path>,
+	samples: &[i16],
+	rate: u32,
+	channels: u16
+) -> Result<()> {
+	let mut file = BufWriter::new(File::create(path)?);
+	write_pcm16_to(&mut file, samples, rate, channels)?;
+	file.flush()
+}
+
+/// Writes channel-interleaved `samples` to `writer` as PCM16 WAV data, with a
+/// canonical 44-byte header.
+pub fn write_pcm16_to(
+	mut writer: impl Write,
+	samples: &[i16],
+	rate: u32,
+	channels: u16
+) -> Result<()> {
+	writer.write_all(&header(samples.len(), rate, channels))?;
+
+	for sample in samples {
+		writer.write_all(&sample.to_le_bytes())?;
+	}
+
+	Ok(())
+}
+
+/// Reads channel-interleaved samples and a stream descriptor from a PCM16 WAV
+/// file at `path`.
+///
+/// # Errors
+///
+/// See [`read_pcm16_from`].
+pub fn read_pcm16(path: impl AsRef<Path>) -> result::Result<(Vec<i16>, StreamDescriptor), WavError> {
+	read_pcm16_from(File::open(path)?)
+}
+
+/// Reads channel-interleaved samples and a stream descriptor from PCM16 WAV data.
+///
+/// # Errors
+///
+/// [`WavError::NotWave`]: the data doesn't start with a RIFF/WAVE header.
+///
+/// [`WavError::MissingChunk`]: the `fmt ` chunk is missing or follows the `data`
+/// chunk, or the `data` chunk is missing.
+///
+/// [`WavError::Unsupported`]: the samples aren't 16-bit integer PCM.
+///
+/// [`WavError::Descriptor`]: the sample rate, channel count, or sample count is
+/// unsupported by QOA.
+pub fn read_pcm16_from(mut reader: impl Read) -> result::Result<(Vec<i16>, StreamDescriptor), WavError> {
+	let mut data = Vec::new();
+	reader.read_to_end(&mut data)?;
+
+	if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+		return Err(WavError::NotWave)
+	}
+
+	let mut format = None;
+	let mut chunks = &data[12..];
+	while chunks.len() >= 8 {
+		let (id, rest) = chunks.split_at(4);
+		let size = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+		let body = &rest[4..];
+		let body = &body[..size.min(body.len())];
+
+		match id {
+			b"fmt " if body.len() >= 16 => {
+				let tag      = u16::from_le_bytes([body[ 0], body[ 1]]);
+				let channels = u16::from_le_bytes([body[ 2], body[ 3]]);
+				let rate     = u32::from_le_bytes([body[ 4], body[ 5], body[ 6], body[ 7]]);
+				let bits     = u16::from_le_bytes([body[14], body[15]]);
+
+				// Accept PCM and extensible formats; extensible PCM has the same layout.
+				if !matches!(tag, 1 | 0xFFFE) || bits != 16 {
+					return Err(WavError::Unsupported(tag, bits))
+				}
+
+				format = Some((rate, channels as usize));
+			}
+			b"data" => {
+				let (rate, channels) = format.ok_or(WavError::MissingChunk("fmt "))?;
+				let samples: Vec<i16> = body.chunks_exact(2)
+											.map(|s| i16::from_le_bytes([s[0], s[1]]))
+											.collect();
+				let desc = StreamDescriptor::new(
+					Some(samples.len() / channels.max(1)),
+					Some(rate),
+					Some(channels)
+				).map_err(WavError::Descriptor)?;
+				return Ok((samples, desc))
+			}
+			_ => { }
+		}
+
+		// Chunks are padded to an even length.
+		let len = 8 + size + size % 2;
+		chunks = &chunks[len.min(chunks.len())..];
+	}
+
+	Err(WavError::MissingChunk("data"))
+}
+
+fn header(sample_count: usize, rate: u32, channels: u16) -> [u8; HEADER_LEN] {
+	let data_len = (sample_count * 2) as u32;
+	let block_align = channels * 2;
+	let byte_rate = rate * block_align as u32;
+
+	let mut header = [0; HEADER_LEN];
+	header[ 0.. 4].copy_from_slice(b"RIFF");
+	header[ 4.. 8].copy_from_slice(&(data_len + 36).to_le_bytes());
+	header[ 8..12].copy_from_slice(b"WAVE");
+	header[12..16].copy_from_slice(b"fmt ");
+	header[16..20].copy_from_slice(&16u32.to_le_bytes());
+	header[20..22].copy_from_slice(&1u16.to_le_bytes());
+	header[22..24].copy_from_slice(&channels.to_le_bytes());
+	header[24..28].copy_from_slice(&rate.to_le_bytes());
+	header[28..32].copy_from_slice(&byte_rate.to_le_bytes());
+	header[32..34].copy_from_slice(&block_align.to_le_bytes());
+	header[34..36].copy_from_slice(&16u16.to_le_bytes());
+	header[36..40].copy_from_slice(b"data");
+	header[40..44].copy_from_slice(&data_len.to_le_bytes());
+	header
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::Cursor;
+	use super::{read_pcm16_from, WavError, write_pcm16_to};
+
+	fn round_trip(channels: u16) {
+		let samples: Vec<i16> = (0..1200).map(|i| (i * 89 % 60000 - 30000) as i16).collect();
+		let mut wav = Vec::new();
+		write_pcm16_to(&mut wav, &samples, 44100, channels).unwrap();
+
+		let (actual, desc) = read_pcm16_from(Cursor::new(wav)).unwrap();
+		assert_eq!(actual, samples);
+		assert_eq!(desc.samples(), Some(1200 / channels as usize));
+		assert_eq!(desc.rate(), Some(44100));
+		assert_eq!(desc.channels(), Some(channels as usize));
+	}
+
+	#[test]
+	fn read_mono() { round_trip(1) }
+
+	#[test]
+	fn read_stereo() { round_trip(2) }
+
+	#[test]
+	fn read_unsupported() {
+		let mut wav = Vec::new();
+		write_pcm16_to(&mut wav, &[0; 4], 8000, 1).unwrap();
+		wav[34] = 8;
+		assert!(matches!(read_pcm16_from(Cursor::new(wav)), Err(WavError::Unsupported(1, 8))));
+		assert!(matches!(read_pcm16_from(Cursor::new(b"RIFX")), Err(WavError::NotWave)));
+	}
+
+	#[cfg(feature = "conv")]
+	#[test]
+	fn write_read() {
+		use symphonia::core::audio::SampleBuffer;
+		use symphonia::core::codecs::DecoderOptions;
+		use symphonia::core::formats::FormatOptions;
+		use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+		use symphonia::core::meta::MetadataOptions;
+		use symphonia::core::probe::Hint;
+		use symphonia::default::{get_codecs, get_probe};
+
+		let samples: Vec<i16> = (0..2000).map(|i| (i * 97 % 60000 - 30000) as i16).collect();
+		let mut wav = Vec::new();
+		write_pcm16_to(&mut wav, &samples, 22050, 2).unwrap();
+		assert_eq!(wav.len(), 44 + samples.len() * 2);
+
+		let source = MediaSourceStream::new(
+			Box::new(Cursor::new(wav)),
+			MediaSourceStreamOptions::default()
+		);
+		let mut format = get_probe().format(
+			Hint::new().with_extension("wav"),
+			source,
+			&FormatOptions::default(),
+			&MetadataOptions::default()
+		).unwrap().format;
+		let track = format.default_track().unwrap().clone();
+		assert_eq!(track.codec_params.sample_rate, Some(22050));
+		assert_eq!(track.codec_params.channels.map(|c| c.count()), Some(2));
+
+		let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default()).unwrap();
+		let mut actual = Vec::new();
+		while let Ok(packet) = format.next_packet() {
+			let buf = decoder.decode(&packet).unwrap();
+			let mut samples = SampleBuffer::<i16>::new(buf.capacity() as u64, *buf.spec());
+			samples.copy_interleaved_ref(buf);
+			actual.extend_from_slice(samples.samples());
+		}
+
+		assert_eq!(actual, samples);
+	}
+}
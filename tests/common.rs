@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::env::temp_dir;
+use std::env::{temp_dir, var_os};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::{create_dir_all, File, remove_dir, remove_file};
@@ -33,6 +33,10 @@ use symphonia::default::{get_codecs, get_probe};
 use zip::ZipArchive;
 use qoar::conv::FormatSource;
 
+/// Returns whether benches should print the encoding error of each sample, set
+/// with the `QOAR_REPORT_ERROR` environment variable.
+pub fn report_error() -> bool { var_os("QOAR_REPORT_ERROR").is_some() }
+
 pub const TEST_SAMPLE_URL: &str = "https://qoaformat.org/samples/qoa_test_samples_2023_02_18.zip";
 pub const TEST_SAMPLE_DIR: &str = "run/qoa_test_samples_2023_02_18";
 pub const TEST_SAMPLE_ZIP: &str = "qoar/qoa_test_samples_2023_02_18.zip";
@@ -13,6 +13,8 @@
 // limitations under the License.
 
 mod common;
+#[path = "../src/reference.rs"]
+mod reference;
 
 use std::error::Error;
 use std::fmt;
@@ -23,6 +25,7 @@ use qoar::byte_decoder::Decoder;
 use qoar::io::Buffer;
 use qoar::{PcmBuffer, PcmFrame, PcmSink};
 use crate::common::{DisplayError, OculusAudioPack, OpaqueData, Sample};
+use crate::reference::from_ref_desc;
 
 #[test]
 fn decode_oculus_audio_pack() -> Result<(), DisplayError> {
@@ -32,18 +35,46 @@ fn decode_oculus_audio_pack() -> Result<(), DisplayError> {
 
 fn decode_sample(sample: impl Sample) -> Result<(), Box<dyn Error>> {
 	let data = read(sample.qoa_path())?;
-	let dec = {
+	let (dec, desc) = {
 		let mut buf = Vec::new();
-		Decoder::default().decode(&*data, &mut buf)?;
-		buf
+		let (_, desc) = Decoder::default().decode_with_descriptor(&*data, &mut buf)?;
+		(buf, desc)
 	};
-	let qoa = decode(&*data, &mut QoaDesc::default())?;
+	let ref mut qoa_desc = QoaDesc::default();
+	let qoa = decode(&*data, qoa_desc)?;
 
 	assert_eq!(OpaqueData(&dec), OpaqueData(&qoa));
+	assert_eq!(desc, from_ref_desc(qoa_desc)?);
 
 	Ok(())
 }
 
+#[cfg(feature = "mmap")]
+#[test]
+fn decode_mapped_oculus_audio_pack() -> Result<(), DisplayError> {
+	decode_mapped_sample(OculusAudioPack::ActionDropCoin)
+		.map_err(DisplayError)
+}
+
+#[cfg(feature = "mmap")]
+fn decode_mapped_sample(sample: impl Sample) -> Result<(), Box<dyn Error>> {
+	use qoar::io::{MappedFile, SourceStream};
+
+	let data = read(sample.qoa_path())?;
+	// Safety: sample files aren't modified while tests run.
+	let mapped = unsafe { MappedFile::open(sample.qoa_path())? };
+	assert_eq!(mapped.len(), data.len());
+
+	let (mut expected, mut actual) = (Vec::new(), Vec::new());
+	Decoder::default().decode(&data, &mut expected)?;
+	Decoder::default().decode(&mapped, &mut actual)?;
+	assert_eq!(OpaqueData(&actual), OpaqueData(&expected));
+
+	let mut source = mapped.source();
+	assert_eq!(source.read_long()? >> 32, u32::from_be_bytes(*b"qoaf") as u64);
+	Ok(())
+}
+
 #[derive(Eq, PartialEq)]
 struct OpaqueFrame(PcmFrame);
 
@@ -16,7 +16,7 @@ mod common;
 
 use std::error::Error;
 use qoa_ref_sys::{encode, QoaDesc, read_wav};
-use qoar::{Encoder, PcmSource, PcmStream, StreamDescriptor};
+use qoar::{byte_decoder, Encoder, PcmBuffer, PcmSource, PcmStream, StreamDescriptor};
 use qoar::io::Buffer;
 use crate::common::{DisplayError, OculusAudioPack, OpaqueData, Sample};
 
@@ -26,6 +26,54 @@ fn encode_oculus_audio_pack() -> Result<(), DisplayError> {
 		.map_err(DisplayError)
 }
 
+#[test]
+fn encode_streaming() -> Result<(), DisplayError> {
+	encode_streaming_tone().map_err(DisplayError)
+}
+
+fn encode_streaming_tone() -> Result<(), Box<dyn Error>> {
+	const FRAME_LEN: usize = 5120;
+	const SAMPLES: usize = FRAME_LEN * 2 + 777;
+
+	let data: Vec<i16> = (0..SAMPLES * 2).map(|i| {
+		let t = (i / 2) as f64 / 44100.0;
+		let f = if i % 2 == 0 { 440.0 } else { 660.0 };
+		((t * f * std::f64::consts::TAU).sin() * 12000.0) as i16
+	}).collect();
+
+	let encode = |mut enc: Encoder<Buffer>| -> Result<Vec<u8>, Box<dyn Error>> {
+		enc.encode(&mut PcmBuffer::from_interleaved(&data, 44100, 2))?;
+		Ok(enc.close().unwrap()?.encode())
+	};
+	let streaming = encode(Encoder::new_streaming(Buffer::default()))?;
+	let fixed = encode(Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default())?)?;
+
+	// Only the file header differs from a fixed stream.
+	assert_eq!(u64::from_be_bytes(streaming[..8].try_into()?) as u32, 0);
+	assert_eq!(OpaqueData(&streaming[8..]), OpaqueData(&fixed[8..]));
+
+	let mut frames = &streaming[8..];
+	let mut counts = Vec::new();
+	while !frames.is_empty() {
+		let header = u64::from_be_bytes(frames[..8].try_into()?);
+		assert_eq!(header >> 56, 2);
+		assert_eq!(header >> 32 & 0xFFFFFF, 44100);
+		counts.push((header >> 16 & 0xFFFF) as usize);
+		frames = &frames[(header & 0xFFFF) as usize..];
+	}
+	assert_eq!(counts, [FRAME_LEN, FRAME_LEN, 777]);
+
+	let mut decoder = byte_decoder::Decoder::default();
+	let (mut streaming_dec, mut fixed_dec) = (Vec::new(), Vec::new());
+	let (_, desc) = decoder.decode_with_descriptor(&streaming, &mut streaming_dec)?;
+	decoder.decode(&fixed, &mut fixed_dec)?;
+
+	assert_eq!(desc.samples(), Some(SAMPLES));
+	assert_eq!(streaming_dec.len(), SAMPLES * 2);
+	assert_eq!(streaming_dec, fixed_dec);
+	Ok(())
+}
+
 fn encode_sample(sample: impl Sample) -> Result<(), Box<dyn Error>> {
 	let wav = sample.decode_wav()?;
 	let samples  = wav.sample_count() as u32;
@@ -0,0 +1,46 @@
+// Copyright 2023 Strixpyrr
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks the byte decoder builds and runs without the `std` feature. Run with
+//! `cargo test --no-default-features --test no_std`.
+
+#![cfg(not(feature = "std"))]
+
+use qoar::byte_decoder::{Decoder, Error};
+
+/// A mono stream of one 20-sample frame.
+const DATA: [u8; 40] = [
+	// File header: magic, 20 samples
+	b'q', b'o', b'a', b'f', 0x00, 0x00, 0x00, 0x14,
+	// Frame header: 1 channel, 44100Hz, 20 samples, 32 bytes
+	0x01, 0x00, 0xAC, 0x44, 0x00, 0x14, 0x00, 0x20,
+	// LMS history
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	// LMS weights: 0, 0, -2^13, 2^14
+	0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x40, 0x00,
+	// Slice: scale factor 0, residuals 0
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn decode_into() {
+	let mut sink = [0; 20];
+	assert_eq!(Decoder::default().decode_into(&DATA, &mut sink).unwrap(), 20);
+}
+
+#[test]
+fn decode_into_full() {
+	let mut sink = [0; 10];
+	assert!(matches!(Decoder::default().decode_into(&DATA, &mut sink), Err(Error::SinkFull)));
+}
@@ -19,7 +19,7 @@ macro_rules! gen {
 			use test::Bencher;
 			use qoar::{Encoder, PcmSource, PcmStream};
 			use qoar::io::Buffer;
-			use crate::{OculusAudioPack::*, Sample};
+			use crate::{OculusAudioPack::*, report_error, Sample};
 
 			$(
 			#[bench]
@@ -30,6 +30,14 @@ macro_rules! gen {
 				let rate     = data.sample_rate();
 				let ref data = data.read_all()?;
 
+				if report_error() {
+					let mut enc = Encoder::new_fixed(samples, rate, channels, Buffer::default())?;
+					enc.set_channel_errors(true);
+					enc.encode(&mut data.clone())?;
+					let mse = enc.channel_errors().iter().sum::<f64>() / channels as f64;
+					eprintln!("{}: qoar rmse {:.3}", stringify!($name), mse.sqrt());
+				}
+
 				b.iter(|| {
 					Encoder::new_fixed(samples, rate, channels, Buffer::default())?
 						.encode(&mut data.clone())
@@ -39,6 +47,44 @@ macro_rules! gen {
 			)+
 		}
 
+		/// Encodes with the SIMD vector scaler, for comparison with the linear
+		/// scaler benches in `encode`. Each bench first checks that both scalers
+		/// encode the sample identically.
+		#[cfg(feature = "simd")]
+		mod encode_simd {
+			use std::error::Error;
+			use test::Bencher;
+			use qoar::{Encoder, PcmBuffer, PcmSource, PcmStream, SliceScaler};
+			use qoar::io::Buffer;
+			use crate::{OculusAudioPack::*, Sample};
+
+			fn encode<Sc: SliceScaler>(mut enc: Encoder<Buffer, Sc>, data: &PcmBuffer) -> Result<Vec<u8>, Box<dyn Error>> {
+				enc.encode(&mut data.clone())?;
+				Ok(enc.close().unwrap()?.encode())
+			}
+
+			$(
+			#[bench]
+			fn $name(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+				let data = $sample.decode_wav()?;
+				let samples  = data.sample_count();
+				let channels = data.channel_count();
+				let rate     = data.sample_rate();
+				let ref data = data.read_all()?;
+
+				let linear = encode(Encoder::new_fixed(samples, rate, channels, Buffer::default())?, data)?;
+				let vector = encode(Encoder::new_fixed_simd(samples, rate, channels, Buffer::default())?, data)?;
+				assert!(linear == vector, "linear and vector scalers encoded differently");
+
+				b.iter(|| {
+					Encoder::new_fixed_simd(samples, rate, channels, Buffer::default())?
+						.encode(&mut data.clone())
+				});
+				Ok(())
+			}
+			)+
+		}
+
 		mod decode {
 			use std::error;
 			use std::fs::read;
@@ -136,3 +182,174 @@ gen! {
 	voice_male_breathing(VoiceMaleBreathing)
 	voice_male_scream(VoiceMaleScream)
 }
+
+#[cfg(feature = "rayon")]
+mod encode_parallel {
+	use std::error::Error;
+	use test::Bencher;
+	use qoar::{Encoder, PcmFrame, PcmSource, PcmStream, StreamDescriptor};
+	use qoar::io::Buffer;
+	use crate::{BandcampSample::FourTetBaby, Sample};
+
+	fn read_sample() -> Result<(usize, u32, usize, Vec<i16>), Box<dyn Error>> {
+		let data = FourTetBaby.decode_wav()?;
+		let samples  = data.sample_count();
+		let channels = data.channel_count();
+		let rate     = data.sample_rate();
+		let data = data.read_all()?
+					   .unwrap()
+					   .into_iter()
+					   .flat_map(PcmFrame::unwrap)
+					   .collect();
+		Ok((samples, rate, channels, data))
+	}
+
+	#[bench]
+	fn serial(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		let (samples, rate, channels, ref data) = read_sample()?;
+
+		b.iter(|| {
+			Encoder::new_fixed(samples, rate, channels, Buffer::default())?
+				.encode_vec(&mut data.clone(), StreamDescriptor::default())
+		});
+		Ok(())
+	}
+
+	#[bench]
+	fn parallel(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		let (samples, rate, channels, ref data) = read_sample()?;
+
+		b.iter(|| {
+			Encoder::new_fixed(samples, rate, channels, Buffer::default())?
+				.encode_parallel(&mut data.clone(), StreamDescriptor::default())
+		});
+		Ok(())
+	}
+}
+
+mod encode_format {
+	use std::error::Error;
+	use test::Bencher;
+	use qoar::{Encoder, PcmSource, PcmStream};
+	use qoar::io::Buffer;
+	use crate::{BandcampSample::FourTetBaby, Sample};
+
+	#[bench]
+	fn pcm_frames(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		b.iter(|| -> Result<_, Box<dyn Error>> {
+			let mut source = FourTetBaby.decode_wav()?;
+			let samples = source.sample_count();
+			let mut enc = Encoder::new_fixed(samples, source.sample_rate(), source.channel_count(), Buffer::default())?;
+			enc.encode(&mut source)?;
+			Ok(enc)
+		});
+		Ok(())
+	}
+
+	#[bench]
+	fn audio_buffers(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		b.iter(|| -> Result<_, Box<dyn Error>> {
+			let mut source = FourTetBaby.decode_wav()?;
+			let samples = source.sample_count();
+			let mut enc = Encoder::new_fixed(samples, source.sample_rate(), source.channel_count(), Buffer::default())?;
+			enc.encode_format(&mut source)?;
+			Ok(enc)
+		});
+		Ok(())
+	}
+}
+
+/// Encodes from whole-frame reads, which skip the sample buffer, and from short
+/// reads, which go through it.
+mod encode_aligned {
+	use std::error::Error;
+	use test::Bencher;
+	use qoar::{Encoder, Error as PcmError, PcmBuffer, PcmSink, PcmSource, PcmStream};
+	use qoar::io::Buffer;
+	use crate::{BandcampSample::FourTetBaby, Sample};
+
+	/// A source reading at most 1000 samples at a time.
+	struct ShortReads(PcmBuffer);
+
+	impl PcmStream for ShortReads {
+		fn channel_count(&self) -> usize { self.0.channel_count() }
+		fn sample_rate(&self) -> u32 { self.0.sample_rate() }
+	}
+
+	impl PcmSource for ShortReads {
+		fn read(&mut self, buf: &mut impl PcmSink, sample_count: usize) -> Result<usize, PcmError> {
+			self.0.read(buf, sample_count.min(1000))
+		}
+
+		fn sample_count(&self) -> usize { self.0.sample_count() }
+	}
+
+	fn read_sample() -> Result<(usize, u32, usize, PcmBuffer), Box<dyn Error>> {
+		let data = FourTetBaby.decode_wav()?;
+		let samples  = data.sample_count();
+		let channels = data.channel_count();
+		let rate     = data.sample_rate();
+		Ok((samples, rate, channels, data.read_all()?))
+	}
+
+	#[bench]
+	fn aligned(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		let (samples, rate, channels, ref data) = read_sample()?;
+
+		b.iter(|| {
+			Encoder::new_fixed(samples, rate, channels, Buffer::default())?
+				.encode(&mut data.clone())
+		});
+		Ok(())
+	}
+
+	#[bench]
+	fn buffered(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		let (samples, rate, channels, ref data) = read_sample()?;
+
+		b.iter(|| {
+			Encoder::new_fixed(samples, rate, channels, Buffer::default())?
+				.encode(&mut ShortReads(data.clone()))
+		});
+		Ok(())
+	}
+}
+
+/// Decodes a generated stereo tone with the stream decoder into a [`PcmBuffer`],
+/// and with the byte decoder into a [`Vec`], for comparison.
+mod decode_stream {
+	use std::error::Error;
+	use std::mem::size_of;
+	use test::Bencher;
+	use qoar::{byte_decoder, Decoder, Encoder, PcmBuffer};
+	use qoar::io::Buffer;
+	use qoar::testing::{SignalSource, Waveform};
+
+	const SAMPLES: usize = 5120 * 10;
+
+	fn encode() -> Result<Vec<u8>, Box<dyn Error>> {
+		let mut enc = Encoder::new_fixed(SAMPLES, 44100, 2, Buffer::default())?;
+		enc.encode(&mut SignalSource::new(Waveform::Sine(440.0), 44100, 2, SAMPLES))?;
+		Ok(enc.close().unwrap()?.encode())
+	}
+
+	#[bench]
+	fn pcm_buffer(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		let source = Buffer::decode(&mut encode()?);
+		b.bytes = (SAMPLES * 2 * size_of::<i16>()) as u64;
+		b.iter(|| Decoder::new(PcmBuffer::default()).decode(&mut source.clone()));
+		Ok(())
+	}
+
+	#[bench]
+	fn bytes(b: &mut Bencher) -> Result<(), Box<dyn Error>> {
+		let data = encode()?;
+		b.bytes = (SAMPLES * 2 * size_of::<i16>()) as u64;
+		b.iter(|| -> Result<_, byte_decoder::Error> {
+			let mut sink = Vec::new();
+			byte_decoder::Decoder::default().decode(&data, &mut sink)?;
+			Ok(sink)
+		});
+		Ok(())
+	}
+}
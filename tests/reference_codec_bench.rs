@@ -15,7 +15,7 @@
 macro_rules! gen {
 	($($name:ident($sample:ident))+) => {
 		mod encode {
-			use crate::{OculusAudioPack::*, Sample};
+			use crate::{OculusAudioPack::*, report_error, Sample};
 			use test::Bencher;
 			use qoa_ref_sys::{encode, QoaDesc, read_wav};
 			use std::error::Error;
@@ -26,6 +26,12 @@ macro_rules! gen {
 				let ref mut descriptor = QoaDesc::default();
 				let ref data = read_wav($sample.wav_path(), descriptor)?;
 
+				if report_error() {
+					let ref mut desc = *descriptor;
+					encode(data, desc)?;
+					eprintln!("{}: reference rmse {:.3}", stringify!($name), desc.rmse());
+				}
+
 				b.iter(|| encode(data, descriptor));
 				Ok(())
 			}